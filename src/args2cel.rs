@@ -1,71 +1,303 @@
-use anyhow::{Context, Result, bail};
-use cel::objects::Value as CelValue;
-use std::collections::BTreeMap;
+use anyhow::{Context, Result, anyhow, bail};
+use cel::objects::{Key, Value as CelValue};
+use serde_json::Value as JsonValue;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
 use std::sync::Arc;
 
+use crate::json2cel::json_value_to_cel_value;
+
+/// Argument types whose value can be read from a file with `@path`
+const FILE_ARG_TYPES: &[&str] = &["string", "str", "bytes", "json", "list", "map"];
+
+/// Where the value of an argument comes from
+enum ArgumentSource<'a> {
+    Literal(&'a str),
+    File(&'a str),
+    Env(&'a str),
+}
+
+impl<'a> ArgumentSource<'a> {
+    /// A value of the form `@path` names a file, like curl does. A leading `@@` escapes a
+    /// literal value starting with `@`.
+    fn parse(type_name: &str, value: &'a str) -> Self {
+        if !FILE_ARG_TYPES.contains(&type_name) {
+            return ArgumentSource::Literal(value);
+        }
+        match value.strip_prefix('@') {
+            Some(rest) if rest.starts_with('@') => ArgumentSource::Literal(rest),
+            Some(path) => ArgumentSource::File(path),
+            None => ArgumentSource::Literal(value),
+        }
+    }
+
+    fn text(&self, name: &str) -> Result<Cow<'a, str>> {
+        match self {
+            ArgumentSource::Literal(value) => Ok(Cow::Borrowed(value)),
+            ArgumentSource::File(path) => fs::read_to_string(path)
+                .map(Cow::Owned)
+                .map_err(|e| anyhow!("Failed to read argument '{}' from '{}': {}", name, path, e)),
+            ArgumentSource::Env(var) => std::env::var(var)
+                .map(Cow::Owned)
+                .map_err(|e| anyhow!("Failed to read argument '{}' from ${}: {}", name, var, e)),
+        }
+    }
+
+    fn bytes(&self, name: &str) -> Result<Vec<u8>> {
+        match self {
+            ArgumentSource::Literal(value) => Ok(value.as_bytes().to_vec()),
+            ArgumentSource::File(path) => fs::read(path)
+                .map_err(|e| anyhow!("Failed to read argument '{}' from '{}': {}", name, path, e)),
+            ArgumentSource::Env(_) => Ok(self.text(name)?.into_owned().into_bytes()),
+        }
+    }
+}
+
 /// Convert CLI arguments into a BTreeMap of CEL values.
-/// Only supports simple types: int, uint, float, string, bool
+/// Supports simple types (int, uint, float, string, bytes, bool, duration) and JSON values
+/// (list for arrays, map for objects, json for anything). Values of string, bytes, and JSON
+/// types can be read from a file with `@path`.
 pub fn args_to_cel_variables(
     args: &[(String, String, String)], // (name, type_name, value)
 ) -> Result<BTreeMap<String, CelValue>> {
     let mut variables = BTreeMap::new();
 
-    for (name, type_name, value_str) in args {
-        let cel_value = match type_name.to_lowercase().as_str() {
-            "int" | "i64" => {
-                let parsed = value_str.parse::<i64>().with_context(|| {
-                    format!(
-                        "Failed to parse argument '{}': cannot parse '{}' as int",
-                        name, value_str
-                    )
-                })?;
-                CelValue::Int(parsed)
-            }
+    for (name, type_name, value) in args {
+        let type_name = type_name.to_lowercase();
+        let source = ArgumentSource::parse(&type_name, value);
+        let cel_value = arg_to_cel_value(name, &type_name, source)?;
+        variables.insert(name.clone(), cel_value);
+    }
 
-            "uint" | "u64" => {
-                let parsed = value_str.parse::<u64>().with_context(|| {
-                    format!(
-                        "Failed to parse argument '{}': cannot parse '{}' as uint",
-                        name, value_str
-                    )
-                })?;
-                CelValue::UInt(parsed)
-            }
+    Ok(variables)
+}
+
+/// Convert `--arg-env` arguments into a BTreeMap of CEL values.
+/// The value of each argument is the name of the environment variable holding it, which is
+/// read as-is: it is never treated as an `@path`.
+pub fn env_args_to_cel_variables(
+    args: &[(String, String, String)], // (name, type_name, environment variable)
+) -> Result<BTreeMap<String, CelValue>> {
+    let mut variables = BTreeMap::new();
+
+    for (name, type_name, var) in args {
+        let type_name = type_name.to_lowercase();
+        let cel_value = arg_to_cel_value(name, &type_name, ArgumentSource::Env(var))?;
+        variables.insert(name.clone(), cel_value);
+    }
+
+    Ok(variables)
+}
+
+/// Load argument variables from a file holding a JSON object, or a YAML mapping if the file
+/// ends in `.yaml` or `.yml`. Each key becomes a variable holding its converted value.
+pub fn arg_file_to_cel_variables(path: &Path) -> Result<BTreeMap<String, CelValue>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read argument file '{}'", path.display()))?;
 
-            "float" | "f64" | "double" => {
-                let parsed = value_str.parse::<f64>().with_context(|| {
-                    format!(
-                        "Failed to parse argument '{}': cannot parse '{}' as float",
-                        name, value_str
-                    )
-                })?;
-                CelValue::Float(parsed)
+    let is_yaml = path
+        .extension()
+        .is_some_and(|ext| ext == "yaml" || ext == "yml");
+    let parsed: JsonValue = if is_yaml {
+        #[cfg(feature = "from-yaml")]
+        {
+            serde_saphyr::from_str(&contents).map_err(|e| anyhow!(e))
+        }
+
+        #[cfg(not(feature = "from-yaml"))]
+        {
+            Err(anyhow!("Binary was compiled without YAML support"))
+        }
+    } else {
+        serde_json::from_str(&contents).map_err(|e| anyhow!(e))
+    }
+    .with_context(|| format!("Failed to parse argument file '{}'", path.display()))?;
+
+    let JsonValue::Object(fields) = parsed else {
+        bail!(
+            "Argument file '{}' must hold an object of variables",
+            path.display()
+        );
+    };
+
+    Ok(fields
+        .iter()
+        .map(|(name, value)| (name.clone(), json_value_to_cel_value(value)))
+        .collect())
+}
+
+/// Name of the variable holding every argument with `--args` or `--jsonargs`, like jq's `$ARGS`
+pub const CELQ_ARGS_VAR: &str = "celq_args";
+
+/// Convert the positional arguments of `--args` to strings, or of `--jsonargs` to JSON values
+pub fn positional_args_to_cel_values(args: &[String], json: bool) -> Result<Vec<CelValue>> {
+    args.iter()
+        .enumerate()
+        .map(|(idx, arg)| {
+            if !json {
+                return Ok(CelValue::String(Arc::new(arg.clone())));
             }
+            let parsed: JsonValue = serde_json::from_str(arg).with_context(|| {
+                format!(
+                    "Failed to parse positional argument {}: cannot parse '{}' as json",
+                    idx, arg
+                )
+            })?;
+            Ok(json_value_to_cel_value(&parsed))
+        })
+        .collect()
+}
+
+/// Build the `celq_args` map, holding the named argument variables under `named` and the
+/// positional arguments, in order, under `positional`
+pub fn celq_args_variable(
+    named: &BTreeMap<String, CelValue>,
+    positional: Vec<CelValue>,
+) -> CelValue {
+    let named: HashMap<Key, CelValue> = named
+        .iter()
+        .map(|(name, value)| (Key::String(Arc::new(name.clone())), value.clone()))
+        .collect();
+
+    let mut args = HashMap::new();
+    args.insert(
+        Key::String(Arc::new("named".to_string())),
+        CelValue::Map(named.into()),
+    );
+    args.insert(
+        Key::String(Arc::new("positional".to_string())),
+        CelValue::List(Arc::new(positional)),
+    );
+    CelValue::Map(args.into())
+}
 
-            "string" | "str" => CelValue::String(Arc::new(value_str.clone())),
+/// Remove `_` digit separators from a numeric argument, as in Rust literals like `1_000_000`
+fn strip_digit_separators(value: &str) -> String {
+    value.replace('_', "")
+}
+
+/// Convert a single argument into a CEL value of the given (lowercase) type
+fn arg_to_cel_value(name: &str, type_name: &str, source: ArgumentSource) -> Result<CelValue> {
+    // Bytes may not be valid UTF-8, so they are read separately from every other type
+    if type_name == "bytes" {
+        let bytes = source.bytes(name)?;
+        return Ok(CelValue::Bytes(Arc::new(bytes)));
+    }
 
-            "bool" | "boolean" => {
-                let parsed = value_str.parse::<bool>().with_context(|| {
-                    format!(
-                        "Failed to parse argument '{}': cannot parse '{}' as bool",
-                        name, value_str
-                    )
-                })?;
-                CelValue::Bool(parsed)
+    let value_str = source.text(name)?;
+    let value_str = value_str.as_ref();
+
+    // Values from the environment may be secrets, so errors name the variable instead
+    let shown = match source {
+        ArgumentSource::Env(var) => format!("${}", var),
+        _ => value_str.to_string(),
+    };
+    let cel_value = match type_name {
+        "int" | "i64" => {
+            let parsed = strip_digit_separators(value_str).parse::<i64>().with_context(|| {
+                format!(
+                    "Failed to parse argument '{}': cannot parse '{}' as int",
+                    name, shown
+                )
+            })?;
+            CelValue::Int(parsed)
+        }
+
+        "uint" | "u64" => {
+            let parsed = strip_digit_separators(value_str).parse::<u64>().with_context(|| {
+                format!(
+                    "Failed to parse argument '{}': cannot parse '{}' as uint",
+                    name, shown
+                )
+            })?;
+            CelValue::UInt(parsed)
+        }
+
+        "float" | "f64" | "double" => {
+            let parsed = strip_digit_separators(value_str).parse::<f64>().with_context(|| {
+                format!(
+                    "Failed to parse argument '{}': cannot parse '{}' as float",
+                    name, shown
+                )
+            })?;
+            CelValue::Float(parsed)
+        }
+
+        "string" | "str" => CelValue::String(Arc::new(value_str.to_string())),
+
+        "bool" | "boolean" => {
+            let parsed = value_str.parse::<bool>().with_context(|| {
+                format!(
+                    "Failed to parse argument '{}': cannot parse '{}' as bool",
+                    name, shown
+                )
+            })?;
+            CelValue::Bool(parsed)
+        }
+
+        "list" => {
+            let parsed: JsonValue = serde_json::from_str(value_str).with_context(|| {
+                format!(
+                    "Failed to parse argument '{}': cannot parse '{}' as list",
+                    name, shown
+                )
+            })?;
+            if !parsed.is_array() {
+                bail!(
+                    "Failed to parse argument '{}': '{}' is not a JSON array",
+                    name,
+                    shown
+                );
             }
+            json_value_to_cel_value(&parsed)
+        }
 
-            _ => {
+        "map" => {
+            let parsed: JsonValue = serde_json::from_str(value_str).with_context(|| {
+                format!(
+                    "Failed to parse argument '{}': cannot parse '{}' as map",
+                    name, shown
+                )
+            })?;
+            if !parsed.is_object() {
                 bail!(
-                    "Unsupported type: '{}'. Only simple types (int, uint, float, string, bool) are supported.",
-                    type_name
+                    "Failed to parse argument '{}': '{}' is not a JSON object",
+                    name,
+                    shown
                 );
             }
-        };
+            json_value_to_cel_value(&parsed)
+        }
 
-        variables.insert(name.clone(), cel_value);
-    }
+        "json" => {
+            let parsed: JsonValue = serde_json::from_str(value_str).with_context(|| {
+                format!(
+                    "Failed to parse argument '{}': cannot parse '{}' as json",
+                    name, shown
+                )
+            })?;
+            json_value_to_cel_value(&parsed)
+        }
 
-    Ok(variables)
+        "duration" => cel::functions::duration(Arc::new(value_str.to_string())).map_err(|_| {
+            anyhow!(
+                "Failed to parse argument '{}': cannot parse '{}' as duration. Expected numbers followed by units h, m, s, ms, us or ns, such as '1h30m', '45s' or '1.5h'",
+                name,
+                shown
+            )
+        })?,
+
+        _ => {
+            bail!(
+                "Unsupported type: '{}'. Supported types are int, uint, float, string, bytes, bool, duration, list, map, and json.",
+                type_name
+            );
+        }
+    };
+
+    Ok(cel_value)
 }
 
 #[cfg(test)]
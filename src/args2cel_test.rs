@@ -25,6 +25,35 @@ fn test_float() {
     }
 }
 
+#[test]
+fn test_int_with_underscores() {
+    let args = vec![("x".to_string(), "int".to_string(), "1_000".to_string())];
+    let vars = args_to_cel_variables(&args).unwrap();
+    assert!(matches!(vars.get("x").unwrap(), CelValue::Int(1000)));
+}
+
+#[test]
+fn test_float_with_underscores() {
+    let args = vec![("x".to_string(), "float".to_string(), "3_000.5".to_string())];
+    let vars = args_to_cel_variables(&args).unwrap();
+    if let CelValue::Float(f) = vars.get("x").unwrap() {
+        assert_eq!(*f, 3000.5);
+    } else {
+        panic!("Expected float");
+    }
+}
+
+#[test]
+fn test_string_keeps_underscores() {
+    let args = vec![("x".to_string(), "string".to_string(), "1_000".to_string())];
+    let vars = args_to_cel_variables(&args).unwrap();
+    if let CelValue::String(s) = vars.get("x").unwrap() {
+        assert_eq!(s.as_str(), "1_000");
+    } else {
+        panic!("Expected string");
+    }
+}
+
 #[test]
 fn test_string() {
     let args = vec![("x".to_string(), "string".to_string(), "hello".to_string())];
@@ -43,6 +72,102 @@ fn test_bool() {
     assert!(matches!(vars.get("x").unwrap(), CelValue::Bool(true)));
 }
 
+#[test]
+fn test_duration() {
+    let args = vec![("x".to_string(), "duration".to_string(), "1h30m".to_string())];
+    let vars = args_to_cel_variables(&args).unwrap();
+    if let CelValue::Duration(d) = vars.get("x").unwrap() {
+        assert_eq!(d.num_minutes(), 90);
+    } else {
+        panic!("Expected duration");
+    }
+}
+
+#[test]
+fn test_duration_parse_error() {
+    let args = vec![("x".to_string(), "duration".to_string(), "soon".to_string())];
+    let result = args_to_cel_variables(&args);
+    assert!(result.is_err());
+    let err_msg = result.unwrap_err().to_string();
+    assert!(err_msg.contains("cannot parse 'soon' as duration"));
+    assert!(err_msg.contains("1h30m"));
+}
+
+#[test]
+fn test_list() {
+    let args = vec![(
+        "x".to_string(),
+        "list".to_string(),
+        r#"["a", 1]"#.to_string(),
+    )];
+    let vars = args_to_cel_variables(&args).unwrap();
+    if let CelValue::List(list) = vars.get("x").unwrap() {
+        assert_eq!(list.len(), 2);
+        assert!(matches!(list[1], CelValue::Int(1)));
+    } else {
+        panic!("Expected list");
+    }
+}
+
+#[test]
+fn test_list_not_an_array() {
+    let args = vec![(
+        "x".to_string(),
+        "list".to_string(),
+        r#"{"a": 1}"#.to_string(),
+    )];
+    let result = args_to_cel_variables(&args);
+    assert!(result.is_err());
+    let err_msg = result.unwrap_err().to_string();
+    assert!(err_msg.contains("is not a JSON array"));
+}
+
+#[test]
+fn test_map() {
+    let args = vec![(
+        "x".to_string(),
+        "map".to_string(),
+        r#"{"k": 1}"#.to_string(),
+    )];
+    let vars = args_to_cel_variables(&args).unwrap();
+    assert!(matches!(vars.get("x").unwrap(), CelValue::Map(_)));
+}
+
+#[test]
+fn test_map_not_an_object() {
+    let args = vec![("x".to_string(), "map".to_string(), "[1, 2]".to_string())];
+    let result = args_to_cel_variables(&args);
+    assert!(result.is_err());
+    let err_msg = result.unwrap_err().to_string();
+    assert!(err_msg.contains("is not a JSON object"));
+}
+
+#[test]
+fn test_json() {
+    let args = vec![
+        (
+            "x".to_string(),
+            "json".to_string(),
+            r#"{"k": 1}"#.to_string(),
+        ),
+        ("y".to_string(), "json".to_string(), "42".to_string()),
+        ("z".to_string(), "json".to_string(), "null".to_string()),
+    ];
+    let vars = args_to_cel_variables(&args).unwrap();
+    assert!(matches!(vars.get("x").unwrap(), CelValue::Map(_)));
+    assert!(matches!(vars.get("y").unwrap(), CelValue::Int(42)));
+    assert!(matches!(vars.get("z").unwrap(), CelValue::Null));
+}
+
+#[test]
+fn test_json_parse_error() {
+    let args = vec![("x".to_string(), "json".to_string(), "{".to_string())];
+    let result = args_to_cel_variables(&args);
+    assert!(result.is_err());
+    let err_msg = result.unwrap_err().to_string();
+    assert!(err_msg.contains("cannot parse '{' as json"));
+}
+
 #[test]
 fn test_multiple_args() {
     let args = vec![
@@ -58,7 +183,7 @@ fn test_multiple_args() {
 
 #[test]
 fn test_unsupported_type() {
-    let args = vec![("x".to_string(), "list".to_string(), "[]".to_string())];
+    let args = vec![("x".to_string(), "set".to_string(), "[]".to_string())];
     let result = args_to_cel_variables(&args);
     assert!(result.is_err());
     let err_msg = result.unwrap_err().to_string();
@@ -77,3 +202,177 @@ fn test_parse_error() {
     let err_msg = result.unwrap_err().to_string();
     assert!(err_msg.contains("Failed to parse argument 'x'"));
 }
+
+fn file_arg(name: &str, type_name: &str, path: &std::path::Path) -> (String, String, String) {
+    (
+        name.to_string(),
+        type_name.to_string(),
+        format!("@{}", path.display()),
+    )
+}
+
+#[test]
+fn test_string_from_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("cert.pem");
+    std::fs::write(&path, "-----BEGIN CERTIFICATE-----\n").unwrap();
+
+    let vars = args_to_cel_variables(&[file_arg("cert", "string", &path)]).unwrap();
+    assert_eq!(
+        vars.get("cert").unwrap(),
+        &CelValue::String(Arc::new("-----BEGIN CERTIFICATE-----\n".to_string()))
+    );
+}
+
+#[test]
+fn test_bytes_and_json_from_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let bytes_path = dir.path().join("blob.bin");
+    std::fs::write(&bytes_path, [0u8, 159, 146, 150]).unwrap();
+    let json_path = dir.path().join("config.json");
+    std::fs::write(&json_path, r#"{"port": 8080}"#).unwrap();
+
+    let vars = args_to_cel_variables(&[
+        file_arg("blob", "bytes", &bytes_path),
+        file_arg("config", "json", &json_path),
+    ])
+    .unwrap();
+
+    assert_eq!(
+        vars.get("blob").unwrap(),
+        &CelValue::Bytes(Arc::new(vec![0, 159, 146, 150]))
+    );
+    assert!(matches!(vars.get("config").unwrap(), CelValue::Map(_)));
+}
+
+#[test]
+fn test_escaped_at_is_literal() {
+    let args = vec![(
+        "x".to_string(),
+        "string".to_string(),
+        "@@handle".to_string(),
+    )];
+    let vars = args_to_cel_variables(&args).unwrap();
+    assert_eq!(
+        vars.get("x").unwrap(),
+        &CelValue::String(Arc::new("@handle".to_string()))
+    );
+}
+
+#[test]
+fn test_missing_file_names_argument() {
+    let args = vec![(
+        "cert".to_string(),
+        "string".to_string(),
+        "@/does/not/exist.pem".to_string(),
+    )];
+    let err_msg = args_to_cel_variables(&args).unwrap_err().to_string();
+    assert!(err_msg.contains("'cert'"));
+    assert!(err_msg.contains("/does/not/exist.pem"));
+}
+
+#[test]
+fn test_env_arg() {
+    // SAFETY: no other test reads or writes this variable
+    unsafe { std::env::set_var("CELQ_TEST_ENV_ARG", "eu-west-1") };
+    let args = vec![(
+        "region".to_string(),
+        "string".to_string(),
+        "CELQ_TEST_ENV_ARG".to_string(),
+    )];
+    let vars = env_args_to_cel_variables(&args).unwrap();
+    assert_eq!(
+        vars.get("region").unwrap(),
+        &CelValue::String(Arc::new("eu-west-1".to_string()))
+    );
+}
+
+#[test]
+fn test_env_arg_missing_variable() {
+    let args = vec![(
+        "token".to_string(),
+        "string".to_string(),
+        "CELQ_TEST_UNSET_VARIABLE".to_string(),
+    )];
+    let err_msg = env_args_to_cel_variables(&args).unwrap_err().to_string();
+    assert!(err_msg.contains("$CELQ_TEST_UNSET_VARIABLE"));
+}
+
+#[test]
+fn test_env_arg_errors_hide_value() {
+    // SAFETY: no other test reads or writes this variable
+    unsafe { std::env::set_var("CELQ_TEST_ENV_ARG_SECRET", "s3cret") };
+    let args = vec![(
+        "token".to_string(),
+        "int".to_string(),
+        "CELQ_TEST_ENV_ARG_SECRET".to_string(),
+    )];
+    let err_msg = env_args_to_cel_variables(&args).unwrap_err().to_string();
+    assert!(err_msg.contains("$CELQ_TEST_ENV_ARG_SECRET"));
+    assert!(!err_msg.contains("s3cret"));
+}
+
+#[test]
+fn test_arg_file_json() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("params.json");
+    std::fs::write(&path, r#"{"limit": 5, "tags": ["a", "b"]}"#).unwrap();
+
+    let vars = arg_file_to_cel_variables(&path).unwrap();
+    assert_eq!(vars.get("limit").unwrap(), &CelValue::Int(5));
+    assert!(matches!(vars.get("tags").unwrap(), CelValue::List(list) if list.len() == 2));
+}
+
+#[test]
+#[cfg(feature = "from-yaml")]
+fn test_arg_file_yaml() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("params.yaml");
+    std::fs::write(&path, "limit: 7\nname: celq\n").unwrap();
+
+    let vars = arg_file_to_cel_variables(&path).unwrap();
+    assert_eq!(vars.get("limit").unwrap(), &CelValue::Int(7));
+    assert_eq!(
+        vars.get("name").unwrap(),
+        &CelValue::String(Arc::new("celq".to_string()))
+    );
+}
+
+#[test]
+fn test_arg_file_requires_object() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("params.json");
+    std::fs::write(&path, "[1, 2]").unwrap();
+
+    let err_msg = arg_file_to_cel_variables(&path).unwrap_err().to_string();
+    assert!(err_msg.contains("must hold an object"));
+}
+
+#[test]
+fn test_positional_args() {
+    let args = vec!["1".to_string(), "[2]".to_string()];
+
+    let strings = positional_args_to_cel_values(&args, false).unwrap();
+    assert_eq!(strings[0], CelValue::String(Arc::new("1".to_string())));
+
+    let values = positional_args_to_cel_values(&args, true).unwrap();
+    assert_eq!(values[0], CelValue::Int(1));
+    assert!(matches!(&values[1], CelValue::List(list) if list.len() == 1));
+
+    let err_msg = positional_args_to_cel_values(&["x".to_string()], true)
+        .unwrap_err()
+        .to_string();
+    assert!(err_msg.contains("positional argument 0"));
+}
+
+#[test]
+fn test_celq_args_shape() {
+    let named =
+        args_to_cel_variables(&[("x".to_string(), "int".to_string(), "1".to_string())]).unwrap();
+    let celq_args = celq_args_variable(&named, vec![CelValue::Bool(true)]);
+
+    assert_eq!(
+        crate::cel_value_to_json_value(&celq_args, false, None),
+        serde_json::json!({"named": {"x": 1}, "positional": [true]})
+    );
+}
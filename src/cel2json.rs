@@ -1,8 +1,18 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
 use cel::objects::{Key, Value as CelValue};
 use serde_json::Value as JsonValue;
 
 /// Convert a CEL value to a serde_json::Value
-pub fn cel_value_to_json_value(value: &CelValue) -> JsonValue {
+///
+/// Durations are written as protobuf-style seconds (e.g. `"3600s"`), or in ISO 8601
+/// (e.g. `"PT3600S"`) when `iso_durations` is set. Floats are rounded to `float_precision`
+/// decimal places, if given, and are still output as JSON numbers.
+pub fn cel_value_to_json_value(
+    value: &CelValue,
+    iso_durations: bool,
+    float_precision: Option<usize>,
+) -> JsonValue {
     match value {
         CelValue::Null => JsonValue::Null,
 
@@ -16,7 +26,11 @@ pub fn cel_value_to_json_value(value: &CelValue) -> JsonValue {
             // serde_json::Number doesn't support NaN or infinity
             // Handle these edge cases appropriately
             if f.is_finite() {
-                serde_json::Number::from_f64(*f)
+                let f = match float_precision {
+                    Some(precision) => round_float(*f, precision),
+                    None => *f,
+                };
+                serde_json::Number::from_f64(f)
                     .map(JsonValue::Number)
                     .unwrap_or(JsonValue::Null)
             } else {
@@ -26,8 +40,20 @@ pub fn cel_value_to_json_value(value: &CelValue) -> JsonValue {
 
         CelValue::String(s) => JsonValue::String(s.to_string()),
 
+        // JSON has no binary type, so bytes are output as base64 text
+        CelValue::Bytes(bytes) => JsonValue::String(BASE64.encode(bytes.as_slice())),
+
+        CelValue::Timestamp(ts) => JsonValue::String(ts.to_rfc3339()),
+
+        CelValue::Duration(duration) if iso_durations => JsonValue::String(duration.to_string()),
+
+        CelValue::Duration(duration) => JsonValue::String(protobuf_duration(duration)),
+
         CelValue::List(list) => {
-            let json_array: Vec<JsonValue> = list.iter().map(cel_value_to_json_value).collect();
+            let json_array: Vec<JsonValue> = list
+                .iter()
+                .map(|item| cel_value_to_json_value(item, iso_durations, float_precision))
+                .collect();
             JsonValue::Array(json_array)
         }
 
@@ -41,12 +67,67 @@ pub fn cel_value_to_json_value(value: &CelValue) -> JsonValue {
                     Key::Uint(u) => u.to_string(),
                     Key::Bool(b) => b.to_string(),
                 };
-                json_map.insert(key_string, cel_value_to_json_value(val));
+                json_map.insert(
+                    key_string,
+                    cel_value_to_json_value(val, iso_durations, float_precision),
+                );
             }
             JsonValue::Object(json_map)
         }
 
-        // Handle any other CEL value types by converting to string representation
-        _ => JsonValue::String(format!("{:?}", value)),
+        // Functions and opaque values have no JSON form, use their debug representation
+        CelValue::Function(_, _) | CelValue::Opaque(_) => JsonValue::String(format!("{:?}", value)),
+    }
+}
+
+/// Round a finite float to `precision` decimal places
+///
+/// The float is formatted and parsed back, so the result is the float closest to the rounded
+/// decimal, e.g. 0.30000000000000004 becomes 0.3 with any precision up to 16.
+fn round_float(f: f64, precision: usize) -> f64 {
+    format!("{:.*}", precision, f).parse().unwrap_or(f)
+}
+
+/// Format a duration like the JSON mapping of `google.protobuf.Duration`
+///
+/// Seconds are followed by 0, 3, 6 or 9 fractional digits, e.g. `"3600s"` or `"-1.500s"`.
+fn protobuf_duration(duration: &chrono::Duration) -> String {
+    // Both parts share the sign of the duration
+    let secs = duration.num_seconds();
+    let nanos = duration.subsec_nanos();
+    let sign = if secs < 0 || nanos < 0 { "-" } else { "" };
+    let (secs, nanos) = (secs.unsigned_abs(), nanos.unsigned_abs());
+
+    if nanos == 0 {
+        format!("{sign}{secs}s")
+    } else if nanos % 1_000_000 == 0 {
+        format!("{sign}{secs}.{:03}s", nanos / 1_000_000)
+    } else if nanos % 1_000 == 0 {
+        format!("{sign}{secs}.{:06}s", nanos / 1_000)
+    } else {
+        format!("{sign}{secs}.{nanos:09}s")
+    }
+}
+
+/// Name of the CEL type of a value, as returned by the CEL `type()` function
+pub fn cel_type_name(value: &CelValue) -> &'static str {
+    match value {
+        CelValue::List(_) => "list",
+        CelValue::Map(_) => "map",
+        CelValue::Function(_, _) => "function",
+        CelValue::Int(_) => "int",
+        CelValue::UInt(_) => "uint",
+        CelValue::Float(_) => "double",
+        CelValue::String(_) => "string",
+        CelValue::Bytes(_) => "bytes",
+        CelValue::Bool(_) => "bool",
+        CelValue::Opaque(_) => "opaque",
+        CelValue::Duration(_) => "google.protobuf.Duration",
+        CelValue::Timestamp(_) => "google.protobuf.Timestamp",
+        CelValue::Null => "null_type",
     }
 }
+
+#[cfg(test)]
+#[path = "cel2json_test.rs"]
+mod test;
@@ -0,0 +1,99 @@
+use super::*;
+use crate::json2cel::json_to_cel_variables;
+use crate::json2cel::{JsonOptions, NumberMode};
+use cel::{Context, Program};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+fn evaluated(source: &str) -> JsonValue {
+    let program = Program::compile(source).expect("expression should compile");
+    let value = program
+        .execute(&Context::default())
+        .expect("expression should evaluate");
+    cel_value_to_json_value(&value, false, None)
+}
+
+#[test]
+fn test_round_trip() {
+    let input = json!({
+        "null": null,
+        "bool": true,
+        "int": -7,
+        "uint": 18446744073709551615u64,
+        "float": 1.5,
+        "string": "hello",
+        "list": [1, "two", [3.0]],
+        "map": {"nested": {"empty": {}}}
+    });
+    let options = JsonOptions {
+        root_var: "this",
+        slurp: false,
+        from_json5: false,
+        from_toml: false,
+        from_yaml: false,
+        number_mode: NumberMode::Auto,
+        expand_only: &[],
+        expose_fields: false,
+    };
+    let vars = json_to_cel_variables(&input.to_string(), &options).unwrap();
+    assert_eq!(
+        cel_value_to_json_value(vars.get("this").unwrap(), false, None),
+        input
+    );
+}
+
+#[test]
+fn test_bytes_as_base64() {
+    assert_eq!(evaluated("b'hello'"), json!("aGVsbG8="));
+}
+
+#[test]
+fn test_timestamp_as_rfc3339() {
+    assert_eq!(
+        evaluated("timestamp('2024-01-02T03:04:05Z')"),
+        json!("2024-01-02T03:04:05+00:00")
+    );
+}
+
+#[test]
+fn test_duration_as_seconds() {
+    assert_eq!(evaluated("duration('1h')"), json!("3600s"));
+    assert_eq!(evaluated("duration('0s')"), json!("0s"));
+    assert_eq!(evaluated("duration('1.5s')"), json!("1.500s"));
+    assert_eq!(evaluated("duration('-1.5s')"), json!("-1.500s"));
+    assert_eq!(evaluated("duration('-250ms')"), json!("-0.250s"));
+    assert_eq!(evaluated("duration('1us')"), json!("0.000001s"));
+    assert_eq!(evaluated("duration('2ns')"), json!("0.000000002s"));
+}
+
+#[test]
+fn test_duration_as_iso8601() {
+    let program = Program::compile("duration('1h')").unwrap();
+    let value = program.execute(&Context::default()).unwrap();
+    assert_eq!(
+        cel_value_to_json_value(&value, true, None),
+        json!("PT3600S")
+    );
+}
+
+#[test]
+fn test_non_string_map_keys() {
+    let mut map = HashMap::new();
+    map.insert(Key::Int(-1), CelValue::Null);
+    map.insert(Key::Uint(2), CelValue::Null);
+    map.insert(Key::Bool(true), CelValue::Null);
+    map.insert(Key::String(Arc::new("s".to_string())), CelValue::Null);
+    assert_eq!(
+        cel_value_to_json_value(&CelValue::Map(map.into()), false, None),
+        json!({"-1": null, "2": null, "true": null, "s": null})
+    );
+}
+
+#[test]
+fn test_float_precision() {
+    let program = Program::compile("[0.1 + 0.2, 2.0 / 3.0, -1.005, 7, 1e300]").unwrap();
+    let value = program.execute(&Context::default()).unwrap();
+    let json_value = cel_value_to_json_value(&value, false, Some(2));
+    assert_eq!(json_value.to_string(), "[0.3,0.67,-1.0,7,1e300]");
+}
@@ -2,13 +2,19 @@
 // Original: https://github.com/cloud-custodian/cel-python/blob/3a134c10394058c73a6bbe0e4ca7e862ea9707b3/docs/source/cli.rst
 // Copyright 2020 The Cloud Custodian Authors.
 // SPDX-License-Identifier: Apache-2.0
+use crate::json2cel::NumberMode;
+use cel::Program;
 use clap::ArgGroup;
 use clap::Parser;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize};
+use std::sync::{Arc, Mutex};
 
 #[derive(Debug, Clone)]
 pub struct Argument {
     pub name: String,
-    pub type_name: String,
+    /// The type, or None for `--arg-type-default` when it was left out as in `name=value`
+    pub type_name: Option<String>,
     pub value: String,
 }
 
@@ -16,11 +22,22 @@ impl std::str::FromStr for Argument {
     type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Format: name=value, without a colon before the '='
+        if let Some((name, value)) = s.split_once('=')
+            && !name.contains(':')
+        {
+            return Ok(Argument {
+                name: name.to_string(),
+                type_name: None,
+                value: value.to_string(),
+            });
+        }
+
         // Format: name:type=value
         let parts: Vec<&str> = s.splitn(2, ':').collect();
         if parts.len() != 2 {
             return Err(format!(
-                "Invalid argument format '{}'. Expected 'name:type=value'",
+                "Invalid argument format '{}'. Expected 'name:type=value' or 'name=value'",
                 s
             ));
         }
@@ -40,7 +57,7 @@ impl std::str::FromStr for Argument {
 
         Ok(Argument {
             name,
-            type_name: type_name.to_string(),
+            type_name: Some(type_name.to_string()),
             value,
         })
     }
@@ -56,29 +73,123 @@ impl std::str::FromStr for Argument {
     group(
         ArgGroup::new("program")
             .required(true)
-            .args(&["expression", "from_file"])
+            .args(&["expression", "from_file", "exprs"])
     ),
     group(
         ArgGroup::new("input_format")
-            .args(&["slurp", "from_json5", "from_toml", "from_yaml"])
+            .args(&[
+                "slurp",
+                "from_json5",
+                "from_toml",
+                "from_yaml",
+                "grok",
+                "length_prefixed_input",
+                "blank_separated",
+                "csv_input",
+            ])
     )
 )]
 pub struct Cli {
     /// Define argument variables, types, and values.
-    /// Format: name:type=value.
-    /// Supported types: int, uint, float, bool, string
+    /// Format: name:type=value, or name=value for the --arg-type-default type.
+    /// Supported types: int, uint, float, bool, string, bytes, duration, list, map, json.
+    /// Values of string, bytes, list, map, and json types are read from a file with @path
     #[arg(short = 'a', long = "arg", value_name = "name:type=value")]
     pub args: Vec<Argument>,
 
-    /// Return a status code based on boolean output
+    /// Type of the --arg and --arg-env arguments given without one, as in name=value
+    #[arg(
+        long = "arg-type-default",
+        value_name = "TYPE",
+        default_value = "string",
+        value_parser = ["int", "uint", "float", "bool", "string", "bytes", "duration", "list", "map", "json"]
+    )]
+    pub arg_type_default: String,
+
+    /// Load argument variables from the keys of a JSON object, or a YAML mapping if the file ends
+    /// in .yaml or .yml. --arg and --arg-env take precedence
+    #[arg(long = "arg-file", value_name = "FILE")]
+    pub arg_files: Vec<std::path::PathBuf>,
+
+    /// Treat the positional arguments after the expression as string values instead of input
+    /// files, and bind every argument to the celq_args variable
+    #[arg(long = "args", conflicts_with = "positional_jsonargs")]
+    pub positional_args: bool,
+
+    /// Like --args, but the positional arguments are parsed as JSON values
+    #[arg(long = "jsonargs")]
+    pub positional_jsonargs: bool,
+
+    /// Define an argument variable whose value is read from an environment variable.
+    /// Format: name:type=VAR, or name=VAR. These take precedence over --arg
+    #[arg(long = "arg-env", value_name = "name:type=VAR")]
+    pub arg_envs: Vec<Argument>,
+
+    /// Also insert the argument variables as keys of the root map. Input fields win on conflicts
+    #[arg(long = "merge-args-into-root")]
+    pub merge_args_into_root: bool,
+
+    /// Return a status code based on boolean output instead of printing it
     /// true = 0, false = 1, exception = 2
     #[arg(short = 'b', long = "boolean")]
     pub boolean: bool,
 
-    /// Do not read JSON input from stdin
+    /// Print the output as usual, and set the exit code from the truthiness of the last output
+    /// true = 0, false or null = 1, exception = 2
+    // -b already decides the exit code and hides the output, so combining it with -e is
+    // rejected instead of letting one of them silently win
+    #[arg(short = 'e', long = "exit-status", conflicts_with = "boolean")]
+    pub exit_status: bool,
+
+    /// Treat the expression as a check on each NDJSON record, print the line number and input
+    /// of every record it does not pass, and exit with 1 if any failed
+    #[arg(
+        long = "validate",
+        conflicts_with_all = [
+            "slurp",
+            "from_json5",
+            "from_toml",
+            "from_yaml",
+            "null_input",
+            "env_input",
+            "boolean",
+            "exit_status",
+            "count",
+            "find",
+            "index_by",
+            "index_by_line",
+            "error_output",
+            "grok",
+            "csv_input",
+            "blank_separated",
+            "length_prefixed_input",
+        ]
+    )]
+    pub validate: bool,
+
+    /// Print only the number of truthy results instead of the results themselves
+    #[arg(long = "count", conflicts_with_all = ["boolean", "void"])]
+    pub count: bool,
+
+    /// Do not read JSON input from stdin. The root variable is bound to null
     #[arg(short = 'n', long = "null-input")]
     pub null_input: bool,
 
+    /// Bind a map of all environment variables to the root variable instead of reading input
+    #[arg(
+        long = "env-input",
+        conflicts_with_all = ["null_input", "files", "input", "input_format", "args_from_stdin_header"]
+    )]
+    pub env_input: bool,
+
+    /// Read the first line of stdin as a JSON object of argument variables, and the rest as input.
+    /// Its fields take precedence over --arg
+    #[arg(
+        long = "args-from-stdin-header",
+        conflicts_with_all = ["null_input", "files", "input"]
+    )]
+    pub args_from_stdin_header: bool,
+
     /// Do not write JSON output to stdout
     #[arg(long = "void")]
     pub void: bool,
@@ -100,6 +211,124 @@ pub struct Cli {
     #[arg(long = "from-yaml")]
     pub from_yaml: bool,
 
+    /// How JSON numbers of the input are converted to CEL numbers
+    #[arg(long = "number-mode", value_enum, default_value_t = NumberMode::Auto)]
+    pub number_mode: NumberMode,
+
+    /// Fail on JSON integers that fit neither an int nor a uint, instead of reading them as
+    /// floats and losing precision
+    #[arg(
+        long = "bignum-error",
+        conflicts_with_all = ["from_json5", "from_toml", "from_yaml"]
+    )]
+    pub bignum_error: bool,
+
+    /// Read input from this path, which may be a named pipe or a Unix socket, before any input
+    /// files. Can be repeated, and is how input files are given with --from-file or --expr
+    #[arg(short = 'i', long = "input", value_name = "PATH")]
+    pub input: Vec<std::path::PathBuf>,
+
+    /// Decompress gzipped input. Input files ending in .gz are always decompressed
+    #[arg(short = 'z', long = "gzip")]
+    pub gzip: bool,
+
+    /// Keep reading the input file as lines are appended to it, like tail -f, until Ctrl-C.
+    /// Each output is flushed as soon as it is written
+    #[arg(
+        short = 'F',
+        long = "follow",
+        conflicts_with_all = [
+            "input_format",
+            "raw_input",
+            "seq",
+            "null_input",
+            "env_input",
+            "gzip",
+            "header_line",
+            "presort_by",
+            "batch_size",
+            "index_by",
+            "index_by_line",
+            "boolean",
+            "count",
+        ]
+    )]
+    pub follow: bool,
+
+    /// Parse each input line with a regex, binding its named capture groups to the root variable.
+    /// Lines that do not match are skipped
+    #[arg(long = "grok", value_name = "REGEX")]
+    pub grok: Option<String>,
+
+    /// Treat the first NDJSON line as a JSON object whose fields are default variables for the
+    /// following lines
+    #[arg(long = "header-line", conflicts_with = "input_format")]
+    pub header_line: bool,
+
+    /// Report NDJSON lines that fail to parse or evaluate on stderr and keep processing the rest
+    #[arg(short = 'C', long = "continue", conflicts_with = "input_format")]
+    pub continue_on_error: bool,
+
+    /// Output an object with the error, line number, and input in place of each NDJSON line that
+    /// fails to parse or evaluate
+    #[arg(
+        long = "error-output",
+        conflicts_with_all = ["input_format", "continue_on_error"]
+    )]
+    pub error_output: bool,
+
+    /// Print each input record and its result to stderr as `input => output`, to trace which
+    /// record produced which result
+    #[arg(short = 'v', long = "verbose")]
+    pub verbose: bool,
+
+    /// Read input as JSON frames, each prefixed by its length as a 4-byte big-endian integer
+    #[arg(long = "length-prefixed-input")]
+    pub length_prefixed_input: bool,
+
+    /// Bind each input line to the root variable as a string instead of parsing it as JSON.
+    /// With --slurp, the whole input is bound as a single string
+    #[arg(
+        long = "raw-input",
+        conflicts_with_all = [
+            "from_json5",
+            "from_toml",
+            "from_yaml",
+            "grok",
+            "length_prefixed_input",
+            "blank_separated",
+            "seq",
+            "csv_input",
+            "header_line",
+            "presort_by",
+            "batch_size",
+            "find",
+            "precheck",
+            "env_input",
+        ]
+    )]
+    pub raw_input: bool,
+
+    /// Read input as an RFC 7464 JSON text sequence, with records separated by the RS character.
+    /// Malformed records are handled like NDJSON lines
+    #[arg(
+        long = "seq",
+        conflicts_with_all = ["input_format", "header_line", "presort_by", "batch_size", "find", "index_by_line"]
+    )]
+    pub seq: bool,
+
+    /// Read input as JSON documents that may span several lines, separated by blank lines
+    #[arg(long = "blank-separated")]
+    pub blank_separated: bool,
+
+    /// Read input as CSV, binding each row to the root variable as a map keyed by the header row
+    #[arg(long = "csv-input")]
+    pub csv_input: bool,
+
+    /// Convert CSV fields that look like booleans or numbers to bool, int, or float values
+    #[arg(long = "csv-infer-types", requires = "csv_input")]
+    pub csv_infer_types: bool,
+
     /// Parallelism level for NDJSON inputs (number of threads, -1 for all available)
     #[arg(
         short = 'j',
@@ -110,28 +339,433 @@ pub struct Cli {
     )]
     pub parallelism: i32,
 
+    /// Sort NDJSON records by the result of a CEL key expression before evaluating
+    #[arg(
+        long = "presort-by",
+        value_name = "expr",
+        conflicts_with_all = ["input_format", "null_input", "env_input"]
+    )]
+    pub presort_by: Option<String>,
+
+    /// Group NDJSON records by the result of a CEL key expression, and evaluate the expression
+    /// once per group with the root variable bound to the list of its records, in key order
+    #[arg(
+        long = "group-by",
+        value_name = "expr",
+        conflicts_with_all = [
+            "input_format",
+            "raw_input",
+            "seq",
+            "follow",
+            "header_line",
+            "presort_by",
+            "batch_size",
+            "find",
+            "index_by_line",
+            "continue_on_error",
+            "error_output",
+            "validate",
+            "null_input",
+            "env_input",
+        ]
+    )]
+    pub group_by: Option<String>,
+
+    /// Collect all results into one object, keyed by the result of a CEL expression per record
+    #[arg(
+        long = "index-by",
+        value_name = "expr",
+        conflicts_with = "error_output"
+    )]
+    pub index_by: Option<String>,
+
+    /// How --index-by handles records with the same key
+    #[arg(
+        long = "index-dup",
+        value_enum,
+        default_value_t = IndexDup::Last,
+        requires = "index_by"
+    )]
+    pub index_dup: IndexDup,
+
+    /// Collect all results into one object, keyed by the line number of each NDJSON record
+    #[arg(
+        long = "index-by-line",
+        conflicts_with_all = ["index_by", "input_format", "null_input", "env_input", "explode_keys", "flatten"]
+    )]
+    pub index_by_line: bool,
+
+    /// Abort unless a boolean CEL expression holds for the first record of each input
+    #[arg(
+        long = "precheck",
+        value_name = "expr",
+        conflicts_with_all = ["grok", "csv_input"]
+    )]
+    pub precheck: Option<String>,
+
+    /// Stop at the first NDJSON record whose result is truthy, and output only that result
+    #[arg(
+        long = "find",
+        conflicts_with_all = ["input_format", "presort_by", "index_by", "index_by_line"]
+    )]
+    pub find: bool,
+
+    /// With --find, output the matching record instead of its result
+    #[arg(long = "find-input", requires = "find")]
+    pub find_input: bool,
+
+    /// Output only the first N truthy results, and stop reading NDJSON input once they are found
+    #[arg(
+        long = "limit",
+        value_name = "N",
+        conflicts_with_all = ["find", "index_by", "index_by_line"]
+    )]
+    pub limit: Option<std::num::NonZeroUsize>,
+
+    /// Read and evaluate NDJSON inputs in batches of N lines, bounding memory in parallel mode
+    #[arg(
+        long = "batch-size",
+        value_name = "N",
+        conflicts_with_all = ["input_format", "presort_by", "find"]
+    )]
+    pub batch_size: Option<std::num::NonZeroUsize>,
+
+    /// Fail on NDJSON lines longer than N bytes without reading them into memory, or skip them
+    /// with --continue. Guards against pathological input
+    #[arg(
+        long = "max-line-bytes",
+        value_name = "N",
+        conflicts_with_all = ["input_format", "raw_input", "seq", "follow"]
+    )]
+    pub max_line_bytes: Option<std::num::NonZeroUsize>,
+
+    /// Also bind these top-level fields of the input object as variables
+    #[arg(long = "expand-only", value_name = "FIELDS", value_delimiter = ',')]
+    pub expand_only: Vec<String>,
+
+    /// Also bind every top-level field of the input object as a variable, so `x + y` works
+    /// like `this.x + this.y`. Fields that are not valid identifiers or are named like an
+    /// argument are skipped with a warning
+    #[arg(long = "expose-fields", conflicts_with = "expand_only")]
+    pub expose_fields: bool,
+
+    /// Evaluate variables the expression references but that are not bound, such as fields
+    /// missing under --expose-fields, to null instead of failing. Fields of maps are not affected
+    #[arg(long = "null-for-missing")]
+    pub null_for_missing: bool,
+
+    /// Bind the name of the input source to the `filename` variable ("<stdin>" for stdin)
+    #[arg(long = "with-filename")]
+    pub with_filename: bool,
+
     /// Variable name for the root JSON input
-    #[arg(short = 'R', long = "root-var", default_value = "this")]
+    #[arg(
+        short = 'R',
+        long = "root-var",
+        default_value = "this",
+        value_parser = parse_root_var
+    )]
     pub root_var: String,
 
     /// If the output is a JSON string, output it raw without quotes
     #[arg(short = 'r', long = "raw-output")]
     pub raw_output: bool,
 
+    /// Escape every non-ASCII character of the JSON output as \uXXXX, like jq -a. Raw strings
+    /// are then output as escaped JSON strings too
+    #[arg(
+        short = 'A',
+        long = "ascii-output",
+        conflicts_with_all = ["csv_output", "tab", "sql_insert"]
+    )]
+    pub ascii_output: bool,
+
+    /// Always output results as JSON, quoting strings. This is the default, and cannot be combined
+    /// with --raw-output or the other output formats
+    #[arg(
+        long = "tojson",
+        conflicts_with_all = ["raw_output", "csv_output", "tab", "sql_insert"]
+    )]
+    pub tojson: bool,
+
+    /// Write bytes results to the output as raw bytes, with nothing between them, instead of
+    /// base64 strings. Results of any other type are an error
+    #[arg(
+        long = "output-raw-bytes",
+        conflicts_with_all = [
+            "raw_output",
+            "tojson",
+            "fromjson",
+            "pretty_print",
+            "csv_output",
+            "tab",
+            "sql_insert",
+            "type_of",
+            "diff",
+            "stamp",
+            "explode_keys",
+            "flatten",
+            "index_by",
+            "index_by_line",
+            "error_output",
+            "validate",
+            "count",
+            "in_place",
+            "nul_output",
+            "no_trailing_newline",
+        ]
+    )]
+    pub output_raw_bytes: bool,
+
+    /// Parse string results as JSON before they are output. Other results are output as usual
+    #[arg(long = "fromjson")]
+    pub fromjson: bool,
+
+    /// Output durations in ISO 8601 (e.g. "PT3600S") instead of protobuf-style seconds (e.g. "3600s")
+    #[arg(long = "iso-durations")]
+    pub iso_durations: bool,
+
+    /// Round floats in the output to N decimal places, at most 17. They are still output as
+    /// JSON numbers
+    #[arg(
+        long = "float-precision",
+        value_name = "N",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(0..=17)
+    )]
+    pub float_precision: Option<usize>,
+
+    /// Separate outputs with NUL characters instead of newlines, for xargs -0. Most useful
+    /// with --raw-output
+    #[arg(short = '0', long = "nul-output")]
+    pub nul_output: bool,
+
+    /// Do not write a newline, or NUL with -0, after the last output. Outputs are still
+    /// separated by one
+    #[arg(long = "no-trailing-newline")]
+    pub no_trailing_newline: bool,
+
+    /// Flush each output as soon as it is written, for live pipelines like tail -f. Requires
+    /// -j 1
+    #[arg(
+        short = 'U',
+        long = "unbuffered",
+        conflicts_with_all = ["slurp", "presort_by"]
+    )]
+    pub unbuffered: bool,
+
+    /// Write outputs to this file instead of stdout, creating or truncating it. Errors are
+    /// still reported on stderr
+    #[arg(short = 'o', long = "output-file", value_name = "PATH")]
+    pub output_file: Option<std::path::PathBuf>,
+
+    /// Replace each input file with its outputs, like sed -i. A file is only replaced once all
+    /// of its records were processed, and is left untouched on errors
+    #[arg(
+        long = "in-place",
+        conflicts_with_all = [
+            "output_file",
+            "input",
+            "gzip",
+            "follow",
+            "unbuffered",
+            "void",
+            "boolean",
+            "exit_status",
+            "count",
+            "validate",
+            "find",
+            "limit",
+            "index_by",
+            "index_by_line",
+            "null_input",
+            "env_input",
+            "continue_on_error",
+            "error_output",
+        ]
+    )]
+    pub in_place: bool,
+
     /// Output the fields of each object with the keys in sorted order
     #[arg(short = 'S', long = "sort-keys")]
     pub sort_keys: bool,
 
+    /// With --sort-keys, sort objects whose keys are all numbers by numeric value
+    #[arg(long = "numeric-key-sort", requires = "sort_keys")]
+    pub numeric_key_sort: bool,
+
+    /// Shorten output strings longer than N characters to their first N characters
+    #[arg(long = "truncate-strings", value_name = "N")]
+    pub truncate_strings: Option<usize>,
+
+    /// Suffix appended to strings shortened by --truncate-strings
+    #[arg(
+        long = "truncate-marker",
+        value_name = "MARKER",
+        default_value = "...",
+        requires = "truncate_strings"
+    )]
+    pub truncate_marker: String,
+
+    /// Print how many results had each CEL type to stderr at the end
+    #[arg(long = "type-summary")]
+    pub type_summary: bool,
+
+    /// Output the CEL type name of each result, like int, list or map, instead of its value
+    #[arg(
+        long = "type-of",
+        alias = "type",
+        conflicts_with_all = [
+            "fromjson",
+            "diff",
+            "stamp",
+            "explode_keys",
+            "flatten",
+            "index_by",
+            "index_by_line",
+        ]
+    )]
+    pub type_of: bool,
+
+    /// Skip results identical to the result of one of the last N records
+    #[arg(long = "dedupe-window", value_name = "N")]
+    pub dedupe_window: Option<std::num::NonZeroUsize>,
+
+    /// Skip results identical to the previous result that was output, like uniq
+    #[arg(long = "changes-only", conflicts_with = "dedupe_window")]
+    pub changes_only: bool,
+
+    /// Skip results identical to any result that was already output, keeping the first
+    #[arg(
+        short = 'u',
+        long = "dedup",
+        conflicts_with_all = ["dedupe_window", "changes_only"]
+    )]
+    pub dedup: bool,
+
+    /// Output this JSON value in place of results that are null
+    #[arg(long = "default-output", value_name = "JSON", value_parser = parse_json_value)]
+    pub default_output: Option<serde_json::Value>,
+
+    /// Enable the env(name) function, which reads environment variables
+    #[arg(long = "allow-env")]
+    pub allow_env: bool,
+
+    /// Enable the readFile(path) function, which reads files with the permissions of celq
+    #[arg(long = "allow-fs")]
+    pub allow_fs: bool,
+
+    /// Make uuid() return the same sequence of UUIDs on every run. The sequence is only
+    /// reproducible with -j 1
+    #[arg(long = "seed", value_name = "N")]
+    pub seed: Option<u64>,
+
+    /// Refuse to run programs whose AST has more than N nodes
+    #[arg(long = "max-complexity", value_name = "N")]
+    pub max_complexity: Option<usize>,
+
     /// Read CEL expression from a file
     #[arg(short = 'f', long = "from-file", value_name = "FILE")]
     pub from_file: Option<std::path::PathBuf>,
 
+    /// Evaluate a pipeline of CEL expressions, where each one reads the result of the previous one
+    /// as the root variable. Can be repeated
+    #[arg(long = "expr", value_name = "expr", conflicts_with = "from_file")]
+    pub exprs: Vec<String>,
+
     #[arg(short = 'p', long = "pretty-print")]
     pub pretty_print: bool,
 
+    /// Number of spaces to indent pretty-printed output by, or 0 to indent with tabs
+    #[arg(
+        long = "indent",
+        value_name = "N",
+        default_value_t = 2,
+        requires = "pretty_print"
+    )]
+    pub indent: usize,
+
+    /// Indent pretty-printed output with tabs, like --indent 0
+    #[arg(
+        long = "tab-indent",
+        requires = "pretty_print",
+        conflicts_with = "indent"
+    )]
+    pub tab_indent: bool,
+
+    /// Output each result on a single line. This is the default, and cannot be combined with
+    /// --pretty-print
+    #[arg(short = 'c', long = "compact", conflicts_with = "pretty_print")]
+    pub compact: bool,
+
+    /// When to color JSON output: auto colors it only when stdout is a terminal and NO_COLOR is
+    /// not set. Raw strings and the CSV, TSV, and SQL outputs are never colored
+    #[arg(long = "color", value_name = "WHEN", default_value = "auto")]
+    pub color: ColorMode,
+
+    /// Output each result, which must be a map, as an SQL INSERT statement into TABLE
+    #[arg(
+        long = "sql-insert",
+        value_name = "TABLE",
+        conflicts_with = "pretty_print"
+    )]
+    pub sql_insert: Option<String>,
+
+    /// Columns for --sql-insert, in order. Defaults to the keys of each result in sorted order
+    #[arg(
+        long = "sql-columns",
+        value_name = "COLUMNS",
+        value_delimiter = ',',
+        requires = "sql_insert"
+    )]
+    pub sql_columns: Vec<String>,
+
+    /// Output each result, which must be a list of maps, as CSV with a header row
+    #[arg(long = "csv-output", conflicts_with_all = ["pretty_print", "sql_insert"])]
+    pub csv_output: bool,
+
+    /// Output each result, which must be a list of scalars, as one line of tab-separated fields
+    #[arg(
+        long = "tab",
+        conflicts_with_all = ["pretty_print", "sql_insert", "csv_output"]
+    )]
+    pub tab: bool,
+
+    /// Output one {"key": k, "value": v} record per entry of map results, in key order
+    #[arg(long = "explode-keys")]
+    pub explode_keys: bool,
+
+    /// Output each element of list results on its own, descending into nested lists
+    #[arg(long = "flatten")]
+    pub flatten: bool,
+
+    /// Add the time each map result was output at, as an RFC 3339 string, under FIELD
+    #[arg(
+        long = "stamp",
+        value_name = "FIELD",
+        conflicts_with_all = ["index_by", "index_by_line"]
+    )]
+    pub stamp: Option<String>,
+
+    /// Output an RFC 6902 JSON Patch from the input object to the result instead of the result
+    #[arg(long = "diff")]
+    pub diff: bool,
+
+    /// Print the expression with its constant subexpressions folded, then exit
+    #[arg(long = "optimize")]
+    pub optimize: bool,
+
+    /// Only compile the expression, without reading input. Exits with 0 if it compiles, or
+    /// prints the errors and exits with 2
+    #[arg(long = "check", visible_alias = "compile-only")]
+    pub check: bool,
+
     /// CEL expression to evaluate
     #[arg(value_name = "expr")]
     pub expression: Option<String>,
+
+    /// Input files to read instead of stdin
+    #[arg(value_name = "FILE")]
+    pub files: Vec<std::path::PathBuf>,
 }
 
 fn parse_parallelism(s: &str) -> Result<i32, String> {
@@ -148,16 +782,175 @@ fn parse_parallelism(s: &str) -> Result<i32, String> {
     }
 }
 
+/// Words reserved by the CEL grammar that cannot be used as identifiers
+const CEL_RESERVED_WORDS: &[&str] = &[
+    "true",
+    "false",
+    "null",
+    "in",
+    "as",
+    "break",
+    "const",
+    "continue",
+    "else",
+    "for",
+    "function",
+    "if",
+    "import",
+    "let",
+    "loop",
+    "package",
+    "namespace",
+    "return",
+    "var",
+    "void",
+    "while",
+];
+
+/// Returns true if `s` is made of letters, digits, or '_', and does not start with a digit
+fn has_identifier_syntax(s: &str) -> bool {
+    let mut chars = s.chars();
+    let starts_correctly = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_');
+    starts_correctly && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Returns true if `s` can be used as the name of a CEL variable
+pub fn is_cel_identifier(s: &str) -> bool {
+    has_identifier_syntax(s) && !CEL_RESERVED_WORDS.contains(&s)
+}
+
+fn parse_root_var(s: &str) -> Result<String, String> {
+    if !has_identifier_syntax(s) {
+        Err(format!(
+            "'{}' is not a valid CEL identifier (expected letters, digits, or '_', not starting with a digit)",
+            s
+        ))
+    } else if CEL_RESERVED_WORDS.contains(&s) {
+        Err(format!("'{}' is a reserved word in CEL", s))
+    } else {
+        Ok(s.to_string())
+    }
+}
+
+fn parse_json_value(s: &str) -> Result<serde_json::Value, String> {
+    serde_json::from_str(s).map_err(|e| format!("'{}' is not valid JSON: {}", s, e))
+}
+
 #[derive(Clone, Debug)]
 pub struct InputParameters {
     pub root_var: String,
+    pub expand_only: Vec<String>,
+    pub expose_fields: Option<SkippedFields>,
+    pub null_variables: Vec<String>,
     pub null_input: bool,
+    pub env_input: bool,
     pub slurp: bool,
     pub from_json5: bool,
     pub from_toml: bool,
     pub from_yaml: bool,
+    pub number_mode: NumberMode,
+    pub bignum_error: bool,
+    pub grok: Option<String>,
     pub parallelism: i32,
+    pub presort_by: Option<Arc<Program>>,
+    pub group_by: Option<Arc<Program>>,
+    pub batch_size: Option<std::num::NonZeroUsize>,
+    pub max_line_bytes: Option<std::num::NonZeroUsize>,
+    pub find: bool,
+    pub find_input: bool,
     pub sort_keys: bool,
+    pub numeric_key_sort: bool,
     pub pretty_print: bool,
+    pub indent: String,
     pub raw_output: bool,
+    pub ascii_output: bool,
+    pub output_raw_bytes: bool,
+    pub fromjson: bool,
+    pub iso_durations: bool,
+    pub float_precision: Option<usize>,
+    pub diff: bool,
+    pub with_filename: bool,
+    pub header_line: bool,
+    pub continue_on_error: bool,
+    pub truncate_strings: Option<usize>,
+    pub truncate_marker: String,
+    pub error_output: bool,
+    pub validate: bool,
+    pub verbose: bool,
+    pub args_from_stdin_header: bool,
+    pub compact: bool,
+    pub color: bool,
+    pub type_of: bool,
+    pub limit: Option<LimitRemaining>,
+    pub length_prefixed_input: bool,
+    pub blank_separated: bool,
+    pub raw_input: bool,
+    pub seq: bool,
+    pub csv_input: bool,
+    pub csv_infer_types: bool,
+    pub files: Vec<std::path::PathBuf>,
+    pub gzip: bool,
+    pub follow: Option<FollowStop>,
+    pub sql_insert: Option<String>,
+    pub sql_columns: Vec<String>,
+    pub csv_output: bool,
+    pub tab: bool,
+    pub default_output: Option<serde_json::Value>,
+    pub allow_env: bool,
+    pub allow_fs: bool,
+    pub now: chrono::DateTime<chrono::FixedOffset>,
+    pub uuid_state: Option<UuidState>,
+    pub regex_cache: RegexCache,
+    pub explode_keys: bool,
+    pub flatten: bool,
+    pub stamp: Option<String>,
+    pub merge_args_into_root: bool,
+    pub stages: Vec<Arc<Program>>,
+    pub precheck: Option<Arc<Program>>,
+    pub index_by: Option<Arc<Program>>,
+    pub index_dup: IndexDup,
+    pub index_by_line: bool,
+}
+
+/// How `--index-by` resolves records with the same key
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum IndexDup {
+    /// Keep the first record
+    First,
+    /// Keep the last record
+    Last,
+    /// Fail the run
+    Error,
 }
+
+/// When `--color` colors JSON output
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum ColorMode {
+    /// Color output written to a terminal, unless NO_COLOR is set
+    Auto,
+    /// Always color output
+    Always,
+    /// Never color output
+    Never,
+}
+
+/// State of the generator behind `uuid()` with `--seed`, shared by every document
+pub type UuidState = Arc<AtomicU64>;
+
+/// Regexes compiled by `regexExtract()` and `regexReplace()`, shared by every document
+pub type RegexCache = Arc<Mutex<HashMap<String, regex::Regex>>>;
+
+/// Top-level fields `--expose-fields` already warned about skipping, shared by every document
+pub type SkippedFields = Arc<Mutex<BTreeSet<String>>>;
+
+/// Set once Ctrl-C is pressed, so `--follow` stops waiting for new lines
+pub type FollowStop = Arc<AtomicBool>;
+
+/// Number of truthy results `--limit` still lets through, shared by every input file
+pub type LimitRemaining = Arc<AtomicUsize>;
+
+#[cfg(test)]
+#[path = "cli_test.rs"]
+mod test;
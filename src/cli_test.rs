@@ -0,0 +1,53 @@
+use super::*;
+
+#[test]
+fn test_parse_root_var_valid() {
+    assert_eq!(parse_root_var("this").unwrap(), "this");
+    assert_eq!(parse_root_var("_request2").unwrap(), "_request2");
+}
+
+#[test]
+fn test_parse_root_var_invalid_identifier() {
+    for name in [".", "$", "", "2fast", "my-var", "a.b"] {
+        let err = parse_root_var(name).unwrap_err();
+        assert!(err.contains("not a valid CEL identifier"), "{name}: {err}");
+    }
+}
+
+#[test]
+fn test_parse_root_var_reserved_word() {
+    let err = parse_root_var("null").unwrap_err();
+    assert!(err.contains("reserved word"));
+}
+
+#[test]
+fn test_parse_parallelism() {
+    assert_eq!(parse_parallelism("4").unwrap(), 4);
+    assert_eq!(parse_parallelism("-5").unwrap(), -1);
+    assert!(parse_parallelism("0").is_err());
+    assert!(parse_parallelism("many").is_err());
+}
+
+#[test]
+fn test_parse_argument() {
+    let arg: Argument = "x:int=1".parse().unwrap();
+    assert_eq!(arg.name, "x");
+    assert_eq!(arg.type_name.as_deref(), Some("int"));
+    assert_eq!(arg.value, "1");
+
+    // The value may contain both separators
+    let arg: Argument = "url:string=http://a?b=c".parse().unwrap();
+    assert_eq!(arg.type_name.as_deref(), Some("string"));
+    assert_eq!(arg.value, "http://a?b=c");
+}
+
+#[test]
+fn test_parse_argument_without_type() {
+    let arg: Argument = "url=http://a?b=c".parse().unwrap();
+    assert_eq!(arg.name, "url");
+    assert_eq!(arg.type_name, None);
+    assert_eq!(arg.value, "http://a?b=c");
+
+    assert!("x".parse::<Argument>().is_err());
+    assert!("x:int".parse::<Argument>().is_err());
+}
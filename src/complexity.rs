@@ -0,0 +1,47 @@
+use cel::common::ast::{EntryExpr, Expr};
+use cel::parser::Expression;
+
+/// Statically estimate the cost of evaluating an expression as the number of nodes in its AST
+///
+/// Macros such as `all` or `map` count the nodes of their expanded comprehension, so they are
+/// more expensive than the source suggests. The estimate does not depend on the input.
+pub fn complexity(expr: &Expression) -> usize {
+    let children: usize = match &expr.expr {
+        Expr::Call(call) => {
+            call.target.as_deref().map_or(0, complexity)
+                + call.args.iter().map(complexity).sum::<usize>()
+        }
+        Expr::Select(select) => complexity(&select.operand),
+        Expr::List(list) => list.elements.iter().map(complexity).sum(),
+        Expr::Map(map) => map
+            .entries
+            .iter()
+            .map(|entry| entry_complexity(&entry.expr))
+            .sum(),
+        Expr::Struct(st) => st
+            .entries
+            .iter()
+            .map(|entry| entry_complexity(&entry.expr))
+            .sum(),
+        Expr::Comprehension(comp) => {
+            complexity(&comp.iter_range)
+                + complexity(&comp.accu_init)
+                + complexity(&comp.loop_cond)
+                + complexity(&comp.loop_step)
+                + complexity(&comp.result)
+        }
+        _ => 0,
+    };
+    children + 1
+}
+
+fn entry_complexity(entry: &EntryExpr) -> usize {
+    match entry {
+        EntryExpr::MapEntry(map_entry) => complexity(&map_entry.key) + complexity(&map_entry.value),
+        EntryExpr::StructField(field) => complexity(&field.value),
+    }
+}
+
+#[cfg(test)]
+#[path = "complexity_test.rs"]
+mod test;
@@ -0,0 +1,30 @@
+use super::*;
+use cel::Program;
+
+fn program_complexity(source: &str) -> usize {
+    let program = Program::compile(source).expect("expression should compile");
+    complexity(program.expression())
+}
+
+#[test]
+fn test_literal_is_one_node() {
+    assert_eq!(program_complexity("1"), 1);
+}
+
+#[test]
+fn test_counts_every_node() {
+    assert_eq!(program_complexity("this.x + 2"), 4);
+    assert_eq!(program_complexity("[1, 2, {\"a\": 3}]"), 6);
+}
+
+#[test]
+fn test_nesting_increases_complexity() {
+    let shallow = program_complexity("this.a");
+    let nested = program_complexity("this.a.b.c.d.e.f.g.h");
+    assert!(nested > shallow);
+}
+
+#[test]
+fn test_macros_count_their_expansion() {
+    assert!(program_complexity("this.all(x, x > 0)") > program_complexity("this[0] > 0"));
+}
@@ -0,0 +1,392 @@
+use crate::InputParameters;
+use crate::cel_value_to_json_value;
+use crate::cli::RegexCache;
+use crate::json2cel::json_value_to_cel_value;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use cel::extractors::This;
+use cel::objects::Key;
+use cel::{Context, FunctionContext, ResolveResult, Value as CelValue};
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::env::VarError;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use uuid::Uuid;
+
+/// Register the functions celq adds on top of standard CEL
+///
+/// Functions with side channels into the host, like reading the environment, are only
+/// registered when their flag is passed.
+///
+/// # Arguments
+/// * `context` - The context to register the functions on
+/// * `input_params` - Input configuration parameters, deciding which functions are enabled
+pub fn register_functions(context: &mut Context, input_params: &InputParameters) {
+    // The instant is fixed at startup, so every document of a run sees the same time
+    let now = input_params.now;
+    context.add_function("now", move || now);
+
+    // A seeded generator is shared by every document, so the sequence continues across them
+    match &input_params.uuid_state {
+        Some(state) => {
+            let state = state.clone();
+            context.add_function("uuid", move || seeded_uuid(&state));
+        }
+        None => context.add_function("uuid", uuid),
+    }
+
+    context.add_function("base64Encode", base64_encode);
+    context.add_function("base64Decode", base64_decode);
+
+    context.add_function("sha256", sha256);
+
+    context.add_function("keys", keys);
+    context.add_function("values", values);
+
+    // Values are serialized like the results celq outputs
+    let iso_durations = input_params.iso_durations;
+    let float_precision = input_params.float_precision;
+    context.add_function("parseJSON", parse_json);
+    context.add_function("toJSON", move |value: CelValue| {
+        to_json(value, iso_durations, float_precision)
+    });
+
+    // Patterns are compiled once per run, as the same few are used for every document
+    let regexes = input_params.regex_cache.clone();
+    context.add_function(
+        "regexExtract",
+        move |ftx: &FunctionContext, text: Arc<String>, pattern: Arc<String>, group: i64| {
+            regex_extract(ftx, &regexes, text, pattern, group)
+        },
+    );
+    let regexes = input_params.regex_cache.clone();
+    context.add_function(
+        "regexReplace",
+        move |ftx: &FunctionContext, text: Arc<String>, pattern: Arc<String>, replacement| {
+            regex_replace(ftx, &regexes, text, pattern, replacement)
+        },
+    );
+
+    context.add_function("split", split);
+    context.add_function("trim", trim);
+    context.add_function("upperAscii", upper_ascii);
+    context.add_function("lowerAscii", lower_ascii);
+    context.add_function("replace", replace);
+
+    if input_params.allow_env {
+        context.add_function("env", env);
+    }
+
+    if input_params.allow_fs {
+        context.add_function("readFile", read_file);
+    }
+}
+
+/// Returns the value of an environment variable, failing if it is unset
+///
+/// ```cel
+/// env("PORT") == "8080"
+/// ```
+fn env(ftx: &FunctionContext, name: Arc<String>) -> ResolveResult {
+    match std::env::var(name.as_str()) {
+        Ok(value) => Ok(CelValue::String(Arc::new(value))),
+        Err(VarError::NotPresent) => {
+            Err(ftx.error(format!("environment variable '{}' is not set", name)))
+        }
+        Err(VarError::NotUnicode(_)) => Err(ftx.error(format!(
+            "environment variable '{}' is not valid unicode",
+            name
+        ))),
+    }
+}
+
+/// Returns the contents of a file as a string, failing if it cannot be read or is not UTF-8
+///
+/// ```cel
+/// readFile("VERSION").trim() != ""
+/// ```
+fn read_file(ftx: &FunctionContext, path: Arc<String>) -> ResolveResult {
+    std::fs::read_to_string(path.as_str())
+        .map(|contents| CelValue::String(Arc::new(contents)))
+        .map_err(|e| ftx.error(format!("readFile: failed to read '{}': {}", path, e)))
+}
+
+/// Returns a random version 4 UUID, different on every call
+///
+/// ```cel
+/// size(uuid()) == 36
+/// ```
+fn uuid() -> ResolveResult {
+    Ok(CelValue::String(Arc::new(Uuid::new_v4().to_string())))
+}
+
+/// Returns the next version 4 UUID of a sequence determined by the `--seed`
+fn seeded_uuid(state: &AtomicU64) -> ResolveResult {
+    let mut bytes = [0u8; 16];
+    bytes[..8].copy_from_slice(&splitmix64(state).to_le_bytes());
+    bytes[8..].copy_from_slice(&splitmix64(state).to_le_bytes());
+    let uuid = uuid::Builder::from_random_bytes(bytes).into_uuid();
+    Ok(CelValue::String(Arc::new(uuid.to_string())))
+}
+
+/// Advances a SplitMix64 generator, which is small and good enough for test data
+fn splitmix64(state: &AtomicU64) -> u64 {
+    const GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+    let mut z = state
+        .fetch_add(GAMMA, AtomicOrdering::Relaxed)
+        .wrapping_add(GAMMA);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Encodes a string or bytes as standard base64 with padding
+///
+/// ```cel
+/// base64Encode("hello") == "aGVsbG8="
+/// ```
+fn base64_encode(ftx: &FunctionContext, value: CelValue) -> ResolveResult {
+    let encoded = match value {
+        CelValue::String(s) => BASE64.encode(s.as_bytes()),
+        CelValue::Bytes(b) => BASE64.encode(b.as_slice()),
+        value => {
+            return Err(ftx.error(format!(
+                "base64Encode expects a string or bytes, got {:?}",
+                value
+            )));
+        }
+    };
+    Ok(CelValue::String(Arc::new(encoded)))
+}
+
+/// Decodes standard base64 into bytes, which `string()` turns into text
+///
+/// ```cel
+/// string(base64Decode("aGVsbG8=")) == "hello"
+/// ```
+fn base64_decode(ftx: &FunctionContext, encoded: Arc<String>) -> ResolveResult {
+    let decoded = BASE64
+        .decode(encoded.as_bytes())
+        .map_err(|e| ftx.error(format!("base64Decode: invalid base64: {}", e)))?;
+    Ok(CelValue::Bytes(Arc::new(decoded)))
+}
+
+/// Returns the SHA-256 digest of a string or bytes as lowercase hex
+///
+/// ```cel
+/// sha256("abc") == "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+/// ```
+fn sha256(ftx: &FunctionContext, value: CelValue) -> ResolveResult {
+    let digest = match value {
+        CelValue::String(s) => Sha256::digest(s.as_bytes()),
+        CelValue::Bytes(b) => Sha256::digest(b.as_slice()),
+        value => {
+            return Err(ftx.error(format!("sha256 expects a string or bytes, got {:?}", value)));
+        }
+    };
+    let hex: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+    Ok(CelValue::String(Arc::new(hex)))
+}
+
+/// Returns the keys of a map as a list, in sorted order
+///
+/// ```cel
+/// keys({"b": 1, "a": 2}) == ["a", "b"]
+/// ```
+fn keys(ftx: &FunctionContext, value: CelValue) -> ResolveResult {
+    let entries = sorted_entries(ftx, "keys", value)?;
+    let keys = entries.into_iter().map(|(key, _)| key.into()).collect();
+    Ok(CelValue::List(Arc::new(keys)))
+}
+
+/// Returns the values of a map as a list, in the sorted order of their keys
+///
+/// ```cel
+/// values({"b": 1, "a": 2}) == [2, 1]
+/// ```
+fn values(ftx: &FunctionContext, value: CelValue) -> ResolveResult {
+    let entries = sorted_entries(ftx, "values", value)?;
+    let values = entries.into_iter().map(|(_, value)| value).collect();
+    Ok(CelValue::List(Arc::new(values)))
+}
+
+/// CEL maps are unordered, so their entries are sorted by key to keep outputs deterministic
+fn sorted_entries(
+    ftx: &FunctionContext,
+    function: &str,
+    value: CelValue,
+) -> Result<Vec<(Key, CelValue)>, cel::ExecutionError> {
+    let CelValue::Map(map) = value else {
+        return Err(ftx.error(format!("{} expects a map, got {:?}", function, value)));
+    };
+    let mut entries: Vec<(Key, CelValue)> = map
+        .map
+        .iter()
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(entries)
+}
+
+/// Parses a JSON string into a CEL value, failing if it is not valid JSON
+///
+/// ```cel
+/// parseJSON("{\"id\": 7}").id == 7
+/// ```
+fn parse_json(ftx: &FunctionContext, text: Arc<String>) -> ResolveResult {
+    let json_value: serde_json::Value = serde_json::from_str(&text)
+        .map_err(|e| ftx.error(format!("parseJSON: invalid JSON: {}", e)))?;
+    Ok(json_value_to_cel_value(&json_value))
+}
+
+/// Serializes a value as compact JSON, the same way it would be output
+///
+/// ```cel
+/// toJSON({"ids": [1, 2]}) == "{\"ids\":[1,2]}"
+/// ```
+fn to_json(value: CelValue, iso_durations: bool, float_precision: Option<usize>) -> ResolveResult {
+    let json_value = cel_value_to_json_value(&value, iso_durations, float_precision);
+    Ok(CelValue::String(Arc::new(json_value.to_string())))
+}
+
+/// Most cached patterns are literals of the expression. Patterns built from the input could
+/// grow the cache without bound, so it is emptied once it is full.
+const REGEX_CACHE_CAPACITY: usize = 256;
+
+/// Compile a regex, or reuse the one compiled for the same pattern earlier in the run
+fn compile_regex(
+    ftx: &FunctionContext,
+    cache: &RegexCache,
+    pattern: &str,
+) -> Result<Regex, cel::ExecutionError> {
+    let cached = cache
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(pattern)
+        .cloned();
+    if let Some(regex) = cached {
+        return Ok(regex);
+    }
+
+    // Other documents can evaluate while the pattern compiles
+    let regex = Regex::new(pattern)
+        .map_err(|e| ftx.error(format!("invalid regex '{}': {}", pattern, e)))?;
+    let mut cache = cache.lock().unwrap_or_else(|e| e.into_inner());
+    if cache.len() >= REGEX_CACHE_CAPACITY {
+        cache.clear();
+    }
+    cache.insert(pattern.to_string(), regex.clone());
+    Ok(regex)
+}
+
+/// Returns a capture group of the first match of a regex, or null if the regex does not match
+/// or the group did not participate in the match. Group 0 is the whole match.
+///
+/// ```cel
+/// regexExtract("took 42ms", "(\\d+)ms", 1) == "42"
+/// ```
+fn regex_extract(
+    ftx: &FunctionContext,
+    cache: &RegexCache,
+    text: Arc<String>,
+    pattern: Arc<String>,
+    group: i64,
+) -> ResolveResult {
+    let regex = compile_regex(ftx, cache, &pattern)?;
+    let group = usize::try_from(group)
+        .ok()
+        .filter(|group| *group < regex.captures_len())
+        .ok_or_else(|| {
+            ftx.error(format!(
+                "regex '{}' has no capture group {}",
+                pattern, group
+            ))
+        })?;
+
+    Ok(regex
+        .captures(&text)
+        .and_then(|captures| captures.get(group))
+        .map_or(CelValue::Null, |m| {
+            CelValue::String(Arc::new(m.as_str().to_string()))
+        }))
+}
+
+/// Replaces every match of a regex, where the replacement can refer to groups as `$1` or `$name`
+///
+/// ```cel
+/// regexReplace("a-b-c", "-", "+") == "a+b+c"
+/// ```
+fn regex_replace(
+    ftx: &FunctionContext,
+    cache: &RegexCache,
+    text: Arc<String>,
+    pattern: Arc<String>,
+    replacement: Arc<String>,
+) -> ResolveResult {
+    let regex = compile_regex(ftx, cache, &pattern)?;
+    let replaced = regex.replace_all(&text, replacement.as_str());
+    Ok(CelValue::String(Arc::new(replaced.into_owned())))
+}
+
+/// Splits a string on every occurrence of a separator. An empty separator splits the string
+/// into its characters. It can also be called as a method, as in `text.split(",")`
+///
+/// ```cel
+/// split("a,b,,c", ",") == ["a", "b", "", "c"]
+/// ```
+fn split(This(text): This<Arc<String>>, separator: Arc<String>) -> ResolveResult {
+    let parts: Vec<CelValue> = if separator.is_empty() {
+        text.chars()
+            .map(|c| CelValue::String(Arc::new(c.to_string())))
+            .collect()
+    } else {
+        text.split(separator.as_str())
+            .map(|part| CelValue::String(Arc::new(part.to_string())))
+            .collect()
+    };
+    Ok(CelValue::List(Arc::new(parts)))
+}
+
+/// Removes leading and trailing whitespace, as defined by Unicode
+///
+/// ```cel
+/// "  hello\n".trim() == "hello"
+/// ```
+fn trim(This(text): This<Arc<String>>) -> ResolveResult {
+    Ok(CelValue::String(Arc::new(text.trim().to_string())))
+}
+
+/// Converts ASCII letters to upper case, leaving other characters unchanged
+///
+/// ```cel
+/// "héllo".upperAscii() == "HéLLO"
+/// ```
+fn upper_ascii(This(text): This<Arc<String>>) -> ResolveResult {
+    Ok(CelValue::String(Arc::new(text.to_ascii_uppercase())))
+}
+
+/// Converts ASCII letters to lower case, leaving other characters unchanged
+///
+/// ```cel
+/// "HÉLLO".lowerAscii() == "hÉllo"
+/// ```
+fn lower_ascii(This(text): This<Arc<String>>) -> ResolveResult {
+    Ok(CelValue::String(Arc::new(text.to_ascii_lowercase())))
+}
+
+/// Replaces every occurrence of a substring. An empty substring matches between every
+/// character, as in the CEL strings extension
+///
+/// ```cel
+/// "a-b-c".replace("-", "+") == "a+b+c"
+/// ```
+fn replace(This(text): This<Arc<String>>, from: Arc<String>, to: Arc<String>) -> ResolveResult {
+    Ok(CelValue::String(Arc::new(
+        text.replace(from.as_str(), to.as_str()),
+    )))
+}
+
+#[cfg(test)]
+#[path = "functions_test.rs"]
+mod test;
@@ -0,0 +1,335 @@
+use super::*;
+use cel::Program;
+
+fn evaluate(source: &str) -> Result<CelValue, cel::ExecutionError> {
+    evaluate_with_regexes(source, &RegexCache::default())
+}
+
+fn evaluate_with_regexes(
+    source: &str,
+    regexes: &RegexCache,
+) -> Result<CelValue, cel::ExecutionError> {
+    let mut context = Context::default();
+    context.add_function("env", env);
+    context.add_function("readFile", read_file);
+    context.add_function("base64Encode", base64_encode);
+    context.add_function("base64Decode", base64_decode);
+    context.add_function("sha256", sha256);
+    context.add_function("keys", keys);
+    context.add_function("values", values);
+    context.add_function("parseJSON", parse_json);
+    context.add_function("toJSON", |value: CelValue| to_json(value, false, None));
+    let cache = regexes.clone();
+    context.add_function(
+        "regexExtract",
+        move |ftx: &FunctionContext, text: Arc<String>, pattern: Arc<String>, group: i64| {
+            regex_extract(ftx, &cache, text, pattern, group)
+        },
+    );
+    let cache = regexes.clone();
+    context.add_function(
+        "regexReplace",
+        move |ftx: &FunctionContext, text: Arc<String>, pattern: Arc<String>, replacement| {
+            regex_replace(ftx, &cache, text, pattern, replacement)
+        },
+    );
+    context.add_function("split", split);
+    context.add_function("uuid", uuid);
+    context.add_function("trim", trim);
+    context.add_function("upperAscii", upper_ascii);
+    context.add_function("lowerAscii", lower_ascii);
+    context.add_function("replace", replace);
+    Program::compile(source).unwrap().execute(&context)
+}
+
+#[test]
+fn test_env_reads_variable() {
+    // SAFETY: no other test reads or writes this variable
+    unsafe { std::env::set_var("CELQ_TEST_ENV_FUNCTION", "eu-west-1") };
+    assert_eq!(
+        evaluate("env('CELQ_TEST_ENV_FUNCTION')").unwrap(),
+        CelValue::String(Arc::new("eu-west-1".to_string()))
+    );
+}
+
+#[test]
+fn test_env_unset_variable_errors() {
+    let err = evaluate("env('CELQ_TEST_UNSET_VARIABLE')").unwrap_err();
+    assert!(err.to_string().contains("CELQ_TEST_UNSET_VARIABLE"));
+}
+
+#[test]
+fn test_base64_encode() {
+    assert_eq!(
+        evaluate("base64Encode('hello')").unwrap(),
+        CelValue::String(Arc::new("aGVsbG8=".to_string()))
+    );
+    assert_eq!(
+        evaluate("base64Encode(b'hello')").unwrap(),
+        CelValue::String(Arc::new("aGVsbG8=".to_string()))
+    );
+    assert!(evaluate("base64Encode(1)").is_err());
+}
+
+#[test]
+fn test_base64_round_trip() {
+    assert_eq!(
+        evaluate("base64Decode(base64Encode(b'\\x00\\xff')) == b'\\x00\\xff'").unwrap(),
+        CelValue::Bool(true)
+    );
+    assert_eq!(
+        evaluate("string(base64Decode('aGVsbG8=')) == 'hello'").unwrap(),
+        CelValue::Bool(true)
+    );
+}
+
+#[test]
+fn test_base64_decode_invalid_input() {
+    let err = evaluate("base64Decode('not base64!')").unwrap_err();
+    assert!(err.to_string().contains("invalid base64"));
+}
+
+#[test]
+fn test_regex_extract_group() {
+    assert_eq!(
+        evaluate("regexExtract('GET /a took 42ms', '(\\\\d+)ms', 1)").unwrap(),
+        CelValue::String(Arc::new("42".to_string()))
+    );
+    assert_eq!(
+        evaluate("regexExtract('no numbers', '(\\\\d+)', 1)").unwrap(),
+        CelValue::Null
+    );
+}
+
+#[test]
+fn test_regex_extract_missing_group_errors() {
+    let err = evaluate("regexExtract('abc', '(a)', 2)").unwrap_err();
+    assert!(err.to_string().contains("no capture group 2"));
+}
+
+#[test]
+fn test_regex_replace() {
+    assert_eq!(
+        evaluate("regexReplace('2024-01-31', '(\\\\d+)-(\\\\d+)-(\\\\d+)', '$3/$2/$1')").unwrap(),
+        CelValue::String(Arc::new("31/01/2024".to_string()))
+    );
+}
+
+#[test]
+fn test_regex_patterns_are_compiled_once() {
+    let regexes = RegexCache::default();
+    for _ in 0..2 {
+        for source in [
+            "regexExtract('a1', '(\\\\d)', 1) == '1'",
+            "regexReplace('a1', '\\\\d', '') == 'a'",
+        ] {
+            assert_eq!(
+                evaluate_with_regexes(source, &regexes).unwrap(),
+                CelValue::Bool(true)
+            );
+        }
+    }
+
+    let mut patterns: Vec<_> = regexes.lock().unwrap().keys().cloned().collect();
+    patterns.sort();
+    assert_eq!(patterns, vec!["(\\d)", "\\d"]);
+}
+
+#[test]
+fn test_invalid_regex_errors() {
+    let err = evaluate("regexReplace('abc', '(', '')").unwrap_err();
+    assert!(err.to_string().contains("invalid regex"));
+}
+
+#[test]
+fn test_split() {
+    assert_eq!(
+        evaluate("split('a,b,,c', ',') == ['a', 'b', '', 'c']").unwrap(),
+        CelValue::Bool(true)
+    );
+    assert_eq!(
+        evaluate("split('a--b', '--') == ['a', 'b']").unwrap(),
+        CelValue::Bool(true)
+    );
+    assert_eq!(
+        evaluate("'x y'.split(' ') == ['x', 'y']").unwrap(),
+        CelValue::Bool(true)
+    );
+}
+
+#[test]
+fn test_split_empty_strings() {
+    // Like Go's strings.Split, an empty text is a single empty field
+    assert_eq!(
+        evaluate("split('', ',') == ['']").unwrap(),
+        CelValue::Bool(true)
+    );
+    assert_eq!(
+        evaluate("split('héy', '') == ['h', 'é', 'y']").unwrap(),
+        CelValue::Bool(true)
+    );
+    assert_eq!(
+        evaluate("split('', '') == []").unwrap(),
+        CelValue::Bool(true)
+    );
+}
+
+#[test]
+fn test_read_file() {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(file.path(), "ada\ngrace\n").unwrap();
+    let source = format!("readFile({:?})", file.path().to_str().unwrap());
+    assert_eq!(
+        evaluate(&source).unwrap(),
+        CelValue::String(Arc::new("ada\ngrace\n".to_string()))
+    );
+}
+
+#[test]
+fn test_read_file_missing_errors() {
+    let err = evaluate("readFile('celq-test-missing-file.txt')").unwrap_err();
+    assert!(err.to_string().contains("celq-test-missing-file.txt"));
+}
+
+#[test]
+fn test_trim() {
+    assert_eq!(
+        evaluate("'  hello\\n\\t'.trim() == 'hello'").unwrap(),
+        CelValue::Bool(true)
+    );
+    assert_eq!(
+        evaluate("trim('\\u00a0a b\\u2003') == 'a b'").unwrap(),
+        CelValue::Bool(true)
+    );
+}
+
+#[test]
+fn test_upper_and_lower_ascii() {
+    assert_eq!(
+        evaluate("'héllo World'.upperAscii() == 'HéLLO WORLD'").unwrap(),
+        CelValue::Bool(true)
+    );
+    assert_eq!(
+        evaluate("lowerAscii('HÉLLO World') == 'hÉllo world'").unwrap(),
+        CelValue::Bool(true)
+    );
+}
+
+#[test]
+fn test_replace() {
+    assert_eq!(
+        evaluate("'hello hello'.replace('he', 'we') == 'wello wello'").unwrap(),
+        CelValue::Bool(true)
+    );
+    assert_eq!(
+        evaluate("replace('ab', '', '-') == '-a-b-'").unwrap(),
+        CelValue::Bool(true)
+    );
+}
+
+#[test]
+fn test_string_helpers_reject_other_types() {
+    assert!(evaluate("trim(1)").is_err());
+    assert!(evaluate("[1].upperAscii()").is_err());
+    assert!(evaluate("lowerAscii(b'A')").is_err());
+    assert!(evaluate("'a'.replace(1, 'b')").is_err());
+}
+
+#[test]
+fn test_uuid_is_fresh_v4() {
+    let CelValue::String(first) = evaluate("uuid()").unwrap() else {
+        panic!("Expected string");
+    };
+    let parsed = Uuid::parse_str(&first).unwrap();
+    assert_eq!(parsed.get_version_num(), 4);
+    assert_eq!(evaluate("uuid() != uuid()").unwrap(), CelValue::Bool(true));
+}
+
+#[test]
+fn test_seeded_uuid_is_deterministic() {
+    let sequence = |seed| {
+        let state = AtomicU64::new(seed);
+        [seeded_uuid(&state).unwrap(), seeded_uuid(&state).unwrap()]
+    };
+    assert_eq!(sequence(7), sequence(7));
+    assert_ne!(sequence(7), sequence(8));
+
+    let [first, second] = sequence(7);
+    assert_ne!(first, second);
+    let CelValue::String(first) = first else {
+        panic!("Expected string");
+    };
+    assert_eq!(Uuid::parse_str(&first).unwrap().get_version_num(), 4);
+}
+
+#[test]
+fn test_sha256() {
+    let abc = CelValue::String(Arc::new(
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".to_string(),
+    ));
+    assert_eq!(evaluate("sha256('abc')").unwrap(), abc);
+    assert_eq!(evaluate("sha256(b'abc')").unwrap(), abc);
+    assert_eq!(
+        evaluate("sha256('')").unwrap(),
+        CelValue::String(Arc::new(
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".to_string()
+        ))
+    );
+    assert!(evaluate("sha256(1)").is_err());
+}
+
+#[test]
+fn test_parse_json() {
+    assert_eq!(
+        evaluate(r#"parseJSON('{"id": 7, "tags": ["a"]}').id"#).unwrap(),
+        CelValue::Int(7)
+    );
+    assert_eq!(
+        evaluate(r#"parseJSON('{"id": 7, "tags": ["a"]}').tags[0]"#).unwrap(),
+        CelValue::String(Arc::new("a".to_string()))
+    );
+    assert_eq!(evaluate("parseJSON('null')").unwrap(), CelValue::Null);
+
+    let err = evaluate("parseJSON('{bad')").unwrap_err();
+    assert!(err.to_string().contains("parseJSON: invalid JSON"));
+}
+
+#[test]
+fn test_to_json() {
+    assert_eq!(
+        evaluate(r#"toJSON({"ids": [1, 2.5, null]})"#).unwrap(),
+        CelValue::String(Arc::new(r#"{"ids":[1,2.5,null]}"#.to_string()))
+    );
+    assert_eq!(
+        evaluate("toJSON('x')").unwrap(),
+        CelValue::String(Arc::new(r#""x""#.to_string()))
+    );
+    assert_eq!(
+        evaluate(r#"parseJSON(toJSON({"a": [1.5, true]})) == {"a": [1.5, true]}"#).unwrap(),
+        CelValue::Bool(true)
+    );
+}
+
+#[test]
+fn test_keys_and_values_are_sorted_by_key() {
+    assert_eq!(
+        evaluate(r#"keys({"b": 1, "c": 3, "a": 2}) == ["a", "b", "c"]"#).unwrap(),
+        CelValue::Bool(true)
+    );
+    assert_eq!(
+        evaluate(r#"values({"b": 1, "c": 3, "a": 2}) == [2, 1, 3]"#).unwrap(),
+        CelValue::Bool(true)
+    );
+    assert_eq!(
+        evaluate("keys({10: 'x', 2: 'y'}) == [2, 10]").unwrap(),
+        CelValue::Bool(true)
+    );
+    assert_eq!(evaluate("size(values({}))").unwrap(), CelValue::Int(0));
+}
+
+#[test]
+fn test_keys_and_values_reject_other_types() {
+    let err = evaluate("keys([1, 2])").unwrap_err();
+    assert!(err.to_string().contains("keys expects a map"));
+    assert!(evaluate("values('a')").is_err());
+}
@@ -1,142 +1,2100 @@
 use anyhow::{Context as AnyhowContext, Result};
-use cel::objects::Value as CelValue;
+use cel::objects::{Key, Value as CelValue};
 use cel::{Context, Program};
 use rayon::prelude::*;
+use regex::Regex;
+use serde::Serialize;
+use serde::de::IgnoredAny;
 use serde_json::Value as JsonValue;
-use std::collections::BTreeMap;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
 use std::io::{self, BufRead, BufReader, Cursor, Read};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::time::{Duration, SystemTime};
 
 use crate::InputParameters;
 use crate::cel_value_to_json_value;
+use crate::cel2json::cel_type_name;
+use crate::cli::{IndexDup, SkippedFields, is_cel_identifier};
+use crate::functions::register_functions;
+use crate::json_color::to_colored_string;
+use crate::json_diff::json_diff;
 use crate::json_to_cel_variables;
+use crate::json2cel::{JsonOptions, find_out_of_range_integer, json_value_to_cel_value};
+use crate::json2csv::{json_to_csv, json_to_tsv};
+use crate::json2sql::json_to_sql_insert;
+use crate::output_handler::{Content, Output};
 
-/// Process input from stdin and execute the CEL program
+/// Callback receiving each output as soon as it is produced
+pub type Emit<'a> = dyn FnMut(Output) -> Result<()> + 'a;
+
+/// Callback receiving each record as soon as it is produced, before it is formatted
+type EmitRecord<'a> = dyn FnMut(Record) -> Result<()> + 'a;
+
+/// A result on its way to the output
+///
+/// Results are only formatted once they are emitted, so everything applied to the output as a
+/// whole, like `--stamp`, sees each record as it is written.
+#[derive(Debug)]
+struct Record {
+    value: RecordValue,
+    is_truthy: bool,
+    /// The CEL type of the result, counted by `--type-summary`, or None for error reports
+    type_name: Option<&'static str>,
+}
+
+#[derive(Debug, Clone)]
+enum RecordValue {
+    /// A result, formatted according to the output flags when it is emitted
+    Json(JsonValue),
+    /// An error or validation report, always emitted as compact JSON
+    Report(JsonValue),
+    /// A result collected into the `--index-by` or `--index-by-line` object under its key
+    Keyed(String, JsonValue),
+    /// A bytes result of `--output-raw-bytes`, emitted as is
+    Bytes(Vec<u8>),
+}
+
+impl Record {
+    fn json(value: JsonValue, is_truthy: bool, type_name: &'static str) -> Self {
+        Record {
+            value: RecordValue::Json(value),
+            is_truthy,
+            type_name: Some(type_name),
+        }
+    }
+
+    fn report(report: JsonValue) -> Self {
+        Record {
+            value: RecordValue::Report(report),
+            is_truthy: false,
+            type_name: None,
+        }
+    }
+
+    fn bytes(bytes: Vec<u8>, is_truthy: bool) -> Self {
+        Record {
+            value: RecordValue::Bytes(bytes),
+            is_truthy,
+            type_name: Some("bytes"),
+        }
+    }
+
+    /// Key a result or report for the `--index-by` or `--index-by-line` object
+    fn keyed(self, key: String) -> Self {
+        let value = match self.value {
+            RecordValue::Json(record) | RecordValue::Report(record) => {
+                RecordValue::Keyed(key, record)
+            }
+            value => value,
+        };
+        Record { value, ..self }
+    }
+}
+
+/// Process input from the input files or stdin and execute the CEL program
+///
+/// # Arguments
+/// * `program` - The compiled CEL program
+/// * `arg_variables` - BTreeMap of variables from CLI arguments
+/// * `input_params` - Input configuration parameters
+/// * `emit` - Callback receiving each output, in order
+///
+/// # Returns
+/// * Ok(()) - All outputs were emitted
+/// * Err(anyhow::Error) - Any error that occurred
+pub fn handle_input(
+    program: &Program,
+    arg_variables: &BTreeMap<String, CelValue>,
+    input_params: &InputParameters,
+    emit: &mut Emit,
+) -> Result<()> {
+    let mut emit_record = |record: Record| {
+        let record = stamp_record(record, input_params);
+        emit(Output {
+            is_truthy: record.is_truthy,
+            type_name: record.type_name,
+            content: format_record(record.value, input_params)?,
+        })
+    };
+
+    // With --index-by every result is buffered, and a single object is emitted at the end
+    if input_params.index_by.is_some() || input_params.index_by_line {
+        let mut index = Index::new(input_params.index_dup);
+        read_input(program, arg_variables, input_params, &mut |record| {
+            index.insert(record)
+        })?;
+        return emit_record(index.finish(input_params));
+    }
+
+    // With --limit only truthy results are emitted, until none are left
+    if let Some(remaining) = &input_params.limit {
+        return read_input(program, arg_variables, input_params, &mut |record| {
+            if !record.is_truthy || remaining.load(AtomicOrdering::Relaxed) == 0 {
+                return Ok(());
+            }
+            remaining.fetch_sub(1, AtomicOrdering::Relaxed);
+            emit_record(record)
+        });
+    }
+
+    read_input(program, arg_variables, input_params, &mut emit_record)
+}
+
+/// Add the current time under the `--stamp` field of a map record, as it is emitted
+fn stamp_record(mut record: Record, input_params: &InputParameters) -> Record {
+    let (Some(field), RecordValue::Json(value)) = (&input_params.stamp, &mut record.value) else {
+        return record;
+    };
+    let JsonValue::Object(map) = value else {
+        return record;
+    };
+
+    // Unlike now(), the stamp is the wall-clock time each record was output at
+    let stamp = chrono::DateTime::<chrono::Utc>::from(SystemTime::now()).to_rfc3339();
+    map.insert(field.clone(), JsonValue::String(stamp));
+    if input_params.sort_keys {
+        sort_keys_recursive(value, input_params.numeric_key_sort);
+    }
+    record
+}
+
+/// Returns true if `--limit` is set and all of its results were emitted
+fn limit_reached(input_params: &InputParameters) -> bool {
+    input_params
+        .limit
+        .as_ref()
+        .is_some_and(|remaining| remaining.load(AtomicOrdering::Relaxed) == 0)
+}
+
+/// Read the input files or stdin and execute the CEL program over them
+///
+/// # Arguments
+/// * `program` - The compiled CEL program
+/// * `arg_variables` - BTreeMap of variables from CLI arguments
+/// * `input_params` - Input configuration parameters
+/// * `emit` - Callback receiving each record, in order
+///
+/// # Returns
+/// * Ok(()) - All outputs were emitted
+/// * Err(anyhow::Error) - Any error that occurred
+fn read_input(
+    program: &Program,
+    arg_variables: &BTreeMap<String, CelValue>,
+    input_params: &InputParameters,
+    emit: &mut EmitRecord,
+) -> Result<()> {
+    if input_params.env_input {
+        let arg_variables = &bind_filename(arg_variables, input_params, None);
+        let env_json = environment_json();
+        run_precheck(arg_variables, input_params, Some(&env_json))?;
+        let results = handle_json(program, arg_variables, input_params, Some(&env_json))?;
+        return results.into_iter().try_for_each(emit);
+    }
+
+    if !input_params.null_input && !input_params.files.is_empty() {
+        return handle_files(program, arg_variables, input_params, emit);
+    }
+
+    let filename = (!input_params.null_input).then_some(STDIN_FILENAME);
+    let arg_variables = &bind_filename(arg_variables, input_params, filename);
+
+    if !input_params.null_input {
+        // Read from stdin
+        let stdin = io::stdin();
+        let mut reader = input_reader(stdin.lock(), input_params.gzip)?;
+        if input_params.args_from_stdin_header {
+            let arg_variables = read_args_header(&mut reader, arg_variables)?;
+            return handle_buffer(program, &arg_variables, input_params, reader, emit);
+        }
+        handle_buffer(program, arg_variables, input_params, reader, emit)
+    } else {
+        // No input from stdin - use empty cursor
+        let empty_cursor = Cursor::new(Vec::<u8>::new());
+        let reader = BufReader::new(empty_cursor);
+        handle_buffer(program, arg_variables, input_params, reader, emit)
+    }
+}
+
+/// Snapshot the environment variables as a JSON object of strings
+///
+/// Names and values that are not valid UTF-8 are converted lossily.
+fn environment_json() -> String {
+    let vars: serde_json::Map<String, JsonValue> = std::env::vars_os()
+        .map(|(name, value)| {
+            (
+                name.to_string_lossy().into_owned(),
+                JsonValue::String(value.to_string_lossy().into_owned()),
+            )
+        })
+        .collect();
+    JsonValue::Object(vars).to_string()
+}
+
+/// Process each input file in order and execute the CEL program
+///
+/// Every file is handled on its own, as if it had been piped to stdin, except that
+/// `--group-by` and `--presort-by` collect the records of all files together.
+/// Files with a `.gz` extension are decompressed even without `--gzip`.
+///
+/// # Arguments
+/// * `program` - The compiled CEL program
+/// * `arg_variables` - BTreeMap of variables from CLI arguments
+/// * `input_params` - Input configuration parameters
+/// * `emit` - Callback receiving each record, in order
+///
+/// # Returns
+/// * Ok(()) - The outputs of all files were emitted
+/// * Err(anyhow::Error) - If a file cannot be opened, or any other error occurred
+fn handle_files(
+    program: &Program,
+    arg_variables: &BTreeMap<String, CelValue>,
+    input_params: &InputParameters,
+    emit: &mut EmitRecord,
+) -> Result<()> {
+    if let Some(group_by) = &input_params.group_by {
+        return handle_grouped_files(program, group_by, arg_variables, input_params, emit);
+    }
+    if let Some(presort_by) = &input_params.presort_by {
+        return handle_presorted_files(program, presort_by, arg_variables, input_params, emit);
+    }
+
+    for path in &input_params.files {
+        if let Some(stop) = &input_params.follow {
+            let filename = path.display().to_string();
+            let arg_variables = bind_filename(arg_variables, input_params, Some(&filename));
+            return handle_follow(program, &arg_variables, input_params, path, stop, emit)
+                .with_context(|| format!("Failed to follow input file `{}`", path.display()));
+        }
+        let file = open_input(path)
+            .with_context(|| format!("Failed to open input file `{}`", path.display()))?;
+        let gzip = input_params.gzip || path.extension().is_some_and(|ext| ext == "gz");
+        let reader = input_reader(file, gzip)?;
+        let filename = path.display().to_string();
+        let arg_variables = bind_filename(arg_variables, input_params, Some(&filename));
+        let mut emitted = false;
+        let mut emit_file = |output| {
+            emitted = true;
+            emit(output)
+        };
+        handle_buffer(
+            program,
+            &arg_variables,
+            input_params,
+            reader,
+            &mut emit_file,
+        )
+        .with_context(|| format!("Failed to process input file `{}`", path.display()))?;
+
+        // --find only emits its result, and the files after it are not read
+        if (input_params.find && emitted) || limit_reached(input_params) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Group the NDJSON records of every input file together, and execute the CEL program once
+/// per group when all files were read
+///
+/// A group may hold records of several files, so groups are evaluated with `filename` bound to
+/// null.
+///
+/// # Arguments
+/// * `program` - The compiled CEL program, reducing a group to its outputs
+/// * `group_by` - The compiled key expression, evaluated once per record
+/// * `arg_variables` - BTreeMap of variables from CLI arguments
+/// * `input_params` - Input configuration parameters
+/// * `emit` - Callback receiving each record, in order
+///
+/// # Returns
+/// * Ok(()) - The outputs of all groups were emitted
+/// * Err(anyhow::Error) - If a file cannot be opened, or any other error occurred
+fn handle_grouped_files(
+    program: &Program,
+    group_by: &Program,
+    arg_variables: &BTreeMap<String, CelValue>,
+    input_params: &InputParameters,
+    emit: &mut EmitRecord,
+) -> Result<()> {
+    let mut groups = Groups::default();
+    for path in &input_params.files {
+        let file = open_input(path)
+            .with_context(|| format!("Failed to open input file `{}`", path.display()))?;
+        let gzip = input_params.gzip || path.extension().is_some_and(|ext| ext == "gz");
+        let reader = input_reader(file, gzip)?;
+        let filename = path.display().to_string();
+        let file_variables = bind_filename(arg_variables, input_params, Some(&filename));
+        read_lines(reader, input_params)
+            .and_then(|lines| groups.insert(group_by, &file_variables, input_params, &lines))
+            .with_context(|| format!("Failed to process input file `{}`", path.display()))?;
+    }
+
+    let arg_variables = bind_filename(arg_variables, input_params, None);
+    let results = groups.finish(program, &arg_variables, input_params)?;
+    results.into_iter().try_for_each(emit)
+}
+
+/// Sort the NDJSON records of every input file together, and execute the CEL program over
+/// them in key order when all files were read
+///
+/// Each record is evaluated with the `filename` of the file it came from. A file whose lines
+/// form a single JSON document is one record.
+///
+/// # Arguments
+/// * `program` - The compiled CEL program
+/// * `presort_by` - The compiled key expression, evaluated once per record
+/// * `arg_variables` - BTreeMap of variables from CLI arguments
+/// * `input_params` - Input configuration parameters
+/// * `emit` - Callback receiving each record, in order
+///
+/// # Returns
+/// * Ok(()) - The outputs of all records were emitted
+/// * Err(anyhow::Error) - If a file cannot be opened, or any other error occurred
+fn handle_presorted_files(
+    program: &Program,
+    presort_by: &Program,
+    arg_variables: &BTreeMap<String, CelValue>,
+    input_params: &InputParameters,
+    emit: &mut EmitRecord,
+) -> Result<()> {
+    let num_threads = thread_count(input_params)?;
+    let mut presorted = Presorted::default();
+    for path in &input_params.files {
+        let file = open_input(path)
+            .with_context(|| format!("Failed to open input file `{}`", path.display()))?;
+        let gzip = input_params.gzip || path.extension().is_some_and(|ext| ext == "gz");
+        let reader = input_reader(file, gzip)?;
+        let filename = path.display().to_string();
+        let file_variables = bind_filename(arg_variables, input_params, Some(&filename));
+        read_lines(reader, input_params)
+            .and_then(|mut lines| {
+                let variables = prepare_lines(&file_variables, input_params, &mut lines)?;
+                let is_document = lines
+                    .last()
+                    .is_some_and(|(_, line)| serde_json::from_str::<IgnoredAny>(line).is_err());
+                if is_document {
+                    lines = vec![(lines[0].0, join_lines(&lines))];
+                }
+                presorted.insert(presort_by, &variables, input_params, lines)
+            })
+            .with_context(|| format!("Failed to process input file `{}`", path.display()))?;
+    }
+
+    let results = if presorted.is_empty() {
+        let arg_variables = bind_filename(arg_variables, input_params, None);
+        handle_no_records(program, &arg_variables, input_params)?
+    } else {
+        presorted.finish(program, input_params, num_threads)?
+    };
+    results.into_iter().try_for_each(emit)
+}
+
+/// Open an input path for reading
+///
+/// Named pipes are opened like regular files, and Unix domain sockets are connected to. Neither
+/// can seek or has a known length, so both are read until their writer closes them.
+fn open_input(path: &Path) -> io::Result<Box<dyn Read>> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        use std::os::unix::net::UnixStream;
+
+        if std::fs::metadata(path)?.file_type().is_socket() {
+            return Ok(Box::new(UnixStream::connect(path)?));
+        }
+    }
+    Ok(Box::new(File::open(path)?))
+}
+
+/// How long `--follow` waits before checking the input file for new lines again
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Execute the CEL program over NDJSON lines as they are appended to the input, like `tail -f`
+///
+/// Reaching the end of the input only waits for more lines, and a trailing line without a
+/// newline is held back until it is complete. Like `tail -F`, a file that was truncated or
+/// replaced, as log rotation does, is reopened and read from the start. Reading stops once
+/// `stop` is set by Ctrl-C.
+///
+/// # Arguments
+/// * `program` - The compiled CEL program
+/// * `arg_variables` - BTreeMap of variables from CLI arguments
+/// * `input_params` - Input configuration parameters
+/// * `path` - The input file to follow
+/// * `stop` - Flag set when no more lines should be read
+/// * `emit` - Callback receiving each record, in order
+///
+/// # Returns
+/// * Ok(()) - Following was stopped, and all outputs were emitted
+/// * Err(anyhow::Error) - Any error that occurred
+fn handle_follow(
+    program: &Program,
+    arg_variables: &BTreeMap<String, CelValue>,
+    input_params: &InputParameters,
+    path: &Path,
+    stop: &AtomicBool,
+    emit: &mut EmitRecord,
+) -> Result<()> {
+    let mut identity = file_identity(path);
+    let mut reader = BufReader::new(open_input(path).context("Failed to open input file")?);
+    let mut position = 0;
+    let mut line = String::new();
+    let mut line_number = 0;
+    let mut prechecked = false;
+
+    while !stop.load(AtomicOrdering::Relaxed) {
+        let bytes = reader
+            .read_line(&mut line)
+            .context("Failed to read lines from input")?;
+        position += bytes as u64;
+        if bytes == 0 && was_rotated(path, identity, position) {
+            identity = file_identity(path);
+            reader = BufReader::new(open_input(path).context("Failed to reopen input file")?);
+            position = 0;
+            line.clear();
+            continue;
+        }
+        if bytes == 0 || !line.ends_with('\n') {
+            std::thread::sleep(FOLLOW_POLL_INTERVAL);
+            continue;
+        }
+
+        line_number += 1;
+        let record = line.trim_end_matches(['\r', '\n']);
+        if !record.trim().is_empty() {
+            if !prechecked {
+                run_precheck(arg_variables, input_params, Some(record))?;
+                prechecked = true;
+            }
+            let result = handle_json(program, arg_variables, input_params, Some(record));
+            let outputs = line_output(result, line_number, record, input_params)?;
+            if emit_outputs(outputs, Some(record), input_params, emit)? {
+                break;
+            }
+        }
+        line.clear();
+    }
+
+    Ok(())
+}
+
+/// The device and inode of a file, which tell apart a file that replaced another at its path
+fn file_identity(path: &Path) -> Option<(u64, u64)> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        std::fs::metadata(path)
+            .ok()
+            .map(|metadata| (metadata.dev(), metadata.ino()))
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+/// Whether a followed input file was truncated below the position read so far, or replaced by
+/// another file at its path
+///
+/// Pipes and sockets have no length, so they are never rotated. While the path is missing, as
+/// between renaming a log and creating the next one, following keeps waiting on the open file.
+fn was_rotated(path: &Path, identity: Option<(u64, u64)>, position: u64) -> bool {
+    std::fs::metadata(path).is_ok_and(|metadata| {
+        metadata.is_file() && (metadata.len() < position || file_identity(path) != identity)
+    })
+}
+
+/// Buffer an input reader, decompressing it first if it is gzipped
+///
+/// # Arguments
+/// * `reader` - The raw input
+/// * `gzip` - Whether the input is gzip-compressed
+///
+/// # Returns
+/// * Ok(BufReader) - Reader over the decompressed input
+/// * Err(anyhow::Error) - If the binary was compiled without gzip support
+fn input_reader<'a, R: Read + 'a>(reader: R, gzip: bool) -> Result<BufReader<Box<dyn Read + 'a>>> {
+    if gzip {
+        // Concatenated gzip members, as produced by appending to a log, are read as one stream
+        #[cfg(feature = "gzip")]
+        return Ok(BufReader::new(Box::new(flate2::read::MultiGzDecoder::new(
+            reader,
+        ))));
+
+        #[cfg(not(feature = "gzip"))]
+        anyhow::bail!("Binary was compiled without gzip support");
+    }
+
+    Ok(BufReader::new(Box::new(reader)))
+}
+
+/// Read the first line of the input as a JSON object of arguments
+///
+/// The fields are merged into the CLI arguments, replacing arguments with the same name.
+/// The rest of the input is left in the reader.
+///
+/// # Arguments
+/// * `reader` - BufReader positioned at the start of the input
+/// * `arg_variables` - BTreeMap of variables from CLI arguments
+///
+/// # Returns
+/// * Ok(BTreeMap) - The CLI arguments merged with the header fields
+/// * Err(anyhow::Error) - If the first line is not a JSON object
+fn read_args_header<R: BufRead>(
+    reader: &mut R,
+    arg_variables: &BTreeMap<String, CelValue>,
+) -> Result<BTreeMap<String, CelValue>> {
+    let mut header = String::new();
+    reader
+        .read_line(&mut header)
+        .context("Failed to read the arguments header from input")?;
+
+    let header: JsonValue =
+        serde_json::from_str(&header).context("Failed to parse arguments header as JSON")?;
+    let JsonValue::Object(fields) = header else {
+        anyhow::bail!("Arguments header must be a JSON object");
+    };
+
+    let mut variables = arg_variables.clone();
+    variables.extend(
+        fields
+            .iter()
+            .map(|(name, value)| (name.clone(), json_value_to_cel_value(value))),
+    );
+    Ok(variables)
+}
+
+/// Name bound to the `filename` variable for records read from stdin
+const STDIN_FILENAME: &str = "<stdin>";
+
+/// Bind the `filename` variable when `--with-filename` is enabled
+///
+/// # Arguments
+/// * `arg_variables` - BTreeMap of variables from CLI arguments
+/// * `input_params` - Input configuration parameters
+/// * `filename` - Name of the input source, or None when there is no input
+///
+/// # Returns
+/// * The argument variables, with `filename` added if requested
+fn bind_filename<'a>(
+    arg_variables: &'a BTreeMap<String, CelValue>,
+    input_params: &InputParameters,
+    filename: Option<&str>,
+) -> Cow<'a, BTreeMap<String, CelValue>> {
+    if !input_params.with_filename {
+        return Cow::Borrowed(arg_variables);
+    }
+
+    let mut variables = arg_variables.clone();
+    let value = match filename {
+        Some(name) => CelValue::String(Arc::new(name.to_string())),
+        None => CelValue::Null,
+    };
+    variables.insert("filename".to_string(), value);
+    Cow::Owned(variables)
+}
+
+/// Process input from a BufReader and execute the CEL program
+///
+/// # Arguments
+/// * `program` - The compiled CEL program
+/// * `arg_variables` - BTreeMap of variables from CLI arguments
+/// * `input_params` - Input configuration parameters
+/// * `reader` - BufReader to read input from
+/// * `emit` - Callback receiving each record, in order
+///
+/// # Returns
+/// * Ok(()) - All outputs were emitted
+/// * Err(anyhow::Error) - Any error that occurred
+fn handle_buffer<R: Read>(
+    program: &Program,
+    arg_variables: &BTreeMap<String, CelValue>,
+    input_params: &InputParameters,
+    reader: BufReader<R>,
+    emit: &mut EmitRecord,
+) -> Result<()> {
+    if input_params.raw_input {
+        return handle_raw_input(program, arg_variables, input_params, reader, emit);
+    }
+
+    if let Some(pattern) = &input_params.grok {
+        return handle_grok(program, arg_variables, input_params, reader, pattern, emit);
+    }
+
+    if input_params.length_prefixed_input {
+        return handle_frames(program, arg_variables, input_params, reader, emit);
+    }
+
+    if input_params.blank_separated {
+        return handle_blank_separated(program, arg_variables, input_params, reader, emit);
+    }
+
+    if input_params.seq {
+        return handle_seq(program, arg_variables, input_params, reader, emit);
+    }
+
+    if input_params.csv_input {
+        return handle_csv(program, arg_variables, input_params, reader, emit);
+    }
+
+    if !input_params.slurp
+        && !input_params.from_json5
+        && !input_params.from_toml
+        && !input_params.from_yaml
+    {
+        let num_threads = thread_count(input_params)?;
+
+        // A single thread evaluates each line as soon as it is read, and batches bound the
+        // memory of parallel runs. Otherwise every line is read up front, trading memory for
+        // throughput, which presorting and grouping require anyway
+        if input_params.presort_by.is_none()
+            && input_params.group_by.is_none()
+            && (num_threads == 1 || input_params.batch_size.is_some() || input_params.find)
+        {
+            let batch_size = match input_params.batch_size {
+                Some(batch_size) if num_threads > 1 => batch_size.get(),
+                _ => 1,
+            };
+            return stream_lines(
+                program,
+                arg_variables,
+                input_params,
+                reader,
+                num_threads,
+                batch_size,
+                emit,
+            );
+        }
+
+        let lines = read_lines(reader, input_params)?;
+
+        if let Some(group_by) = &input_params.group_by {
+            let mut groups = Groups::default();
+            groups.insert(group_by, arg_variables, input_params, &lines)?;
+            let results = groups.finish(program, arg_variables, input_params)?;
+            return results.into_iter().try_for_each(emit);
+        }
+
+        let results =
+            handle_buffered_lines(program, arg_variables, input_params, lines, num_threads)?;
+        results.into_iter().try_for_each(emit)
+    } else {
+        // Read all input as a single document
+        let mut buffer = String::new();
+        for line in reader.lines() {
+            let line = line.context("Failed to read line from input")?;
+            buffer.push_str(&line);
+            buffer.push('\n');
+        }
+
+        // Process the entire buffer as one JSON document
+        run_precheck(arg_variables, input_params, Some(&buffer))?;
+        let results = handle_json(program, arg_variables, input_params, Some(&buffer))?;
+        results.into_iter().try_for_each(emit)
+    }
+}
+
+/// Number of threads evaluating NDJSON lines, as set by `--jobs`
+fn thread_count(input_params: &InputParameters) -> Result<usize> {
+    anyhow::ensure!(
+        input_params.parallelism != 0,
+        "Parallelism level cannot be 0"
+    );
+
+    Ok(if input_params.parallelism == -1 {
+        std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+    } else {
+        input_params.parallelism as usize
+    })
+}
+
+/// Collect all non-empty lines of an input, keeping their line numbers for diagnostics
+fn read_lines<R: Read>(
+    reader: BufReader<R>,
+    input_params: &InputParameters,
+) -> Result<Vec<(usize, String)>> {
+    NumberedLines::new(reader, input_params)
+        .filter(|line| !matches!(line, Ok((_, line)) if line.trim().is_empty()))
+        .collect::<Result<Vec<_>>>()
+        .context("Failed to read lines from input")
+}
+
+/// Execute the CEL program over NDJSON lines that were all read into memory
+///
+/// # Arguments
+/// * `program` - The compiled CEL program
+/// * `arg_variables` - BTreeMap of variables from CLI arguments
+/// * `input_params` - Input configuration parameters
+/// * `lines` - The non-empty input lines, with their line numbers
+/// * `num_threads` - Number of threads for the rayon thread pool
+///
+/// # Returns
+/// * Ok(Vec<Record>) - Vector of outputs and their truthiness
+/// * Err(anyhow::Error) - Any error that occurred
+fn handle_buffered_lines(
+    program: &Program,
+    arg_variables: &BTreeMap<String, CelValue>,
+    input_params: &InputParameters,
+    mut lines: Vec<(usize, String)>,
+    num_threads: usize,
+) -> Result<Vec<Record>> {
+    let arg_variables = &*prepare_lines(arg_variables, input_params, &mut lines)?;
+
+    // If no lines were processed, execute with no input
+    if lines.is_empty() {
+        return handle_no_records(program, arg_variables, input_params);
+    }
+
+    // The lines are NDJSON if the last one is a JSON value on its own, otherwise they are read
+    // as a single document
+    if serde_json::from_str::<IgnoredAny>(&lines[lines.len() - 1].1).is_err() {
+        return handle_whole_document(program, arg_variables, input_params, &lines, num_threads);
+    }
+
+    if let Some(presort_by) = &input_params.presort_by {
+        let mut presorted = Presorted::default();
+        presorted.insert(presort_by, arg_variables, input_params, lines)?;
+        return presorted.finish(program, input_params, num_threads);
+    }
+
+    handle_lines(program, arg_variables, input_params, &lines, num_threads)
+}
+
+/// Take the header line off the lines of an input, and run the precheck on its first record
+///
+/// # Arguments
+/// * `arg_variables` - BTreeMap of variables from CLI arguments
+/// * `input_params` - Input configuration parameters
+/// * `lines` - The non-empty input lines, with their line numbers
+///
+/// # Returns
+/// * Ok(Cow<BTreeMap>) - The argument variables, with the fields of the header line below them
+/// * Err(anyhow::Error) - The header line is not an object, or the precheck failed
+fn prepare_lines<'a>(
+    arg_variables: &'a BTreeMap<String, CelValue>,
+    input_params: &InputParameters,
+    lines: &mut Vec<(usize, String)>,
+) -> Result<Cow<'a, BTreeMap<String, CelValue>>> {
+    // The header line provides defaults for every record, below the CLI arguments
+    let arg_variables = if input_params.header_line && !lines.is_empty() {
+        let (_, header) = lines.remove(0);
+        Cow::Owned(header_line_variables(&header, arg_variables)?)
+    } else {
+        Cow::Borrowed(arg_variables)
+    };
+
+    // The first record is the first line, unless the lines form a single document
+    if let Some((_, first)) = lines.first() {
+        if serde_json::from_str::<IgnoredAny>(first).is_ok() {
+            run_precheck(&arg_variables, input_params, Some(first))?;
+        } else {
+            let document = join_lines(lines);
+            run_precheck(&arg_variables, input_params, Some(&document))?;
+        }
+    }
+
+    Ok(arg_variables)
+}
+
+/// Execute the CEL program once without input, for inputs that hold no record
+fn handle_no_records(
+    program: &Program,
+    arg_variables: &BTreeMap<String, CelValue>,
+    input_params: &InputParameters,
+) -> Result<Vec<Record>> {
+    // There is no line to key a result by, so the index stays empty
+    if input_params.index_by_line {
+        return Ok(vec![]);
+    }
+    run_precheck(arg_variables, input_params, None)?;
+    handle_json(program, arg_variables, input_params, None)
+}
+
+/// NDJSON records collected by `--presort-by`, across every input
+///
+/// Records are evaluated in ascending key order once every input was read, and records with
+/// equal keys keep their input order. Each record is evaluated with the variables of its own
+/// input, such as its `filename`.
+#[derive(Default)]
+struct Presorted {
+    variables: Vec<BTreeMap<String, CelValue>>,
+    records: Vec<(CelValue, usize, (usize, String))>,
+}
+
+impl Presorted {
+    /// Add the records of an input, computing their sort keys
+    ///
+    /// # Arguments
+    /// * `presort_by` - The compiled key expression, evaluated once per record
+    /// * `arg_variables` - BTreeMap of variables of the input
+    /// * `input_params` - Input configuration parameters
+    /// * `lines` - The non-empty input lines, with their line numbers
+    fn insert(
+        &mut self,
+        presort_by: &Program,
+        arg_variables: &BTreeMap<String, CelValue>,
+        input_params: &InputParameters,
+        lines: Vec<(usize, String)>,
+    ) -> Result<()> {
+        let input = self.variables.len();
+        for (line_number, line) in lines {
+            let key = build_context(arg_variables, input_params, Some(&line))
+                .and_then(|context| {
+                    presort_by
+                        .execute(&context)
+                        .context("Failed to execute --presort-by expression")
+                })
+                .with_context(|| format!("Failed to process line {}", line_number))?;
+            self.records.push((key, input, (line_number, line)));
+        }
+        self.variables.push(arg_variables.clone());
+        Ok(())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Execute the CEL program over every record, in key order
+    ///
+    /// # Arguments
+    /// * `program` - The compiled CEL program
+    /// * `input_params` - Input configuration parameters
+    /// * `num_threads` - Number of threads for the rayon thread pool
+    ///
+    /// # Returns
+    /// * Ok(Vec<Record>) - The outputs of every record, in key order
+    /// * Err(anyhow::Error) - A record failed to evaluate
+    fn finish(
+        mut self,
+        program: &Program,
+        input_params: &InputParameters,
+        num_threads: usize,
+    ) -> Result<Vec<Record>> {
+        // The sort is stable, so lines with equal keys keep their input order
+        self.records.sort_by(|(a, ..), (b, ..)| compare_keys(a, b));
+
+        // Consecutive records of the same input are evaluated together
+        let mut outputs = Vec::new();
+        let mut records = self.records.into_iter().peekable();
+        while let Some((_, input, line)) = records.next() {
+            let mut lines = vec![line];
+            while let Some((_, _, line)) = records.next_if(|(_, next, _)| *next == input) {
+                lines.push(line);
+            }
+            let variables = &self.variables[input];
+            outputs.extend(handle_lines(
+                program,
+                variables,
+                input_params,
+                &lines,
+                num_threads,
+            )?);
+        }
+        Ok(outputs)
+    }
+}
+
+/// NDJSON records collected into groups by `--group-by`, across every input
+///
+/// Records are grouped by the result of the `--group-by` key expression, and keys are the same
+/// if their JSON serializations are. Groups are evaluated in ascending key order, and records
+/// keep their input order within a group.
+#[derive(Default)]
+struct Groups {
+    indexes: HashMap<String, usize>,
+    groups: Vec<(CelValue, Vec<CelValue>)>,
+}
+
+impl Groups {
+    /// Add the records of an input to their groups
+    ///
+    /// # Arguments
+    /// * `group_by` - The compiled key expression, evaluated once per record
+    /// * `arg_variables` - BTreeMap of variables from CLI arguments
+    /// * `input_params` - Input configuration parameters
+    /// * `lines` - The non-empty input lines, with their line numbers
+    fn insert(
+        &mut self,
+        group_by: &Program,
+        arg_variables: &BTreeMap<String, CelValue>,
+        input_params: &InputParameters,
+        lines: &[(usize, String)],
+    ) -> Result<()> {
+        for (line_number, line) in lines {
+            let keyed =
+                input_variables(arg_variables, input_params, Some(line)).and_then(|variables| {
+                    let record = variables[&input_params.root_var].clone();
+                    let context = context_from_variables(arg_variables, input_params, variables)?;
+                    let key = group_by
+                        .execute(&context)
+                        .context("Failed to execute --group-by expression")?;
+                    Ok((key, record))
+                });
+            let (key, record) =
+                keyed.with_context(|| format!("Failed to process line {}", line_number))?;
+
+            let serialized = cel_value_to_json_value(&key, false, None).to_string();
+            let index = *self.indexes.entry(serialized).or_insert_with(|| {
+                self.groups.push((key, Vec::new()));
+                self.groups.len() - 1
+            });
+            self.groups[index].1.push(record);
+        }
+        Ok(())
+    }
+
+    /// Execute the CEL program once per group, with the root variable bound to the list of
+    /// records in the group
+    ///
+    /// # Returns
+    /// * Ok(Vec<Record>) - The outputs of every group, in key order
+    /// * Err(anyhow::Error) - A group failed to evaluate
+    fn finish(
+        mut self,
+        program: &Program,
+        arg_variables: &BTreeMap<String, CelValue>,
+        input_params: &InputParameters,
+    ) -> Result<Vec<Record>> {
+        // The sort is stable, so keys that compare equal keep their first appearance order
+        self.groups.sort_by(|(a, _), (b, _)| compare_keys(a, b));
+
+        let mut outputs = Vec::new();
+        for (key, records) in self.groups {
+            let variables = BTreeMap::from([(
+                input_params.root_var.clone(),
+                CelValue::List(Arc::new(records)),
+            )]);
+            let context = context_from_variables(arg_variables, input_params, variables)?;
+            let group_outputs =
+                handle_context(program, &context, input_params).with_context(|| {
+                    format!(
+                        "Failed to process group {}",
+                        cel_value_to_json_value(&key, false, None)
+                    )
+                })?;
+            outputs.extend(group_outputs);
+        }
+        Ok(outputs)
+    }
+}
+
+/// Execute the CEL program over input lines joined into a single JSON document
+///
+/// If the joined lines do not parse either, the input is NDJSON with failing lines. They are
+/// reported one by one when continuing on errors, and otherwise the first failing line is
+/// reported with its line number if the first line is a JSON document on its own.
+///
+/// # Arguments
+/// * `program` - The compiled CEL program
+/// * `arg_variables` - BTreeMap of variables from CLI arguments
+/// * `input_params` - Input configuration parameters
+/// * `lines` - The non-empty input lines, with their line numbers
+/// * `num_threads` - Number of threads for the rayon thread pool
+///
+/// # Returns
+/// * Ok(Vec<Record>) - Vector of outputs and their truthiness
+/// * Err(anyhow::Error) - Any error that occurred
+fn handle_whole_document(
+    program: &Program,
+    arg_variables: &BTreeMap<String, CelValue>,
+    input_params: &InputParameters,
+    lines: &[(usize, String)],
+    num_threads: usize,
+) -> Result<Vec<Record>> {
+    let full_buffer = join_lines(lines);
+    let result = handle_json(program, arg_variables, input_params, Some(&full_buffer));
+
+    match result {
+        // The document is checked and keyed like a line, by the line it starts on
+        Ok(results) => line_output(Ok(results), lines[0].0, &full_buffer, input_params),
+        Err(_)
+            if input_params.continue_on_error
+                || input_params.error_output
+                || serde_json::from_str::<IgnoredAny>(&lines[0].1).is_ok() =>
+        {
+            handle_lines(program, arg_variables, input_params, lines, num_threads)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Emit the outputs of a record, or with `--find` only its first truthy output
+///
+/// # Arguments
+/// * `outputs` - The outputs of the record and their truthiness
+/// * `record` - The record itself, output in place of the result with `--find-input`
+/// * `input_params` - Input configuration parameters
+/// * `emit` - Callback receiving each record, in order
+///
+/// # Returns
+/// * Ok(true) - `--find` found its result, or `--limit` has output all of its results, so no
+///   more records should be read
+/// * Ok(false) - Keep reading records
+/// * Err(anyhow::Error) - Any error that occurred
+fn emit_outputs(
+    outputs: Vec<Record>,
+    record: Option<&str>,
+    input_params: &InputParameters,
+    emit: &mut EmitRecord,
+) -> Result<bool> {
+    if !input_params.find {
+        outputs.into_iter().try_for_each(emit)?;
+        return Ok(limit_reached(input_params));
+    }
+
+    let Some(found) = outputs.into_iter().find(|output| output.is_truthy) else {
+        return Ok(false);
+    };
+    let found = match record {
+        Some(record) if input_params.find_input => {
+            let mut record: JsonValue =
+                serde_json::from_str(record).context("Failed to parse JSON input")?;
+            if input_params.sort_keys {
+                sort_keys_recursive(&mut record, input_params.numeric_key_sort);
+            }
+            Record {
+                value: RecordValue::Json(record),
+                ..found
+            }
+        }
+        _ => found,
+    };
+    emit(found)?;
+    Ok(true)
+}
+
+/// Execute the CEL program over NDJSON lines one batch at a time, emitting the outputs of
+/// each batch before reading the next one
+///
+/// Whether the input is NDJSON is decided from its first non-empty line. If that line is not
+/// a JSON value on its own, the whole input is read as a single JSON document instead.
+///
+/// # Arguments
+/// * `program` - The compiled CEL program
+/// * `arg_variables` - BTreeMap of variables from CLI arguments
+/// * `input_params` - Input configuration parameters
+/// * `reader` - BufReader to read input from
+/// * `num_threads` - Number of threads for the rayon thread pool evaluating each batch
+/// * `batch_size` - Number of lines per batch, where 1 evaluates each line as it is read
+/// * `emit` - Callback receiving each record, in order
+///
+/// # Returns
+/// * Ok(()) - All outputs were emitted
+/// * Err(anyhow::Error) - Any error that occurred
+fn stream_lines<R: Read>(
+    program: &Program,
+    arg_variables: &BTreeMap<String, CelValue>,
+    input_params: &InputParameters,
+    reader: BufReader<R>,
+    num_threads: usize,
+    batch_size: usize,
+    emit: &mut EmitRecord,
+) -> Result<()> {
+    let mut lines = NumberedLines::new(reader, input_params)
+        .filter(|line| !matches!(line, Ok((_, line)) if line.trim().is_empty()));
+    let mut next_line = || {
+        lines
+            .next()
+            .transpose()
+            .context("Failed to read lines from input")
+    };
+
+    let mut first = next_line()?;
+
+    // The header line provides defaults for every record, below the CLI arguments
+    let header_variables;
+    let arg_variables = if input_params.header_line
+        && let Some((_, header)) = first.take()
+    {
+        header_variables = header_line_variables(&header, arg_variables)?;
+        first = next_line()?;
+        &header_variables
+    } else {
+        arg_variables
+    };
+
+    let Some((line_number, line)) = first else {
+        // There is no line to key a result by, so the index stays empty
+        if input_params.index_by_line {
+            return Ok(());
+        }
+        // If no lines were processed, execute with no input
+        run_precheck(arg_variables, input_params, None)?;
+        let results = handle_json(program, arg_variables, input_params, None)?;
+        emit_outputs(results, None, input_params, emit)?;
+        return Ok(());
+    };
+
+    // A first line that is not a JSON value on its own starts a multi-line document
+    if serde_json::from_str::<IgnoredAny>(&line).is_err() {
+        let mut lines = vec![(line_number, line)];
+        while let Some(line) = next_line()? {
+            lines.push(line);
+        }
+        let document = join_lines(&lines);
+        run_precheck(arg_variables, input_params, Some(&document))?;
+        let results =
+            handle_whole_document(program, arg_variables, input_params, &lines, num_threads)?;
+        emit_outputs(results, Some(&document), input_params, emit)?;
+        return Ok(());
+    }
+
+    run_precheck(arg_variables, input_params, Some(&line))?;
+
+    if batch_size == 1 {
+        let mut current = Some((line_number, line));
+        while let Some((line_number, line)) = current {
+            let result = handle_json(program, arg_variables, input_params, Some(&line));
+            let outputs = line_output(result, line_number, &line, input_params)?;
+            if emit_outputs(outputs, Some(&line), input_params, emit)? {
+                break;
+            }
+            current = next_line()?;
+        }
+        return Ok(());
+    }
+
+    let mut batch = vec![(line_number, line)];
+    while !batch.is_empty() {
+        while batch.len() < batch_size
+            && let Some(line) = next_line()?
+        {
+            batch.push(line);
+        }
+
+        let results = handle_lines(program, arg_variables, input_params, &batch, num_threads)?;
+        results.into_iter().try_for_each(&mut *emit)?;
+        if limit_reached(input_params) {
+            break;
+        }
+
+        batch.clear();
+        if let Some(line) = next_line()? {
+            batch.push(line);
+        }
+    }
+
+    Ok(())
+}
+
+/// The lines of an NDJSON input with their line numbers, like `BufRead::lines().enumerate()`
+///
+/// With `--max-line-bytes`, a line over the limit is consumed without being kept in memory.
+/// It fails the run, or is reported on stderr and skipped with `--continue`.
+struct NumberedLines<R> {
+    reader: BufReader<R>,
+    max_bytes: Option<usize>,
+    continue_on_error: bool,
+    line_number: usize,
+}
+
+impl<R: Read> NumberedLines<R> {
+    fn new(reader: BufReader<R>, input_params: &InputParameters) -> Self {
+        NumberedLines {
+            reader,
+            max_bytes: input_params.max_line_bytes.map(|max| max.get()),
+            continue_on_error: input_params.continue_on_error,
+            line_number: 0,
+        }
+    }
+
+    /// Read the next line without its terminator
+    ///
+    /// # Returns
+    /// * Ok(Some(Some(line))) - The next line
+    /// * Ok(Some(None)) - The next line is longer than the limit, and was skipped
+    /// * Ok(None) - The input has no more lines
+    /// * Err(io::Error) - The input could not be read, or the line is not valid UTF-8
+    fn read_line(&mut self) -> io::Result<Option<Option<String>>> {
+        let mut line = Vec::new();
+        let mut too_long = false;
+        let mut read_any = false;
+
+        loop {
+            let available = self.reader.fill_buf()?;
+            if available.is_empty() {
+                break;
+            }
+            read_any = true;
+
+            let newline = available.iter().position(|&b| b == b'\n');
+            let chunk = &available[..newline.unwrap_or(available.len())];
+            if !too_long
+                && self
+                    .max_bytes
+                    .is_some_and(|max| line.len() + chunk.len() > max)
+            {
+                too_long = true;
+                line = Vec::new();
+            }
+            if !too_long {
+                line.extend_from_slice(chunk);
+            }
+
+            let consumed = newline.map_or(chunk.len(), |pos| pos + 1);
+            self.reader.consume(consumed);
+            if newline.is_some() {
+                break;
+            }
+        }
+
+        if !read_any {
+            return Ok(None);
+        }
+        if too_long {
+            return Ok(Some(None));
+        }
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        let line = String::from_utf8(line).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "stream did not contain valid UTF-8",
+            )
+        })?;
+        Ok(Some(Some(line)))
+    }
+}
+
+impl<R: Read> Iterator for NumberedLines<R> {
+    type Item = Result<(usize, String)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.line_number += 1;
+            let line = match self.read_line() {
+                Ok(Some(Some(line))) => line,
+                Ok(Some(None)) => {
+                    let max_bytes = self.max_bytes.unwrap_or_default();
+                    if self.continue_on_error {
+                        eprintln!(
+                            "✗ Line {}: longer than --max-line-bytes ({} bytes), skipped",
+                            self.line_number, max_bytes
+                        );
+                        continue;
+                    }
+                    return Some(Err(anyhow::anyhow!(
+                        "Line {} is longer than --max-line-bytes ({} bytes)",
+                        self.line_number,
+                        max_bytes
+                    )));
+                }
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e.into())),
+            };
+            return Some(Ok((self.line_number, line)));
+        }
+    }
+}
+
+/// Parse the header line of an NLJSON input into default variables
+///
+/// # Arguments
+/// * `header` - The first line of the input, which must be a JSON object
+/// * `arg_variables` - BTreeMap of variables from CLI arguments
+///
+/// # Returns
+/// * Ok(BTreeMap) - The header fields, overridden by the CLI arguments
+/// * Err(anyhow::Error) - If the header is not a JSON object
+fn header_line_variables(
+    header: &str,
+    arg_variables: &BTreeMap<String, CelValue>,
+) -> Result<BTreeMap<String, CelValue>> {
+    let header: JsonValue =
+        serde_json::from_str(header).context("Failed to parse header line as JSON")?;
+    let JsonValue::Object(fields) = header else {
+        anyhow::bail!("Header line must be a JSON object");
+    };
+
+    let mut variables: BTreeMap<String, CelValue> = fields
+        .iter()
+        .map(|(name, value)| (name.clone(), json_value_to_cel_value(value)))
+        .collect();
+    variables.extend(
+        arg_variables
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone())),
+    );
+    Ok(variables)
+}
+
+/// Match each input line against a regex and execute the CEL program over its captures
+///
+/// The named capture groups of a matching line are bound as a map to the root
+/// variable. Groups that did not participate in the match are bound to null.
+/// Lines that do not match the regex are skipped.
+///
+/// # Arguments
+/// * `program` - The compiled CEL program
+/// * `arg_variables` - BTreeMap of variables from CLI arguments
+/// * `input_params` - Input configuration parameters
+/// * `reader` - BufReader to read input from
+/// * `pattern` - Regex with named capture groups
+/// * `emit` - Callback receiving each record, in order
+///
+/// # Returns
+/// * Ok(()) - All outputs were emitted
+/// * Err(anyhow::Error) - Any error that occurred
+fn handle_grok<R: Read>(
+    program: &Program,
+    arg_variables: &BTreeMap<String, CelValue>,
+    input_params: &InputParameters,
+    reader: BufReader<R>,
+    pattern: &str,
+    emit: &mut EmitRecord,
+) -> Result<()> {
+    let regex = Regex::new(pattern).context("Failed to compile --grok regex")?;
+    let names: Vec<&str> = regex.capture_names().flatten().collect();
+
+    for line in reader.lines() {
+        let line = line.context("Failed to read line from input")?;
+        let Some(captures) = regex.captures(&line) else {
+            continue;
+        };
+
+        let fields: HashMap<Key, CelValue> = names
+            .iter()
+            .map(|name| {
+                let value = captures
+                    .name(name)
+                    .map(|m| CelValue::String(Arc::new(m.as_str().to_string())))
+                    .unwrap_or(CelValue::Null);
+                (Key::String(Arc::new(name.to_string())), value)
+            })
+            .collect();
+
+        let mut input_variables = BTreeMap::new();
+        input_variables.insert(input_params.root_var.clone(), CelValue::Map(fields.into()));
+
+        let context = context_from_variables(arg_variables, input_params, input_variables)?;
+        let results = handle_context(program, &context, input_params)?;
+        results.into_iter().try_for_each(&mut *emit)?;
+    }
+
+    Ok(())
+}
+
+/// Execute the CEL program over raw text, without parsing it as JSON
+///
+/// Each line is bound to the root variable as a string, including empty lines. With
+/// `--slurp`, the whole input is bound as a single string instead. Lines that fail to evaluate
+/// follow the same `--continue` and `--error-output` policy as NDJSON lines.
+///
+/// # Arguments
+/// * `program` - The compiled CEL program
+/// * `arg_variables` - BTreeMap of variables from CLI arguments
+/// * `input_params` - Input configuration parameters
+/// * `reader` - BufReader to read input from
+/// * `emit` - Callback receiving each record, in order
+///
+/// # Returns
+/// * Ok(()) - All outputs were emitted
+/// * Err(anyhow::Error) - Any error that occurred
+fn handle_raw_input<R: Read>(
+    program: &Program,
+    arg_variables: &BTreeMap<String, CelValue>,
+    input_params: &InputParameters,
+    mut reader: BufReader<R>,
+    emit: &mut EmitRecord,
+) -> Result<()> {
+    let handle_text = |text: String| {
+        let mut input_variables = BTreeMap::new();
+        input_variables.insert(
+            input_params.root_var.clone(),
+            CelValue::String(Arc::new(text)),
+        );
+        let context = context_from_variables(arg_variables, input_params, input_variables)?;
+        handle_context(program, &context, input_params)
+    };
+
+    if input_params.slurp {
+        let mut text = String::new();
+        reader
+            .read_to_string(&mut text)
+            .context("Failed to read input")?;
+        let results = handle_text(text)?;
+        return results.into_iter().try_for_each(emit);
+    }
+
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line.context("Failed to read line from input")?;
+        let result = handle_text(line.clone());
+        let outputs = line_output(result, idx + 1, &line, input_params)?;
+        outputs.into_iter().try_for_each(&mut *emit)?;
+    }
+
+    Ok(())
+}
+
+/// Execute the CEL program over each row of a CSV input
+///
+/// The first row is the header, and every following row is bound to the root variable as a
+/// map from column name to field. Fields are strings unless `--csv-infer-types` is set.
+///
+/// # Arguments
+/// * `program` - The compiled CEL program
+/// * `arg_variables` - BTreeMap of variables from CLI arguments
+/// * `input_params` - Input configuration parameters
+/// * `reader` - BufReader to read input from
+/// * `emit` - Callback receiving each record, in order
+///
+/// # Returns
+/// * Ok(()) - All outputs were emitted
+/// * Err(anyhow::Error) - If a row is malformed, or any other error occurred
+fn handle_csv<R: Read>(
+    program: &Program,
+    arg_variables: &BTreeMap<String, CelValue>,
+    input_params: &InputParameters,
+    reader: BufReader<R>,
+    emit: &mut EmitRecord,
+) -> Result<()> {
+    #[cfg(feature = "from-csv")]
+    {
+        let mut csv_reader = csv::Reader::from_reader(reader);
+        let headers: Vec<Key> = csv_reader
+            .headers()
+            .context("Failed to read CSV header")?
+            .iter()
+            .map(|name| Key::String(Arc::new(name.to_string())))
+            .collect();
+
+        for record in csv_reader.records() {
+            let record = record.context("Failed to read CSV record")?;
+            let fields: HashMap<Key, CelValue> = headers
+                .iter()
+                .cloned()
+                .zip(
+                    record
+                        .iter()
+                        .map(|field| csv_field_value(field, input_params.csv_infer_types)),
+                )
+                .collect();
+
+            let mut input_variables = BTreeMap::new();
+            input_variables.insert(input_params.root_var.clone(), CelValue::Map(fields.into()));
+
+            let context = context_from_variables(arg_variables, input_params, input_variables)?;
+            let results = handle_context(program, &context, input_params)?;
+            results.into_iter().try_for_each(&mut *emit)?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "from-csv"))]
+    {
+        let _ = (program, arg_variables, input_params, reader, emit);
+        anyhow::bail!("Binary was compiled without CSV support");
+    }
+}
+
+/// Convert a CSV field to a CEL value
+///
+/// With `infer_types`, `true` and `false` become bools, integers become ints, and other finite
+/// numbers become floats. Everything else, including empty fields, stays a string.
+#[cfg(feature = "from-csv")]
+fn csv_field_value(field: &str, infer_types: bool) -> CelValue {
+    if infer_types {
+        if let Ok(b) = field.parse::<bool>() {
+            return CelValue::Bool(b);
+        }
+        if let Ok(i) = field.parse::<i64>() {
+            return CelValue::Int(i);
+        }
+        if let Ok(f) = field.parse::<f64>()
+            && f.is_finite()
+        {
+            return CelValue::Float(f);
+        }
+    }
+    CelValue::String(Arc::new(field.to_string()))
+}
+
+/// Execute the CEL program over JSON documents separated by blank lines
+///
+/// Each document may span several lines, e.g. pretty-printed objects. Consecutive blank
+/// lines are treated as a single separator.
+///
+/// # Arguments
+/// * `program` - The compiled CEL program
+/// * `arg_variables` - BTreeMap of variables from CLI arguments
+/// * `input_params` - Input configuration parameters
+/// * `reader` - BufReader to read input from
+/// * `emit` - Callback receiving each record, in order
+///
+/// # Returns
+/// * Ok(()) - All outputs were emitted
+/// * Err(anyhow::Error) - Any error that occurred, naming the line the failing document starts on
+fn handle_blank_separated<R: Read>(
+    program: &Program,
+    arg_variables: &BTreeMap<String, CelValue>,
+    input_params: &InputParameters,
+    reader: BufReader<R>,
+    emit: &mut EmitRecord,
+) -> Result<()> {
+    let mut document = String::new();
+    let mut first_line = 0;
+
+    let mut is_first = true;
+    let mut handle_document = |document: &str, first_line: usize| -> Result<()> {
+        if is_first {
+            run_precheck(arg_variables, input_params, Some(document))?;
+            is_first = false;
+        }
+        let results = handle_json(program, arg_variables, input_params, Some(document))
+            .with_context(|| {
+                format!(
+                    "Failed to process the document starting at line {}",
+                    first_line
+                )
+            })?;
+        results.into_iter().try_for_each(&mut *emit)
+    };
+
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line.context("Failed to read line from input")?;
+        if line.trim().is_empty() {
+            if !document.is_empty() {
+                handle_document(&document, first_line)?;
+                document.clear();
+            }
+            continue;
+        }
+
+        if document.is_empty() {
+            first_line = idx + 1;
+        }
+        document.push_str(&line);
+        document.push('\n');
+    }
+
+    if !document.is_empty() {
+        handle_document(&document, first_line)?;
+    }
+
+    Ok(())
+}
+
+/// Record separator that starts each record of an RFC 7464 JSON text sequence
+const RECORD_SEPARATOR: u8 = 0x1e;
+
+/// Execute the CEL program over the records of an RFC 7464 JSON text sequence
+///
+/// Each record is a JSON document after an RS character, usually followed by a newline.
+/// Empty records are skipped. Records that fail to parse or evaluate follow the same
+/// `--continue` and `--error-output` policy as NDJSON lines, numbered by record.
+///
+/// # Arguments
+/// * `program` - The compiled CEL program
+/// * `arg_variables` - BTreeMap of variables from CLI arguments
+/// * `input_params` - Input configuration parameters
+/// * `reader` - BufReader to read input from
+/// * `emit` - Callback receiving each record, in order
+///
+/// # Returns
+/// * Ok(()) - All outputs were emitted
+/// * Err(anyhow::Error) - Any error that occurred
+fn handle_seq<R: Read>(
+    program: &Program,
+    arg_variables: &BTreeMap<String, CelValue>,
+    input_params: &InputParameters,
+    mut reader: BufReader<R>,
+    emit: &mut EmitRecord,
+) -> Result<()> {
+    let mut record_number = 0;
+    let mut buffer = Vec::new();
+    loop {
+        buffer.clear();
+        let len = reader
+            .read_until(RECORD_SEPARATOR, &mut buffer)
+            .context("Failed to read record from input")?;
+        if len == 0 {
+            break;
+        }
+        if buffer.last() == Some(&RECORD_SEPARATOR) {
+            buffer.pop();
+        }
+
+        let record = std::str::from_utf8(&buffer)
+            .with_context(|| format!("Record {} is not valid UTF-8", record_number + 1))?
+            .trim();
+        if record.is_empty() {
+            continue;
+        }
+
+        record_number += 1;
+        if record_number == 1 {
+            run_precheck(arg_variables, input_params, Some(record))?;
+        }
+        let result = handle_json(program, arg_variables, input_params, Some(record));
+        let outputs = line_output(result, record_number, record, input_params)?;
+        outputs.into_iter().try_for_each(&mut *emit)?;
+    }
+
+    Ok(())
+}
+
+/// Execute the CEL program over length-prefixed JSON frames
+///
+/// Each frame is a 4-byte big-endian length followed by that many bytes of JSON.
+/// Frames are read until the input ends on a frame boundary.
+///
+/// # Arguments
+/// * `program` - The compiled CEL program
+/// * `arg_variables` - BTreeMap of variables from CLI arguments
+/// * `input_params` - Input configuration parameters
+/// * `reader` - BufReader to read input from
+/// * `emit` - Callback receiving each record, in order
+///
+/// # Returns
+/// * Ok(()) - All outputs were emitted
+/// * Err(anyhow::Error) - If a frame is truncated, or any other error occurred
+fn handle_frames<R: Read>(
+    program: &Program,
+    arg_variables: &BTreeMap<String, CelValue>,
+    input_params: &InputParameters,
+    mut reader: BufReader<R>,
+    emit: &mut EmitRecord,
+) -> Result<()> {
+    for frame_number in 1.. {
+        let mut header = [0u8; 4];
+        let header_len = read_up_to(&mut reader, &mut header)
+            .with_context(|| format!("Failed to read the header of frame {}", frame_number))?;
+        if header_len == 0 {
+            break;
+        }
+        anyhow::ensure!(
+            header_len == header.len(),
+            "Truncated frame {}: expected a 4-byte length, got {} bytes",
+            frame_number,
+            header_len
+        );
+
+        // The length is untrusted, so the payload grows with the bytes actually read instead of
+        // being allocated up front
+        let frame_len = u32::from_be_bytes(header) as usize;
+        let mut payload = Vec::new();
+        let payload_len = reader
+            .by_ref()
+            .take(frame_len as u64)
+            .read_to_end(&mut payload)
+            .with_context(|| format!("Failed to read frame {}", frame_number))?;
+        anyhow::ensure!(
+            payload_len == frame_len,
+            "Truncated frame {}: expected {} bytes, got {}",
+            frame_number,
+            frame_len,
+            payload_len
+        );
+
+        let json_str = String::from_utf8(payload)
+            .with_context(|| format!("Frame {} is not valid UTF-8", frame_number))?;
+        if frame_number == 1 {
+            run_precheck(arg_variables, input_params, Some(&json_str))?;
+        }
+        let results = handle_json(program, arg_variables, input_params, Some(&json_str))?;
+        results.into_iter().try_for_each(&mut *emit)?;
+    }
+
+    Ok(())
+}
+
+/// Fill `buf` from the reader, stopping early only at the end of the input
+///
+/// # Returns
+/// * Ok(usize) - The number of bytes read, less than `buf.len()` only at the end of the input
+/// * Err(io::Error) - Any error other than an interruption
+fn read_up_to<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Execute the CEL program over NDJSON lines in parallel, preserving their order
+///
+/// With `--continue`, a failing line is reported on stderr with its line number
+/// and left out of the outputs. With `--error-output`, it is replaced by an error
+/// object in the outputs instead. Failures are handled after the parallel pass,
+/// so they come out in input order.
+///
+/// # Arguments
+/// * `program` - The compiled CEL program
+/// * `arg_variables` - BTreeMap of variables from CLI arguments
+/// * `input_params` - Input configuration parameters
+/// * `lines` - The NDJSON lines to process, with their line numbers
+/// * `num_threads` - Number of threads for the rayon thread pool
+///
+/// # Returns
+/// * Ok(Vec<Record>) - Vector of outputs and their truthiness
+/// * Err(anyhow::Error) - The error of the first failing line, unless continuing on errors
+fn handle_lines(
+    program: &Program,
+    arg_variables: &BTreeMap<String, CelValue>,
+    input_params: &InputParameters,
+    lines: &[(usize, String)],
+    num_threads: usize,
+) -> Result<Vec<Record>> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(num_threads)
+        .build()
+        .context("Failed to build thread pool")?;
+
+    let results: Vec<Result<Vec<Record>>> = pool.install(|| {
+        lines
+            .par_iter()
+            .map(|(_, line)| handle_json(program, arg_variables, input_params, Some(line)))
+            .collect()
+    });
+
+    let mut outputs = Vec::with_capacity(results.len());
+    for ((line_number, line), result) in lines.iter().zip(results) {
+        outputs.extend(line_output(result, *line_number, line, input_params)?);
+    }
+    Ok(outputs)
+}
+
+/// Apply `--continue` and `--error-output` to the result of a single NDJSON line
+///
+/// # Arguments
+/// * `result` - The result of executing the CEL program over the line
+/// * `line_number` - The line number in the input
+/// * `line` - The line itself
+/// * `input_params` - Input configuration parameters
+///
+/// # Returns
+/// * Ok(Vec<Record>) - The outputs of the line, its error object, or
+///   nothing if the line failed and is skipped
+/// * Err(anyhow::Error) - The line failed and errors are not tolerated, with its line number
+///   as context
+fn line_output(
+    result: Result<Vec<Record>>,
+    line_number: usize,
+    line: &str,
+    input_params: &InputParameters,
+) -> Result<Vec<Record>> {
+    match result {
+        Ok(outputs) => {
+            if input_params.validate {
+                return Ok(validation_failure(&outputs, line_number, line));
+            }
+            Ok(key_by_line(outputs, line_number, input_params))
+        }
+        Err(e) if input_params.error_output => {
+            let error = serde_json::json!({
+                "error": format!("{:#}", e),
+                "line": line_number,
+                "input": line,
+            });
+            Ok(key_by_line(
+                vec![Record::report(error)],
+                line_number,
+                input_params,
+            ))
+        }
+        Err(e) if input_params.continue_on_error => {
+            eprintln!("✗ Line {}: {:#}", line_number, e);
+            Ok(vec![])
+        }
+        Err(e) => Err(e.context(format!("Failed to process line {}", line_number))),
+    }
+}
+
+/// Replace the outputs of a line with a falsy `{"line": ..., "input": ...}` object if any of
+/// them is falsy, or with nothing if the line passed `--validate`
+fn validation_failure(outputs: &[Record], line_number: usize, line: &str) -> Vec<Record> {
+    if outputs.iter().all(|output| output.is_truthy) {
+        return vec![];
+    }
+    let failure = serde_json::json!({
+        "line": line_number,
+        "input": line,
+    });
+    vec![Record::report(failure)]
+}
+
+/// Serialize an error or validation report as compact JSON, whatever the output format, with
+/// non-ASCII characters escaped under `--ascii-output` like results are
+fn format_report(report: &JsonValue, input_params: &InputParameters) -> Result<String> {
+    let output = serde_json::to_string(report).context("Failed to serialize report to JSON")?;
+    if input_params.ascii_output {
+        return Ok(escape_non_ascii(&output));
+    }
+    Ok(output)
+}
+
+/// Key the records of a line by its line number when `--index-by-line` is set
+fn key_by_line(
+    outputs: Vec<Record>,
+    line_number: usize,
+    input_params: &InputParameters,
+) -> Vec<Record> {
+    if !input_params.index_by_line {
+        return outputs;
+    }
+
+    outputs
+        .into_iter()
+        .map(|output| output.keyed(line_number.to_string()))
+        .collect()
+}
+
+/// Join input lines back into a single document
+fn join_lines(lines: &[(usize, String)]) -> String {
+    lines
+        .iter()
+        .map(|(_, line)| line.as_str())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Run the `--precheck` expression against the first record of an input, if one was given
 ///
 /// # Arguments
-/// * `program` - The compiled CEL program
 /// * `arg_variables` - BTreeMap of variables from CLI arguments
 /// * `input_params` - Input configuration parameters
+/// * `json_str` - The first record, or None if there is no input
 ///
 /// # Returns
-/// * Ok(Vec<(output_string, is_truthy)>) - Vector of outputs and their truthiness
-/// * Err(anyhow::Error) - Any error that occurred
-pub fn handle_input(
-    program: &Program,
+/// * Ok(()) - There is no precheck, or it returned true
+/// * Err(anyhow::Error) - The precheck returned false, did not return a bool, or failed
+fn run_precheck(
     arg_variables: &BTreeMap<String, CelValue>,
     input_params: &InputParameters,
-) -> Result<Vec<(String, bool)>> {
-    if !input_params.null_input {
-        // Read from stdin
-        let stdin = io::stdin();
-        let reader = BufReader::new(stdin.lock());
-        handle_buffer(program, arg_variables, input_params, reader)
-    } else {
-        // No input from stdin - use empty cursor
-        let empty_cursor = Cursor::new(Vec::<u8>::new());
-        let reader = BufReader::new(empty_cursor);
-        handle_buffer(program, arg_variables, input_params, reader)
+    json_str: Option<&str>,
+) -> Result<()> {
+    let Some(precheck) = &input_params.precheck else {
+        return Ok(());
+    };
+
+    let context = build_context(arg_variables, input_params, json_str)?;
+    let result = precheck
+        .execute(&context)
+        .context("Failed to execute --precheck expression")?;
+
+    match result {
+        CelValue::Bool(true) => Ok(()),
+        CelValue::Bool(false) => {
+            anyhow::bail!("--precheck failed on the first record")
+        }
+        other => anyhow::bail!(
+            "--precheck must return a bool, got {}",
+            cel_type_name(&other)
+        ),
     }
 }
 
-/// Process input from a BufReader and execute the CEL program
+/// Total order over CEL values used as sort keys
+///
+/// Numbers compare by value regardless of their CEL type. Values of different
+/// types are ordered by type: null, bool, numbers, string, bytes, then the rest.
+fn compare_keys(a: &CelValue, b: &CelValue) -> Ordering {
+    fn type_rank(value: &CelValue) -> u8 {
+        match value {
+            CelValue::Null => 0,
+            CelValue::Bool(_) => 1,
+            CelValue::Int(_) | CelValue::UInt(_) | CelValue::Float(_) => 2,
+            CelValue::String(_) => 3,
+            CelValue::Bytes(_) => 4,
+            _ => 5,
+        }
+    }
+
+    match (a, b) {
+        (CelValue::Bool(x), CelValue::Bool(y)) => x.cmp(y),
+        (CelValue::String(x), CelValue::String(y)) => x.cmp(y),
+        (CelValue::Bytes(x), CelValue::Bytes(y)) => x.cmp(y),
+        (CelValue::Int(x), CelValue::Int(y)) => x.cmp(y),
+        (CelValue::UInt(x), CelValue::UInt(y)) => x.cmp(y),
+        (CelValue::Int(x), CelValue::UInt(y)) => i128::from(*x).cmp(&i128::from(*y)),
+        (CelValue::UInt(x), CelValue::Int(y)) => i128::from(*x).cmp(&i128::from(*y)),
+        (CelValue::Float(x), CelValue::Float(y)) => x.total_cmp(y),
+        (CelValue::Float(x), CelValue::Int(y)) => x.total_cmp(&(*y as f64)),
+        (CelValue::Float(x), CelValue::UInt(y)) => x.total_cmp(&(*y as f64)),
+        (CelValue::Int(x), CelValue::Float(y)) => (*x as f64).total_cmp(y),
+        (CelValue::UInt(x), CelValue::Float(y)) => (*x as f64).total_cmp(y),
+        _ => type_rank(a).cmp(&type_rank(b)),
+    }
+}
+
+/// Build the CEL context for a single document
 ///
 /// # Arguments
-/// * `program` - The compiled CEL program
 /// * `arg_variables` - BTreeMap of variables from CLI arguments
 /// * `input_params` - Input configuration parameters
-/// * `reader` - BufReader to read input from
+/// * `json_str` - Optional JSON string to bind to the root variable
 ///
 /// # Returns
-/// * Ok(Vec<(output_string, is_truthy)>) - Vector of outputs and their truthiness
+/// * Ok(Context) - The context with the argument and JSON variables
 /// * Err(anyhow::Error) - Any error that occurred
-fn handle_buffer<R: Read>(
-    program: &Program,
+fn build_context(
     arg_variables: &BTreeMap<String, CelValue>,
     input_params: &InputParameters,
-    reader: BufReader<R>,
-) -> Result<Vec<(String, bool)>> {
-    if !input_params.slurp
-        && !input_params.from_json5
-        && !input_params.from_toml
-        && !input_params.from_yaml
+    json_str: Option<&str>,
+) -> Result<Context<'static>> {
+    let json_variables = input_variables(arg_variables, input_params, json_str)?;
+    context_from_variables(arg_variables, input_params, json_variables)
+}
+
+/// Parse an input document into the variables it binds
+///
+/// # Arguments
+/// * `arg_variables` - BTreeMap of variables from CLI arguments, which `--expose-fields` skips
+/// * `input_params` - Input configuration parameters
+/// * `json_str` - Optional JSON string to bind to the root variable
+///
+/// # Returns
+/// * Ok(BTreeMap) - The root variable, and any fields bound next to it
+/// * Err(anyhow::Error) - If the input does not parse
+fn input_variables(
+    arg_variables: &BTreeMap<String, CelValue>,
+    input_params: &InputParameters,
+    json_str: Option<&str>,
+) -> Result<BTreeMap<String, CelValue>> {
+    if input_params.bignum_error
+        && let Some(integer) = json_str.and_then(find_out_of_range_integer)
     {
-        // Determine thread pool size
-        anyhow::ensure!(
-            input_params.parallelism != 0,
-            "Parallelism level cannot be 0"
+        anyhow::bail!(
+            "JSON integer {} does not fit in an int or uint, and would lose precision as a float",
+            integer
         );
+    }
 
-        let num_threads = if input_params.parallelism == -1 {
-            std::thread::available_parallelism()
-                .map(|n| n.get())
-                .unwrap_or(1)
-        } else {
-            input_params.parallelism as usize
-        };
+    // If we have input, parse it as JSON
+    let mut json_variables = match json_str {
+        Some(json) => {
+            let options = JsonOptions {
+                root_var: &input_params.root_var,
+                slurp: input_params.slurp,
+                from_json5: input_params.from_json5,
+                from_toml: input_params.from_toml,
+                from_yaml: input_params.from_yaml,
+                number_mode: input_params.number_mode,
+                expand_only: &input_params.expand_only,
+                expose_fields: input_params.expose_fields.is_some(),
+            };
+            json_to_cel_variables(json, &options).context("Failed to parse JSON input")?
+        }
+        // Without input the root variable is null, so expressions referencing it still evaluate
+        None => BTreeMap::from([(input_params.root_var.clone(), CelValue::Null)]),
+    };
 
-        // Collect all non-empty lines first
-        let lines: Vec<String> = reader
-            .lines()
-            .collect::<std::io::Result<Vec<_>>>()
-            .context("Failed to read lines from input")?
-            .into_iter()
-            .filter(|line| !line.trim().is_empty())
-            .collect();
+    // Exposed fields that are not valid identifiers would be unreachable, and fields named like
+    // an argument would shadow it
+    if let Some(skipped) = &input_params.expose_fields {
+        json_variables.retain(|field, _| {
+            if *field == input_params.root_var {
+                true
+            } else if !is_cel_identifier(field) {
+                warn_skipped_field(skipped, field, "is not a valid CEL identifier");
+                false
+            } else if arg_variables.contains_key(field) {
+                warn_skipped_field(skipped, field, "is also the name of an argument");
+                false
+            } else {
+                true
+            }
+        });
+    }
 
-        // If no lines were processed, execute with no input
-        if lines.is_empty() {
-            let result = handle_json(program, arg_variables, input_params, None)?;
-            return Ok(vec![result]);
-        }
+    Ok(json_variables)
+}
 
-        // Try to process the last line
-        let last_idx = lines.len() - 1;
-        let last_result = handle_json(program, arg_variables, input_params, Some(&lines[last_idx]));
+/// Warn that a top-level field is not bound as a variable, once per field name
+///
+/// NDJSON records usually share their fields, so repeating the warning for every record
+/// would only bury the output.
+fn warn_skipped_field(skipped: &SkippedFields, field: &str, reason: &str) {
+    let mut skipped = skipped.lock().unwrap_or_else(|e| e.into_inner());
+    if skipped.insert(field.to_string()) {
+        eprintln!(
+            "Warning: field `{}` {}, so it is not bound as a variable",
+            field, reason
+        );
+    }
+}
 
-        match last_result {
-            Ok(last_output) => {
-                // Last line succeeded, process remaining lines in parallel
-                if lines.len() == 1 {
-                    return Ok(vec![last_output]);
-                }
+/// Build the CEL context from argument variables and input variables
+///
+/// # Arguments
+/// * `arg_variables` - BTreeMap of variables from CLI arguments
+/// * `input_params` - Input configuration parameters
+/// * `input_variables` - BTreeMap of variables from the input document
+///
+/// # Returns
+/// * Ok(Context) - The context with all variables, input variables win on conflicts
+/// * Err(anyhow::Error) - Any error that occurred
+fn context_from_variables(
+    arg_variables: &BTreeMap<String, CelValue>,
+    input_params: &InputParameters,
+    input_variables: BTreeMap<String, CelValue>,
+) -> Result<Context<'static>> {
+    // Create context with default values and celq's own functions
+    let mut context = Context::default();
+    register_functions(&mut context, input_params);
 
-                let remaining_results: Result<Vec<_>> = rayon::ThreadPoolBuilder::new()
-                    .num_threads(num_threads)
-                    .build()
-                    .context("Failed to build thread pool")?
-                    .install(|| {
-                        lines[..last_idx]
-                            .par_iter()
-                            .map(|line| {
-                                handle_json(program, arg_variables, input_params, Some(line))
-                            })
-                            .collect()
-                    });
-
-                let mut results = remaining_results?;
-                results.push(last_output);
-                Ok(results)
-            }
-            Err(_) => {
-                // Last line failed, try reading entire input as single JSON document
-                let full_buffer = lines.join("\n");
-                let result = handle_json(program, arg_variables, input_params, Some(&full_buffer))?;
-                Ok(vec![result])
-            }
+    // Bind the referenced variables that nothing else binds to null, with --null-for-missing
+    for name in &input_params.null_variables {
+        if !arg_variables.contains_key(name) && !input_variables.contains_key(name) {
+            context
+                .add_variable(name.clone(), CelValue::Null)
+                .with_context(|| format!("Failed to add variable '{}'", name))?;
         }
-    } else {
-        // Read all input as a single document
-        let mut buffer = String::new();
-        for line in reader.lines() {
-            let line = line.context("Failed to read line from input")?;
-            buffer.push_str(&line);
-            buffer.push('\n');
+    }
+
+    // Add argument variables to context
+    for (name, value) in arg_variables {
+        context
+            .add_variable(name.clone(), value.clone())
+            .with_context(|| format!("Failed to add variable '{}'", name))?;
+    }
+
+    // Add input variables to context
+    for (name, mut value) in input_variables {
+        if input_params.merge_args_into_root && name == input_params.root_var {
+            value = merge_args_into_root(value, arg_variables);
         }
+        context
+            .add_variable(name.clone(), value)
+            .with_context(|| format!("Failed to add JSON variable '{}'", name))?;
+    }
 
-        // Process the entire buffer as one JSON document
-        let result = handle_json(program, arg_variables, input_params, Some(&buffer))?;
-        Ok(vec![result])
+    Ok(context)
+}
+
+/// Insert the argument variables as keys of a map root, keeping the input's fields on conflicts
+///
+/// Roots that are not maps are returned unchanged.
+fn merge_args_into_root(root: CelValue, arg_variables: &BTreeMap<String, CelValue>) -> CelValue {
+    let CelValue::Map(map) = root else {
+        return root;
+    };
+
+    let mut merged = (*map.map).clone();
+    for (name, value) in arg_variables {
+        merged
+            .entry(Key::String(Arc::new(name.clone())))
+            .or_insert_with(|| value.clone());
     }
+    CelValue::Map(merged.into())
 }
 
 /// Execute the CEL program with given JSON input and argument variables
@@ -148,72 +2106,405 @@ fn handle_buffer<R: Read>(
 /// * `json_str` - Optional JSON string to process
 ///
 /// # Returns
-/// * Ok((output_string, is_truthy)) - The output and whether it's truthy
+/// * Ok(Vec<Record>) - The outputs and whether they're truthy, one per
+///   result unless `--explode-keys` expands it
 /// * Err(anyhow::Error) - Any error that occurred
 fn handle_json(
     program: &Program,
     arg_variables: &BTreeMap<String, CelValue>,
     input_params: &InputParameters,
     json_str: Option<&str>,
-) -> Result<(String, bool)> {
-    // Create context with default values
-    let mut context = Context::default();
+) -> Result<Vec<Record>> {
+    let context = build_context(arg_variables, input_params, json_str)?;
+    handle_context(program, &context, input_params)
+}
 
-    // Add argument variables to context
-    for (name, value) in arg_variables {
-        context
-            .add_variable(name.clone(), value.clone())
-            .with_context(|| format!("Failed to add variable '{}'", name))?;
+/// Execute the CEL program within a prepared context and serialize its result
+///
+/// # Arguments
+/// * `program` - The compiled CEL program
+/// * `context` - The context with all variables
+/// * `input_params` - Input configuration parameters
+///
+/// # Returns
+/// * Ok(Vec<Record>) - The outputs and whether they're truthy, one per
+///   result unless `--explode-keys` expands it
+/// * Err(anyhow::Error) - Any error that occurred
+fn handle_context(
+    program: &Program,
+    context: &Context,
+    input_params: &InputParameters,
+) -> Result<Vec<Record>> {
+    let records = evaluate_context(program, context, input_params)?;
+    if input_params.verbose {
+        echo_records(context, &records, input_params)?;
     }
+    Ok(records)
+}
 
-    // If we have input, parse it as JSON and add to context
-    if let Some(json) = json_str {
-        let json_variables = json_to_cel_variables(
-            json,
-            &input_params.root_var,
-            input_params.slurp,
-            input_params.from_json5,
-            input_params.from_toml,
-            input_params.from_yaml,
-        )
-        .context("Failed to parse JSON input")?;
-
-        // Add JSON variables to context
-        for (name, value) in json_variables {
-            context
-                .add_variable(name.clone(), value)
-                .with_context(|| format!("Failed to add JSON variable '{}'", name))?;
-        }
+/// Print the input and each output of an evaluation to stderr as `input => output`, for
+/// `--verbose`
+///
+/// Every input format is evaluated here, so the input is echoed as the compact JSON of the
+/// root variable rather than as it was read.
+fn echo_records(
+    context: &Context,
+    records: &[Record],
+    input_params: &InputParameters,
+) -> Result<()> {
+    let input = context
+        .get_variable(&input_params.root_var)
+        .map(|value| {
+            cel_value_to_json_value(
+                &value,
+                input_params.iso_durations,
+                input_params.float_precision,
+            )
+        })
+        .unwrap_or(JsonValue::Null);
+    for record in records {
+        let output = format_record(record.value.clone(), input_params)?;
+        eprintln!("{} => {}", input, output);
     }
+    Ok(())
+}
 
+/// Execute the CEL program within a prepared context and convert its result to records
+fn evaluate_context(
+    program: &Program,
+    context: &Context,
+    input_params: &InputParameters,
+) -> Result<Vec<Record>> {
     // Execute the program
-    let result = program
-        .execute(&context)
+    let mut result = program
+        .execute(context)
         .context("Failed to execute CEL program")?;
 
+    // Each --expr stage after the first reads the previous result as the root variable
+    for (idx, stage) in input_params.stages.iter().enumerate() {
+        let mut scope = context.new_inner_scope();
+        scope.add_variable_from_value(input_params.root_var.clone(), result);
+        result = stage
+            .execute(&scope)
+            .with_context(|| format!("Failed to execute --expr stage {}", idx + 2))?;
+    }
+
+    // Null results are filled with the default output, which then counts as the result
+    let result = match (&input_params.default_output, result) {
+        (Some(default_output), CelValue::Null) => json_value_to_cel_value(default_output),
+        (_, result) => result,
+    };
+
     // Determine if the result is truthy
     let is_truthy = is_cel_value_truthy(&result);
+    let type_name = cel_type_name(&result);
+
+    // Raw bytes are output as they are, so none of the JSON steps apply
+    if input_params.output_raw_bytes {
+        let CelValue::Bytes(bytes) = &result else {
+            anyhow::bail!(
+                "--output-raw-bytes expects a bytes result, got {}",
+                type_name
+            );
+        };
+        return Ok(vec![Record::bytes(bytes.to_vec(), is_truthy)]);
+    }
+
+    // The type name replaces the result, which still decides the truthiness
+    if input_params.type_of {
+        let type_of = JsonValue::String(type_name.to_string());
+        return Ok(vec![Record::json(type_of, is_truthy, type_name)]);
+    }
 
     // Convert result to JSON string
-    let mut json_value = cel_value_to_json_value(&result);
+    let mut json_value = cel_value_to_json_value(
+        &result,
+        input_params.iso_durations,
+        input_params.float_precision,
+    );
+
+    // Strings are expanded first, so every later step sees the parsed value
+    if input_params.fromjson
+        && let JsonValue::String(s) = &json_value
+    {
+        json_value =
+            serde_json::from_str(s).context("Failed to parse --fromjson result as JSON")?;
+    }
+
+    if input_params.diff {
+        json_value = diff_against_input(context, input_params, &json_value)?;
+    }
 
     if input_params.sort_keys {
-        sort_keys_recursive(&mut json_value);
+        sort_keys_recursive(&mut json_value, input_params.numeric_key_sort);
     }
 
-    let output_string = if let serde_json::Value::String(s) = &json_value {
-        if input_params.raw_output {
-            s.clone()
-        } else {
-            serde_json::to_string(&json_value).context("Failed to serialize result to JSON")?
+    if let Some(max_chars) = input_params.truncate_strings {
+        truncate_strings_recursive(&mut json_value, max_chars, &input_params.truncate_marker);
+    }
+
+    // Exploded records are non-empty maps, so they share the truthiness of the result, while
+    // flattened elements each have their own truthiness and type
+    let records: Vec<Record> = match json_value {
+        JsonValue::Object(map) if input_params.explode_keys => explode_keys(map)
+            .into_iter()
+            .map(|record| Record::json(record, is_truthy, type_name))
+            .collect(),
+        JsonValue::Array(items) if input_params.flatten => {
+            let mut elements = Vec::new();
+            flatten_into(items, &mut elements);
+            elements
+                .into_iter()
+                .map(|element| {
+                    let is_truthy = is_json_value_truthy(&element);
+                    let type_name = json_type_name(&element);
+                    Record::json(element, is_truthy, type_name)
+                })
+                .collect()
+        }
+        json_value => vec![Record::json(json_value, is_truthy, type_name)],
+    };
+
+    // Indexed records are collected under their key and formatted once at the end
+    if let Some(index_by) = &input_params.index_by {
+        let key = index_key(index_by, context)?;
+        return Ok(records
+            .into_iter()
+            .map(|record| record.keyed(key.clone()))
+            .collect());
+    }
+
+    // Records are formatted as they are emitted, and keyed by line number once it is known,
+    // see `key_by_line`
+    Ok(records)
+}
+
+/// Name of the CEL type a JSON value converts to, like `cel_type_name`
+fn json_type_name(value: &JsonValue) -> &'static str {
+    match value {
+        JsonValue::Null => "null_type",
+        JsonValue::Bool(_) => "bool",
+        JsonValue::Number(n) if n.is_i64() => "int",
+        JsonValue::Number(n) if n.is_u64() => "uint",
+        JsonValue::Number(_) => "double",
+        JsonValue::String(_) => "string",
+        JsonValue::Array(_) => "list",
+        JsonValue::Object(_) => "map",
+    }
+}
+
+/// Compute the `--index-by` key of a record
+///
+/// # Arguments
+/// * `index_by` - The compiled key expression
+/// * `context` - The context of the record
+///
+/// # Returns
+/// * Ok(String) - The key, with numbers and bools converted to strings
+/// * Err(anyhow::Error) - If the key is of any other type, or the expression failed
+fn index_key(index_by: &Program, context: &Context) -> Result<String> {
+    let key = index_by
+        .execute(context)
+        .context("Failed to execute --index-by expression")?;
+
+    match key {
+        CelValue::String(s) => Ok(s.to_string()),
+        CelValue::Int(i) => Ok(i.to_string()),
+        CelValue::UInt(u) => Ok(u.to_string()),
+        CelValue::Float(f) => Ok(f.to_string()),
+        CelValue::Bool(b) => Ok(b.to_string()),
+        other => anyhow::bail!(
+            "--index-by keys must be strings, numbers, or bools, got {}",
+            cel_type_name(&other)
+        ),
+    }
+}
+
+/// Results collected into a single object by `--index-by`
+struct Index {
+    records: serde_json::Map<String, JsonValue>,
+    duplicates: IndexDup,
+}
+
+impl Index {
+    fn new(duplicates: IndexDup) -> Self {
+        Index {
+            records: serde_json::Map::new(),
+            duplicates,
+        }
+    }
+
+    /// Add a record keyed by `handle_context` or `key_by_line`
+    fn insert(&mut self, record: Record) -> Result<()> {
+        let RecordValue::Keyed(key, record) = record.value else {
+            anyhow::bail!("--index-by-line requires NDJSON input");
+        };
+
+        match self.duplicates {
+            IndexDup::First => {
+                self.records.entry(key).or_insert(record);
+            }
+            IndexDup::Last => {
+                self.records.insert(key, record);
+            }
+            IndexDup::Error => {
+                anyhow::ensure!(
+                    !self.records.contains_key(&key),
+                    "Duplicate --index-by key '{}'",
+                    key
+                );
+                self.records.insert(key, record);
+            }
         }
-    } else if input_params.pretty_print {
-        serde_json::to_string_pretty(&json_value).context("Failed to serialize result to JSON")?
+        Ok(())
+    }
+
+    /// The collected object, which is truthy unless it is empty
+    fn finish(self, input_params: &InputParameters) -> Record {
+        let is_truthy = !self.records.is_empty();
+        let mut index = JsonValue::Object(self.records);
+        if input_params.sort_keys {
+            sort_keys_recursive(&mut index, input_params.numeric_key_sort);
+        }
+        Record::json(index, is_truthy, "map")
+    }
+}
+
+/// Append the elements of a list to `elements`, descending into nested lists
+fn flatten_into(items: Vec<JsonValue>, elements: &mut Vec<JsonValue>) {
+    for item in items {
+        match item {
+            JsonValue::Array(nested) => flatten_into(nested, elements),
+            item => elements.push(item),
+        }
+    }
+}
+
+/// Split a map into one `{"key": k, "value": v}` record per entry, in key order
+fn explode_keys(map: serde_json::Map<String, JsonValue>) -> Vec<JsonValue> {
+    let mut entries: Vec<_> = map.into_iter().collect();
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    entries
+        .into_iter()
+        .map(|(key, value)| serde_json::json!({"key": key, "value": value}))
+        .collect()
+}
+
+/// Serialize a record as it is emitted, formatting results according to the output flags
+fn format_record(value: RecordValue, input_params: &InputParameters) -> Result<Content> {
+    let text = match value {
+        RecordValue::Json(json_value) => format_output(&json_value, input_params)?,
+        RecordValue::Report(report) => format_report(&report, input_params)?,
+        // Keyed records are collected into the index, and only output as pairs outside of it
+        RecordValue::Keyed(key, record) => {
+            format_output(&serde_json::json!([key, record]), input_params)?
+        }
+        RecordValue::Bytes(bytes) => return Ok(Content::Bytes(bytes)),
+    };
+    Ok(Content::Text(text))
+}
+
+/// Serialize a single output record according to the output flags
+///
+/// # Arguments
+/// * `json_value` - The record to serialize
+/// * `input_params` - Input configuration parameters
+///
+/// # Returns
+/// * Ok(String) - The record as JSON, a raw string, or an SQL statement
+/// * Err(anyhow::Error) - If the record cannot be serialized
+fn format_output(json_value: &JsonValue, input_params: &InputParameters) -> Result<String> {
+    let output_string = if let Some(table) = &input_params.sql_insert {
+        json_to_sql_insert(json_value, table, &input_params.sql_columns)?
+    } else if input_params.csv_output {
+        json_to_csv(json_value)?
+    } else if input_params.tab {
+        json_to_tsv(json_value)?
+    } else if let serde_json::Value::String(s) = json_value
+        && input_params.raw_output
+        && !input_params.ascii_output
+    {
+        s.clone()
+    } else if input_params.color {
+        let indent = input_params.pretty_print && !input_params.compact;
+        to_colored_string(json_value, indent.then_some(input_params.indent.as_str()))
+    } else if input_params.pretty_print && !input_params.compact {
+        to_string_indented(json_value, &input_params.indent)?
     } else {
-        serde_json::to_string(&json_value).context("Failed to serialize result to JSON")?
+        serde_json::to_string(json_value).context("Failed to serialize result to JSON")?
     };
 
-    Ok((output_string, is_truthy))
+    if input_params.ascii_output {
+        return Ok(escape_non_ascii(&output_string));
+    }
+    Ok(output_string)
+}
+
+/// Escape the non-ASCII characters of serialized JSON as `\uXXXX`, using surrogate pairs
+/// outside the Basic Multilingual Plane. Everything but the contents of strings is ASCII, so
+/// the result is still valid JSON with the same value
+fn escape_non_ascii(json: &str) -> String {
+    let mut escaped = String::with_capacity(json.len());
+    for c in json.chars() {
+        if c.is_ascii() {
+            escaped.push(c);
+        } else {
+            for unit in c.encode_utf16(&mut [0; 2]) {
+                escaped.push_str(&format!("\\u{:04x}", unit));
+            }
+        }
+    }
+    escaped
+}
+
+/// Serialize a record as pretty-printed JSON, indenting each level with `indent`
+fn to_string_indented(json_value: &JsonValue, indent: &str) -> Result<String> {
+    let mut buffer = Vec::new();
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+    let mut serializer = serde_json::Serializer::with_formatter(&mut buffer, formatter);
+    json_value
+        .serialize(&mut serializer)
+        .context("Failed to serialize result to JSON")?;
+    // serde_json only writes valid UTF-8
+    String::from_utf8(buffer).context("Failed to serialize result to JSON")
+}
+
+/// Compute the JSON Patch from the input document to the result
+///
+/// # Arguments
+/// * `context` - The context the program was executed with
+/// * `input_params` - Input configuration parameters
+/// * `json_value` - The result of the program
+///
+/// # Returns
+/// * Ok(JsonValue) - The RFC 6902 JSON Patch as a JSON array
+/// * Err(anyhow::Error) - If the input or the result is not an object
+fn diff_against_input(
+    context: &Context,
+    input_params: &InputParameters,
+    json_value: &JsonValue,
+) -> Result<JsonValue> {
+    let input = context
+        .get_variable(&input_params.root_var)
+        .map(|value| {
+            cel_value_to_json_value(
+                &value,
+                input_params.iso_durations,
+                input_params.float_precision,
+            )
+        })
+        .map_err(|_| anyhow::anyhow!("--diff requires a JSON input document"))?;
+
+    anyhow::ensure!(
+        input.is_object(),
+        "--diff requires the input to be an object"
+    );
+    anyhow::ensure!(
+        json_value.is_object(),
+        "--diff requires the result to be an object"
+    );
+
+    Ok(JsonValue::Array(json_diff(&input, json_value)))
 }
 
 /// Determine if a CEL value is truthy
@@ -237,25 +2528,80 @@ fn is_cel_value_truthy(value: &CelValue) -> bool {
     }
 }
 
-fn sort_keys_recursive(value: &mut JsonValue) {
+/// Truthiness of a JSON value, matching `is_cel_value_truthy` for the value it was converted from
+fn is_json_value_truthy(value: &JsonValue) -> bool {
+    match value {
+        JsonValue::Bool(b) => *b,
+        JsonValue::Number(n) => n.as_f64().is_some_and(|f| f != 0.0),
+        JsonValue::String(s) => !s.is_empty(),
+        JsonValue::Array(a) => !a.is_empty(),
+        JsonValue::Object(o) => !o.is_empty(),
+        JsonValue::Null => false,
+    }
+}
+
+/// Shorten every string in the value to at most `max_chars` characters followed by `marker`
+///
+/// Object keys are left untouched.
+fn truncate_strings_recursive(value: &mut JsonValue, max_chars: usize, marker: &str) {
+    match value {
+        JsonValue::String(s) => {
+            if let Some((byte_idx, _)) = s.char_indices().nth(max_chars) {
+                s.truncate(byte_idx);
+                s.push_str(marker);
+            }
+        }
+        JsonValue::Array(arr) => {
+            for v in arr {
+                truncate_strings_recursive(v, max_chars, marker);
+            }
+        }
+        JsonValue::Object(map) => {
+            for v in map.values_mut() {
+                truncate_strings_recursive(v, max_chars, marker);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Sort the keys of every object in the value
+///
+/// With `numeric`, objects whose keys all parse as numbers are sorted by their
+/// numeric value instead of lexicographically.
+fn sort_keys_recursive(value: &mut JsonValue, numeric: bool) {
     use std::mem;
     match value {
         JsonValue::Object(map) => {
             for v in map.values_mut() {
-                sort_keys_recursive(v);
+                sort_keys_recursive(v, numeric);
             }
 
             // Rebuild the map in sorted key order
             let old_map = mem::take(map);
 
             let mut entries: Vec<_> = old_map.into_iter().collect();
-            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            let numeric_keys: Option<Vec<f64>> = if numeric {
+                entries.iter().map(|(k, _)| k.parse::<f64>().ok()).collect()
+            } else {
+                None
+            };
+
+            match numeric_keys {
+                Some(numbers) => {
+                    let mut keyed: Vec<_> = numbers.into_iter().zip(entries).collect();
+                    // total_cmp gives NaN a well-defined position
+                    keyed.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+                    entries = keyed.into_iter().map(|(_, entry)| entry).collect();
+                }
+                None => entries.sort_by(|(a, _), (b, _)| a.cmp(b)),
+            }
 
             *map = entries.into_iter().collect();
         }
         JsonValue::Array(arr) => {
             for v in arr {
-                sort_keys_recursive(v);
+                sort_keys_recursive(v, numeric);
             }
         }
         _ => {} // Null / Bool / Number / String
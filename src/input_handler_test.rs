@@ -4,25 +4,155 @@ use cel::Program;
 fn default_params() -> InputParameters {
     InputParameters {
         root_var: "this".to_string(),
+        expand_only: vec![],
+        expose_fields: None,
+        null_variables: Vec::new(),
         null_input: false,
+        env_input: false,
         slurp: false,
         from_json5: false,
         from_toml: false,
         from_yaml: false,
+        number_mode: crate::json2cel::NumberMode::Auto,
+        bignum_error: false,
+        grok: None,
         parallelism: -1,
+        presort_by: None,
+        group_by: None,
+        batch_size: None,
+        max_line_bytes: None,
+        find: false,
+        find_input: false,
         sort_keys: false,
+        numeric_key_sort: false,
         pretty_print: false,
+        indent: "  ".to_string(),
         raw_output: false,
+        ascii_output: false,
+        output_raw_bytes: false,
+        fromjson: false,
+        iso_durations: false,
+        float_precision: None,
+        diff: false,
+        with_filename: false,
+        header_line: false,
+        continue_on_error: false,
+        truncate_strings: None,
+        truncate_marker: "...".to_string(),
+        error_output: false,
+        validate: false,
+        verbose: false,
+        args_from_stdin_header: false,
+        compact: false,
+        color: false,
+        type_of: false,
+        limit: None,
+        length_prefixed_input: false,
+        blank_separated: false,
+        raw_input: false,
+        seq: false,
+        csv_input: false,
+        csv_infer_types: false,
+        files: vec![],
+        gzip: false,
+        follow: None,
+        sql_insert: None,
+        sql_columns: vec![],
+        csv_output: false,
+        tab: false,
+        default_output: None,
+        allow_env: false,
+        allow_fs: false,
+        now: chrono::DateTime::UNIX_EPOCH.into(),
+        uuid_state: None,
+        regex_cache: Default::default(),
+        explode_keys: false,
+        flatten: false,
+        stamp: None,
+        merge_args_into_root: false,
+        stages: vec![],
+        precheck: None,
+        index_by: None,
+        index_dup: IndexDup::Last,
+        index_by_line: false,
     }
 }
 
+/// Format a record the way it is emitted, without the delivery-time `--stamp`
+fn formatted(record: Record, input_params: &InputParameters) -> (String, bool) {
+    let output = format_record(record.value, input_params).unwrap();
+    (output.to_string(), record.is_truthy)
+}
+
+fn format_records(records: Vec<Record>, input_params: &InputParameters) -> Vec<(String, bool)> {
+    records
+        .into_iter()
+        .map(|record| formatted(record, input_params))
+        .collect()
+}
+
+fn handle_single(
+    program: &Program,
+    arg_variables: &BTreeMap<String, CelValue>,
+    input_params: &InputParameters,
+    json_str: Option<&str>,
+) -> Result<(String, bool)> {
+    let mut outputs = handle_json(program, arg_variables, input_params, json_str)?;
+    assert_eq!(outputs.len(), 1, "expected a single output");
+    Ok(formatted(outputs.remove(0), input_params))
+}
+
+fn collect_buffer<R: Read>(
+    program: &Program,
+    arg_variables: &BTreeMap<String, CelValue>,
+    input_params: &InputParameters,
+    reader: BufReader<R>,
+) -> Result<Vec<(String, bool)>> {
+    let mut results = Vec::new();
+    handle_buffer(
+        program,
+        arg_variables,
+        input_params,
+        reader,
+        &mut |record| {
+            results.push(formatted(record, input_params));
+            Ok(())
+        },
+    )?;
+    Ok(results)
+}
+
+fn collect_input(
+    program: &Program,
+    arg_variables: &BTreeMap<String, CelValue>,
+    input_params: &InputParameters,
+) -> Result<Vec<(String, bool)>> {
+    let mut results = Vec::new();
+    collect_input_with(program, arg_variables, input_params, |output| {
+        results.push(output)
+    })?;
+    Ok(results)
+}
+
+fn collect_input_with(
+    program: &Program,
+    arg_variables: &BTreeMap<String, CelValue>,
+    input_params: &InputParameters,
+    mut on_output: impl FnMut((String, bool)),
+) -> Result<()> {
+    handle_input(program, arg_variables, input_params, &mut |output| {
+        on_output((output.content.to_string(), output.is_truthy));
+        Ok(())
+    })
+}
+
 #[test]
 fn test_handle_json_null_input() {
     let program = Program::compile("2 + 3").unwrap();
     let args = BTreeMap::new();
     let params = default_params();
 
-    let (output, is_truthy) = handle_json(&program, &args, &params, None).unwrap();
+    let (output, is_truthy) = handle_single(&program, &args, &params, None).unwrap();
 
     assert!(output.contains("5"));
     assert!(is_truthy);
@@ -35,7 +165,7 @@ fn test_handle_json_with_json() {
     let json = r#"{"x": 10, "y": 20}"#;
     let params = default_params();
 
-    let (output, is_truthy) = handle_json(&program, &args, &params, Some(json)).unwrap();
+    let (output, is_truthy) = handle_single(&program, &args, &params, Some(json)).unwrap();
 
     assert!(output.contains("30"));
     assert!(is_truthy);
@@ -49,7 +179,7 @@ fn test_handle_json_with_args() {
     args.insert("y".to_string(), CelValue::Int(7));
     let params = default_params();
 
-    let (output, is_truthy) = handle_json(&program, &args, &params, None).unwrap();
+    let (output, is_truthy) = handle_single(&program, &args, &params, None).unwrap();
 
     assert!(output.contains("12"));
     assert!(is_truthy);
@@ -63,7 +193,7 @@ fn test_handle_json_args_and_json() {
     let json = r#"{"value": 50}"#;
     let params = default_params();
 
-    let (output, is_truthy) = handle_json(&program, &args, &params, Some(json)).unwrap();
+    let (output, is_truthy) = handle_single(&program, &args, &params, Some(json)).unwrap();
 
     assert!(output.contains("150"));
     assert!(is_truthy);
@@ -75,7 +205,7 @@ fn test_handle_json_boolean_false() {
     let args = BTreeMap::new();
     let params = default_params();
 
-    let (output, is_truthy) = handle_json(&program, &args, &params, None).unwrap();
+    let (output, is_truthy) = handle_single(&program, &args, &params, None).unwrap();
 
     assert!(output.contains("false"));
     assert!(!is_truthy);
@@ -87,7 +217,7 @@ fn test_handle_json_boolean_true() {
     let args = BTreeMap::new();
     let params = default_params();
 
-    let (output, is_truthy) = handle_json(&program, &args, &params, None).unwrap();
+    let (output, is_truthy) = handle_single(&program, &args, &params, None).unwrap();
 
     assert!(output.contains("true"));
     assert!(is_truthy);
@@ -99,7 +229,7 @@ fn test_handle_json_truthiness_zero() {
     let args = BTreeMap::new();
     let params = default_params();
 
-    let (_output, is_truthy) = handle_json(&program, &args, &params, None).unwrap();
+    let (_output, is_truthy) = handle_single(&program, &args, &params, None).unwrap();
 
     assert!(!is_truthy);
 }
@@ -110,7 +240,7 @@ fn test_handle_json_truthiness_empty_string() {
     let args = BTreeMap::new();
     let params = default_params();
 
-    let (_output, is_truthy) = handle_json(&program, &args, &params, None).unwrap();
+    let (_output, is_truthy) = handle_single(&program, &args, &params, None).unwrap();
 
     assert!(!is_truthy);
 }
@@ -138,6 +268,43 @@ fn test_handle_json_missing_variable() {
     assert!(result.is_err());
 }
 
+#[test]
+fn test_handle_json_null_for_missing_variable() {
+    let program = Program::compile("[missing_var, x, this.y]").unwrap();
+    let args = BTreeMap::from([("x".to_string(), CelValue::Int(1))]);
+    let mut params = default_params();
+    params.null_variables = vec![
+        "missing_var".to_string(),
+        "x".to_string(),
+        "this".to_string(),
+    ];
+
+    let result = handle_json(&program, &args, &params, Some(r#"{"y": 2}"#)).unwrap();
+
+    assert_eq!(
+        format_records(result, &params),
+        vec![("[null,1,2]".to_string(), true)]
+    );
+}
+
+#[test]
+fn test_handle_json_expose_fields_skips_unusable_names() {
+    let program = Program::compile("x + y").unwrap();
+    let args = BTreeMap::from([("y".to_string(), CelValue::Int(100))]);
+    let mut params = default_params();
+    let skipped = crate::cli::SkippedFields::default();
+    params.expose_fields = Some(Arc::clone(&skipped));
+
+    // "y" keeps the argument's value, and the names that are not identifiers are skipped
+    let input = r#"{"x": 1, "y": 2, "not-ident": 3, "in": 4}"#;
+    let (output, _) = handle_single(&program, &args, &params, Some(input)).unwrap();
+    assert_eq!(output, "101");
+
+    // Each skipped field is warned about once for the whole run
+    let skipped: Vec<_> = skipped.lock().unwrap().iter().cloned().collect();
+    assert_eq!(skipped, vec!["in", "not-ident", "y"]);
+}
+
 #[test]
 fn test_handle_buffer_single_line() {
     let program = Program::compile("this.x").unwrap();
@@ -147,7 +314,7 @@ fn test_handle_buffer_single_line() {
     let reader = BufReader::new(cursor);
     let params = default_params();
 
-    let results = handle_buffer(&program, &args, &params, reader).unwrap();
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
 
     assert_eq!(results.len(), 1);
     assert!(results[0].0.contains("42"));
@@ -165,7 +332,7 @@ fn test_handle_buffer_multiple_lines() {
     let reader = BufReader::new(cursor);
     let params = default_params();
 
-    let results = handle_buffer(&program, &args, &params, reader).unwrap();
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
 
     assert_eq!(results.len(), 3);
     assert!(results[0].0.contains("1"));
@@ -184,7 +351,7 @@ fn test_handle_buffer_slurp() {
     let mut params = default_params();
     params.slurp = true;
 
-    let results = handle_buffer(&program, &args, &params, reader).unwrap();
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
 
     assert_eq!(results.len(), 1);
     assert!(results[0].0.contains("30"));
@@ -199,7 +366,7 @@ fn test_handle_buffer_empty_input() {
     let reader = BufReader::new(cursor);
     let params = default_params();
 
-    let results = handle_buffer(&program, &args, &params, reader).unwrap();
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
 
     assert_eq!(results.len(), 1);
     assert!(results[0].0.contains("5"));
@@ -213,13 +380,26 @@ fn test_handle_input_null_input() {
     let mut params = default_params();
     params.null_input = true;
 
-    let results = handle_input(&program, &args, &params).unwrap();
+    let results = collect_input(&program, &args, &params).unwrap();
 
     assert_eq!(results.len(), 1);
     assert!(results[0].0.contains("5"));
     assert!(results[0].1);
 }
 
+#[test]
+fn test_null_input_binds_null_root() {
+    let program = Program::compile("this == null && x == 1").unwrap();
+    let mut args = BTreeMap::new();
+    args.insert("x".to_string(), CelValue::Int(1));
+    let mut params = default_params();
+    params.null_input = true;
+
+    let results = collect_input(&program, &args, &params).unwrap();
+
+    assert_eq!(results, vec![("true".to_string(), true)]);
+}
+
 #[test]
 fn test_handle_buffer_skip_empty_lines() {
     let program = Program::compile("this.x").unwrap();
@@ -233,10 +413,1527 @@ fn test_handle_buffer_skip_empty_lines() {
     let reader = BufReader::new(cursor);
     let params = default_params();
 
-    let results = handle_buffer(&program, &args, &params, reader).unwrap();
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
 
     assert_eq!(results.len(), 3);
     assert!(results[0].0.contains("1"));
     assert!(results[1].0.contains("2"));
     assert!(results[2].0.contains("3"));
 }
+
+#[test]
+fn test_handle_buffer_presort_by() {
+    let program = Program::compile("this.name").unwrap();
+    let args = BTreeMap::new();
+    let input = r#"{"n": 3, "name": "c"}
+{"n": 1, "name": "a"}
+{"n": 2, "name": "b"}"#;
+    let cursor = Cursor::new(input.as_bytes());
+    let reader = BufReader::new(cursor);
+    let mut params = default_params();
+    params.presort_by = Some(Arc::new(Program::compile("this.n").unwrap()));
+
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
+
+    let outputs: Vec<&str> = results.iter().map(|(output, _)| output.as_str()).collect();
+    assert_eq!(outputs, vec![r#""a""#, r#""b""#, r#""c""#]);
+}
+
+#[test]
+fn test_compare_keys_mixed_numbers() {
+    assert_eq!(
+        compare_keys(&CelValue::Int(-1), &CelValue::UInt(1)),
+        Ordering::Less
+    );
+    assert_eq!(
+        compare_keys(&CelValue::Float(2.5), &CelValue::Int(2)),
+        Ordering::Greater
+    );
+    assert_eq!(
+        compare_keys(&CelValue::Null, &CelValue::Bool(false)),
+        Ordering::Less
+    );
+}
+
+#[test]
+fn test_handle_json_diff_added_field() {
+    let program = Program::compile(r#"{"a": this.a, "b": 2}"#).unwrap();
+    let args = BTreeMap::new();
+    let json = r#"{"a": 1}"#;
+    let mut params = default_params();
+    params.diff = true;
+
+    let (output, _) = handle_single(&program, &args, &params, Some(json)).unwrap();
+
+    assert_eq!(output, r#"[{"op":"add","path":"/b","value":2}]"#);
+}
+
+#[test]
+fn test_handle_json_diff_non_object_result() {
+    let program = Program::compile("this.a").unwrap();
+    let args = BTreeMap::new();
+    let json = r#"{"a": 1}"#;
+    let mut params = default_params();
+    params.diff = true;
+
+    let result = handle_json(&program, &args, &params, Some(json));
+
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_handle_buffer_grok() {
+    let program = Program::compile("this.method + ' ' + this.status").unwrap();
+    let args = BTreeMap::new();
+    let input = r#"GET /index.html 200
+not a request line
+POST /login 401"#;
+    let cursor = Cursor::new(input.as_bytes());
+    let reader = BufReader::new(cursor);
+    let mut params = default_params();
+    params.grok = Some(r"^(?P<method>[A-Z]+) \S+ (?P<status>\d+)$".to_string());
+
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
+
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].0, r#""GET 200""#);
+    assert_eq!(results[1].0, r#""POST 401""#);
+}
+
+#[test]
+fn test_handle_buffer_grok_invalid_regex() {
+    let program = Program::compile("this").unwrap();
+    let args = BTreeMap::new();
+    let cursor = Cursor::new(b"line".to_vec());
+    let reader = BufReader::new(cursor);
+    let mut params = default_params();
+    params.grok = Some("(unclosed".to_string());
+
+    let result = collect_buffer(&program, &args, &params, reader);
+
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_handle_json_sort_keys() {
+    let program = Program::compile(r#"{"b": 1, "a": {"d": 2, "c": 3}}"#).unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.sort_keys = true;
+
+    let (output, _) = handle_single(&program, &args, &params, None).unwrap();
+
+    assert_eq!(output, r#"{"a":{"c":3,"d":2},"b":1}"#);
+}
+
+#[test]
+fn test_handle_json_sort_keys_inside_lists() {
+    let program = Program::compile(r#"[{"z": 1, "y": 2}, {"x": 3, "w": 4}]"#).unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.sort_keys = true;
+
+    let (output, _) = handle_single(&program, &args, &params, None).unwrap();
+
+    assert_eq!(output, r#"[{"y":2,"z":1},{"w":4,"x":3}]"#);
+}
+
+#[test]
+fn test_handle_json_numeric_key_sort() {
+    let program =
+        Program::compile(r#"{"10": "b", "2": "a", "nested": {"1.5": 1, "-3": 2}}"#).unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.sort_keys = true;
+    params.numeric_key_sort = true;
+
+    let (output, _) = handle_single(&program, &args, &params, None).unwrap();
+
+    // The top-level keys are not all numeric, so they fall back to lexicographic order
+    assert_eq!(output, r#"{"10":"b","2":"a","nested":{"-3":2,"1.5":1}}"#);
+}
+
+#[test]
+fn test_handle_json_numeric_key_sort_all_numbers() {
+    let program = Program::compile(r#"{"10": "b", "2": "a", "1": "c"}"#).unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.sort_keys = true;
+    params.numeric_key_sort = true;
+
+    let (output, _) = handle_single(&program, &args, &params, None).unwrap();
+
+    assert_eq!(output, r#"{"1":"c","2":"a","10":"b"}"#);
+}
+
+#[test]
+fn test_bind_filename() {
+    let args = BTreeMap::new();
+    let mut params = default_params();
+
+    assert!(bind_filename(&args, &params, Some("<stdin>")).is_empty());
+
+    params.with_filename = true;
+    let vars = bind_filename(&args, &params, Some("<stdin>"));
+    assert_eq!(
+        vars.get("filename"),
+        Some(&CelValue::String(Arc::new("<stdin>".to_string())))
+    );
+
+    let vars = bind_filename(&args, &params, None);
+    assert_eq!(vars.get("filename"), Some(&CelValue::Null));
+}
+
+#[test]
+fn test_header_line_defaults() {
+    let program = Program::compile("this.value > threshold").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.header_line = true;
+
+    let input = "{\"threshold\": 10}\n{\"value\": 5}\n{\"value\": 15}\n";
+    let reader = BufReader::new(Cursor::new(input.as_bytes()));
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
+
+    assert_eq!(
+        results,
+        vec![("false".to_string(), false), ("true".to_string(), true)]
+    );
+}
+
+#[test]
+fn test_header_line_arguments_take_precedence() {
+    let vars = header_line_variables(
+        r#"{"threshold": 10, "unit": "ms"}"#,
+        &BTreeMap::from([("threshold".to_string(), CelValue::Int(3))]),
+    )
+    .unwrap();
+
+    assert_eq!(vars.get("threshold"), Some(&CelValue::Int(3)));
+    assert_eq!(
+        vars.get("unit"),
+        Some(&CelValue::String(Arc::new("ms".to_string())))
+    );
+}
+
+#[test]
+fn test_header_line_must_be_object() {
+    let result = header_line_variables("[1, 2]", &BTreeMap::new());
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_continue_skips_failing_lines() {
+    let program = Program::compile("this.x * 2").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.continue_on_error = true;
+
+    // A malformed line, and a line where `x` is missing
+    let input = "{\"x\": 1}\n{not json}\n{\"y\": 2}\n{\"x\": 3}\n";
+    let reader = BufReader::new(Cursor::new(input.as_bytes()));
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
+
+    assert_eq!(
+        results,
+        vec![("2".to_string(), true), ("6".to_string(), true)]
+    );
+}
+
+#[test]
+fn test_max_line_bytes() {
+    let program = Program::compile("this.x").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.max_line_bytes = std::num::NonZeroUsize::new(10);
+
+    // The second line is longer than 10 bytes, and the last one is exactly 10 bytes long
+    let long = format!("{{\"x\": \"{}\"}}", "a".repeat(100));
+    let input = format!("{{\"x\": 1}}\r\n{}\n\n{{\"x\": 22}}", long);
+    // A tiny buffer splits the long line across many reads
+    let reader = || BufReader::with_capacity(4, Cursor::new(input.clone().into_bytes()));
+
+    let err = collect_buffer(&program, &args, &params, reader()).unwrap_err();
+    assert!(format!("{:#}", err).contains("Line 2 is longer than --max-line-bytes (10 bytes)"));
+
+    params.continue_on_error = true;
+    let results = collect_buffer(&program, &args, &params, reader()).unwrap();
+    assert_eq!(
+        results,
+        vec![("1".to_string(), true), ("22".to_string(), true)]
+    );
+}
+
+#[test]
+fn test_continue_with_failing_last_line() {
+    let program = Program::compile("this.x").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.continue_on_error = true;
+
+    let input = "{\"x\": 1}\n{\"x\": 2}\n{truncated";
+    let reader = BufReader::new(Cursor::new(input.as_bytes()));
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
+
+    assert_eq!(
+        results,
+        vec![("1".to_string(), true), ("2".to_string(), true)]
+    );
+}
+
+#[test]
+fn test_failing_line_aborts_without_continue() {
+    let program = Program::compile("this.x").unwrap();
+    let args = BTreeMap::new();
+    let params = default_params();
+
+    let input = "{\"x\": 1}\n{not json}\n{\"x\": 3}\n";
+    let reader = BufReader::new(Cursor::new(input.as_bytes()));
+    assert!(collect_buffer(&program, &args, &params, reader).is_err());
+}
+
+#[test]
+fn test_truncate_strings_nested() {
+    let program = Program::compile("this").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.truncate_strings = Some(5);
+
+    let input = r#"{"outer": ["short", {"text": "abcdefghij"}, "éèêëēėę"]}"#;
+    let (output, _) = handle_single(&program, &args, &params, Some(input)).unwrap();
+
+    assert_eq!(
+        output,
+        r#"{"outer":["short",{"text":"abcde..."},"éèêëē..."]}"#
+    );
+}
+
+#[test]
+fn test_truncate_strings_custom_marker() {
+    let mut value = serde_json::json!(["abcdef"]);
+    truncate_strings_recursive(&mut value, 3, "~");
+    assert_eq!(value, serde_json::json!(["abc~"]));
+}
+
+#[test]
+fn test_error_output_replaces_failing_lines() {
+    let program = Program::compile("this.x").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.error_output = true;
+
+    let input = "{\"x\": 1}\n{bad}\n{\"x\": 3}\n";
+    let reader = BufReader::new(Cursor::new(input.as_bytes()));
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
+
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0], ("1".to_string(), true));
+    assert_eq!(results[2], ("3".to_string(), true));
+
+    let (error_output, is_truthy) = &results[1];
+    assert!(!is_truthy);
+    let error: serde_json::Value = serde_json::from_str(error_output).unwrap();
+    assert_eq!(error["line"], 2);
+    assert_eq!(error["input"], "{bad}");
+    assert!(error["error"].as_str().unwrap().contains("JSON"));
+}
+
+#[test]
+fn test_validate_outputs_only_failing_lines() {
+    let program = Program::compile("this.x > 0").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.validate = true;
+
+    let input = "{\"x\": 1}\n{\"x\": 0}\n\n{\"x\": 3}\n{\"x\": -1}\n";
+    let reader = BufReader::new(Cursor::new(input.as_bytes()));
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
+
+    assert_eq!(
+        results,
+        vec![
+            (r#"{"line":2,"input":"{\"x\": 0}"}"#.to_string(), false),
+            (r#"{"line":5,"input":"{\"x\": -1}"}"#.to_string(), false),
+        ]
+    );
+}
+
+#[test]
+fn test_read_args_header() {
+    let program = Program::compile("this.value * x").unwrap();
+    let args = BTreeMap::from([
+        ("x".to_string(), CelValue::Int(1)),
+        ("y".to_string(), CelValue::Int(2)),
+    ]);
+    let params = default_params();
+
+    let input = "{\"x\": 10}\n{\"value\": 1}\n{\"value\": 2}\n";
+    let mut reader = BufReader::new(Cursor::new(input.as_bytes()));
+    let vars = read_args_header(&mut reader, &args).unwrap();
+
+    // The header replaces the argument with the same name and keeps the others
+    assert_eq!(vars.get("x"), Some(&CelValue::Int(10)));
+    assert_eq!(vars.get("y"), Some(&CelValue::Int(2)));
+
+    let results = collect_buffer(&program, &vars, &params, reader).unwrap();
+    assert_eq!(
+        results,
+        vec![("10".to_string(), true), ("20".to_string(), true)]
+    );
+}
+
+#[test]
+fn test_read_args_header_must_be_object() {
+    let mut reader = BufReader::new(Cursor::new("42\n{}\n".as_bytes()));
+    assert!(read_args_header(&mut reader, &BTreeMap::new()).is_err());
+}
+
+#[test]
+fn test_compact_output() {
+    let program = Program::compile("[1, [2, 3]]").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.compact = true;
+
+    let (output, _) = handle_single(&program, &args, &params, None).unwrap();
+    assert_eq!(output, "[1,[2,3]]");
+}
+
+#[test]
+fn test_records_carry_result_types() {
+    let program = Program::compile("this.x").unwrap();
+    let args = BTreeMap::new();
+    let params = default_params();
+
+    let input = "{\"x\": 1}\n{\"x\": \"a\"}\n{\"x\": 2}\n{\"x\": {}}\n{\"x\": null}\n";
+    let mut type_names = Vec::new();
+    handle_buffer(
+        &program,
+        &args,
+        &params,
+        BufReader::new(input.as_bytes()),
+        &mut |record| {
+            type_names.push(record.type_name);
+            Ok(())
+        },
+    )
+    .unwrap();
+
+    assert_eq!(
+        type_names,
+        vec![
+            Some("int"),
+            Some("string"),
+            Some("int"),
+            Some("map"),
+            Some("null_type")
+        ]
+    );
+}
+
+#[test]
+fn test_type_of_replaces_results() {
+    let program = Program::compile("this.x").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.type_of = true;
+
+    let input = "{\"x\": 1}\n{\"x\": 1.5}\n{\"x\": []}\n{\"x\": null}\n";
+    let reader = BufReader::new(Cursor::new(input.as_bytes()));
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
+
+    assert_eq!(
+        results,
+        vec![
+            ("\"int\"".to_string(), true),
+            ("\"double\"".to_string(), true),
+            ("\"list\"".to_string(), false),
+            ("\"null_type\"".to_string(), false),
+        ]
+    );
+}
+
+#[test]
+fn test_output_raw_bytes_requires_bytes() {
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.output_raw_bytes = true;
+
+    let program = Program::compile("bytes(this.s)").unwrap();
+    let reader = BufReader::new(Cursor::new("{\"s\": \"hi\"}\n".as_bytes()));
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
+    assert_eq!(results, vec![("hi".to_string(), true)]);
+
+    let program = Program::compile("this.s").unwrap();
+    let reader = BufReader::new(Cursor::new("{\"s\": \"hi\"}\n".as_bytes()));
+    let err = collect_buffer(&program, &args, &params, reader).unwrap_err();
+    assert!(format!("{:#}", err).contains("expects a bytes result, got string"));
+}
+
+#[test]
+fn test_group_by_reduces_each_group_in_key_order() {
+    let program = Program::compile("[this[0].k, this.map(r, r.v)]").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.group_by = Some(Arc::new(Program::compile("this.k").unwrap()));
+
+    // Equal keys of different types are grouped apart, since their JSON differs
+    let input = "{\"k\": \"b\", \"v\": 1}\n{\"k\": 2, \"v\": 2}\n\n{\"k\": \"b\", \"v\": 3}\n{\"k\": 10, \"v\": 4}\n";
+    let reader = BufReader::new(Cursor::new(input.as_bytes()));
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
+
+    assert_eq!(
+        results,
+        vec![
+            ("[2,[2]]".to_string(), true),
+            ("[10,[4]]".to_string(), true),
+            ("[\"b\",[1,3]]".to_string(), true),
+        ]
+    );
+}
+
+#[test]
+fn test_group_by_reports_failing_line() {
+    let program = Program::compile("size(this)").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.group_by = Some(Arc::new(Program::compile("this.k").unwrap()));
+
+    let input = "{\"k\": 1}\n{\"x\": 2}\n";
+    let reader = BufReader::new(Cursor::new(input.as_bytes()));
+    let err = collect_buffer(&program, &args, &params, reader).unwrap_err();
+    assert!(format!("{:#}", err).contains("Failed to process line 2"));
+}
+
+fn frame(json: &str) -> Vec<u8> {
+    let mut bytes = (json.len() as u32).to_be_bytes().to_vec();
+    bytes.extend_from_slice(json.as_bytes());
+    bytes
+}
+
+#[test]
+fn test_length_prefixed_input() {
+    let program = Program::compile("this.x + 1").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.length_prefixed_input = true;
+
+    // Frames may contain newlines, unlike NDJSON lines
+    let mut input = frame("{\"x\":\n1}");
+    input.extend(frame("{\"x\": 41}"));
+    let reader = BufReader::new(Cursor::new(input));
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
+
+    assert_eq!(
+        results,
+        vec![("2".to_string(), true), ("42".to_string(), true)]
+    );
+}
+
+#[test]
+fn test_length_prefixed_input_truncated() {
+    let program = Program::compile("this").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.length_prefixed_input = true;
+
+    let mut input = frame("{\"x\": 1}");
+    input.truncate(input.len() - 2);
+    let reader = BufReader::new(Cursor::new(input));
+    let err = collect_buffer(&program, &args, &params, reader).unwrap_err();
+    assert!(err.to_string().contains("Truncated frame 1"));
+
+    let reader = BufReader::new(Cursor::new(vec![0u8, 0]));
+    let err = collect_buffer(&program, &args, &params, reader).unwrap_err();
+    assert!(err.to_string().contains("expected a 4-byte length"));
+    // A huge length on a short input fails without allocating the announced size
+    let mut input = u32::MAX.to_be_bytes().to_vec();
+    input.extend_from_slice(b"{}");
+    let reader = BufReader::new(Cursor::new(input));
+    let err = collect_buffer(&program, &args, &params, reader).unwrap_err();
+    assert!(err.to_string().contains("expected 4294967295 bytes, got 2"));
+}
+
+#[test]
+fn test_handle_files_binds_filename() {
+    let dir = tempfile::tempdir().unwrap();
+    let first = dir.path().join("first.json");
+    let second = dir.path().join("second.json");
+    std::fs::write(&first, "{\"x\": 1}\n{\"x\": 2}\n").unwrap();
+    std::fs::write(&second, "{\"x\": 3}\n").unwrap();
+
+    let program = Program::compile("[filename, this.x]").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.with_filename = true;
+    params.files = vec![first.clone(), second.clone()];
+
+    let results = collect_input(&program, &args, &params).unwrap();
+    let outputs: Vec<String> = results.into_iter().map(|(output, _)| output).collect();
+
+    let first_name = serde_json::to_string(&first.display().to_string()).unwrap();
+    let second_name = serde_json::to_string(&second.display().to_string()).unwrap();
+    assert_eq!(
+        outputs,
+        vec![
+            format!("[{},1]", first_name),
+            format!("[{},2]", first_name),
+            format!("[{},3]", second_name),
+        ]
+    );
+}
+
+#[test]
+fn test_handle_files_missing_file() {
+    let program = Program::compile("this").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.files = vec!["does-not-exist.json".into()];
+
+    let err = collect_input(&program, &args, &params).unwrap_err();
+    assert!(err.to_string().contains("does-not-exist.json"));
+}
+
+#[test]
+fn test_handle_files_follow_reads_appended_lines() {
+    use std::io::Write;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.json");
+    // The last line is incomplete, so it is held back until the rest is appended
+    std::fs::write(&path, "{\"x\": 1}\n\n{\"x\": 2").unwrap();
+
+    let program = Program::compile("this.x").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.files = vec![path.clone()];
+    let stop = crate::cli::FollowStop::default();
+    params.follow = Some(Arc::clone(&stop));
+
+    let mut results = Vec::new();
+    collect_input_with(&program, &args, &params, |output| {
+        results.push(output);
+        match results.len() {
+            1 => {
+                let mut file = std::fs::OpenOptions::new()
+                    .append(true)
+                    .open(&path)
+                    .unwrap();
+                file.write_all(b"}\n{\"x\": 3}\n").unwrap();
+            }
+            3 => stop.store(true, AtomicOrdering::Relaxed),
+            _ => {}
+        }
+    })
+    .unwrap();
+
+    assert_eq!(
+        results,
+        vec![
+            ("1".to_string(), true),
+            ("2".to_string(), true),
+            ("3".to_string(), true),
+        ]
+    );
+}
+
+#[test]
+fn test_handle_files_follow_rereads_truncated_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.json");
+    std::fs::write(&path, "{\"x\": 1}\n{\"x\": 2}\n").unwrap();
+
+    let program = Program::compile("this.x").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.files = vec![path.clone()];
+    let stop = crate::cli::FollowStop::default();
+    params.follow = Some(Arc::clone(&stop));
+
+    let mut results = Vec::new();
+    collect_input_with(&program, &args, &params, |output| {
+        results.push(output);
+        match results.len() {
+            2 => std::fs::write(&path, "{\"x\": 3}\n").unwrap(),
+            3 => stop.store(true, AtomicOrdering::Relaxed),
+            _ => {}
+        }
+    })
+    .unwrap();
+
+    let values: Vec<_> = results.into_iter().map(|(value, _)| value).collect();
+    assert_eq!(values, vec!["1", "2", "3"]);
+}
+
+#[test]
+#[cfg(unix)]
+fn test_handle_files_follow_reopens_replaced_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.json");
+    std::fs::write(&path, "{\"x\": 1}\n").unwrap();
+
+    let program = Program::compile("this.x").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.files = vec![path.clone()];
+    let stop = crate::cli::FollowStop::default();
+    params.follow = Some(Arc::clone(&stop));
+
+    let mut results = Vec::new();
+    collect_input_with(&program, &args, &params, |output| {
+        results.push(output);
+        match results.len() {
+            // Rotate the log by moving a new file over it, longer than what was read so far
+            1 => {
+                let next = dir.path().join("next.json");
+                std::fs::write(&next, "{\"x\": 10}\n{\"x\": 20}\n").unwrap();
+                std::fs::rename(&next, &path).unwrap();
+            }
+            3 => stop.store(true, AtomicOrdering::Relaxed),
+            _ => {}
+        }
+    })
+    .unwrap();
+
+    let values: Vec<_> = results.into_iter().map(|(value, _)| value).collect();
+    assert_eq!(values, vec!["1", "10", "20"]);
+}
+
+#[test]
+#[cfg(unix)]
+fn test_handle_files_reads_unix_socket() {
+    use std::io::Write;
+    use std::os::unix::net::UnixListener;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("input.sock");
+    let listener = UnixListener::bind(&path).unwrap();
+    let writer = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(b"{\"x\": 1}\n{\"x\": 2}\n").unwrap();
+    });
+
+    let program = Program::compile("this.x").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.files = vec![path];
+
+    let results = collect_input(&program, &args, &params).unwrap();
+    writer.join().unwrap();
+    assert_eq!(
+        results,
+        vec![("1".to_string(), true), ("2".to_string(), true)]
+    );
+}
+
+#[cfg(feature = "gzip")]
+fn gzip_bytes(data: &str) -> Vec<u8> {
+    use flate2::{Compression, write::GzEncoder};
+    use std::io::Write;
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data.as_bytes()).unwrap();
+    encoder.finish().unwrap()
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn test_input_reader_gzip() {
+    let program = Program::compile("this.x").unwrap();
+    let args = BTreeMap::new();
+    let params = default_params();
+
+    // Two concatenated gzip members
+    let mut compressed = gzip_bytes("{\"x\": 1}\n");
+    compressed.extend(gzip_bytes("{\"x\": 2}\n"));
+    let reader = input_reader(Cursor::new(compressed), true).unwrap();
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
+
+    assert_eq!(
+        results,
+        vec![("1".to_string(), true), ("2".to_string(), true)]
+    );
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn test_handle_files_detects_gz_extension() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("records.json.gz");
+    std::fs::write(&path, gzip_bytes("{\"x\": 7}")).unwrap();
+
+    let program = Program::compile("this.x").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.files = vec![path];
+
+    let results = collect_input(&program, &args, &params).unwrap();
+    assert_eq!(results, vec![("7".to_string(), true)]);
+}
+
+#[test]
+fn test_sql_insert_output() {
+    let program = Program::compile("{'name': this.name}").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.sql_insert = Some("people".to_string());
+
+    let (output, _) =
+        handle_single(&program, &args, &params, Some(r#"{"name": "D'Arcy"}"#)).unwrap();
+    assert_eq!(output, "INSERT INTO people (name) VALUES ('D''Arcy');");
+}
+
+#[test]
+fn test_streaming_ndjson() {
+    let program = Program::compile("this.x * 2").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.parallelism = 1;
+
+    let input = "{\"x\": 1}\n\n{\"x\": 2}\n{\"x\": 3}\n";
+    let reader = BufReader::new(input.as_bytes());
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
+
+    let outputs: Vec<&str> = results.iter().map(|(output, _)| output.as_str()).collect();
+    assert_eq!(outputs, vec!["2", "4", "6"]);
+}
+
+#[test]
+fn test_streaming_emits_before_failing_line() {
+    let program = Program::compile("this.x * 2").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.parallelism = 1;
+
+    let input = "{\"x\": 1}\n{\"x\": 2}\nnot json\n{\"x\": 3}\n";
+    let mut emitted = Vec::new();
+    let result = handle_buffer(
+        &program,
+        &args,
+        &params,
+        BufReader::new(input.as_bytes()),
+        &mut |record| {
+            emitted.push(formatted(record, &params).0);
+            Ok(())
+        },
+    );
+
+    assert!(result.is_err());
+    assert_eq!(emitted, vec!["2", "4"]);
+}
+
+#[test]
+fn test_streaming_continue_on_error() {
+    let program = Program::compile("this.x * 2").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.parallelism = 1;
+    params.continue_on_error = true;
+
+    let input = "{\"x\": 1}\nnot json\n{\"x\": 3}\n";
+    let reader = BufReader::new(input.as_bytes());
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
+
+    let outputs: Vec<&str> = results.iter().map(|(output, _)| output.as_str()).collect();
+    assert_eq!(outputs, vec!["2", "6"]);
+}
+
+#[test]
+fn test_streaming_multi_line_document() {
+    let program = Program::compile("this.x").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.parallelism = 1;
+
+    let input = "{\n  \"x\": 7\n}\n";
+    let reader = BufReader::new(input.as_bytes());
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
+
+    assert_eq!(results, vec![("7".to_string(), true)]);
+}
+
+#[test]
+fn test_streaming_header_line() {
+    let program = Program::compile("this.x + offset").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.parallelism = 1;
+    params.header_line = true;
+
+    let input = "{\"offset\": 10}\n{\"x\": 1}\n{\"x\": 2}\n";
+    let reader = BufReader::new(input.as_bytes());
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
+
+    let outputs: Vec<&str> = results.iter().map(|(output, _)| output.as_str()).collect();
+    assert_eq!(outputs, vec!["11", "12"]);
+}
+
+#[test]
+fn test_streaming_empty_input() {
+    let program = Program::compile("1 + 1").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.parallelism = 1;
+
+    let reader = BufReader::new("".as_bytes());
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
+
+    assert_eq!(results, vec![("2".to_string(), true)]);
+}
+
+#[test]
+fn test_batches_keep_input_order() {
+    let program = Program::compile("this.x * 2").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.parallelism = 4;
+    params.batch_size = std::num::NonZeroUsize::new(2);
+
+    let input = (1..=5)
+        .map(|x| format!("{{\"x\": {}}}\n", x))
+        .collect::<String>();
+    let reader = BufReader::new(input.as_bytes());
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
+
+    let outputs: Vec<&str> = results.iter().map(|(output, _)| output.as_str()).collect();
+    assert_eq!(outputs, vec!["2", "4", "6", "8", "10"]);
+}
+
+#[test]
+fn test_batches_emit_before_failing_batch() {
+    let program = Program::compile("this.x").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.parallelism = 2;
+    params.batch_size = std::num::NonZeroUsize::new(2);
+
+    let input = "{\"x\": 1}\n{\"x\": 2}\n{\"x\": 3}\nnot json\n";
+    let mut emitted = Vec::new();
+    let result = handle_buffer(
+        &program,
+        &args,
+        &params,
+        BufReader::new(input.as_bytes()),
+        &mut |record| {
+            emitted.push(formatted(record, &params).0);
+            Ok(())
+        },
+    );
+
+    assert!(result.is_err());
+    assert_eq!(emitted, vec!["1", "2"]);
+}
+
+#[test]
+fn test_default_output_replaces_null() {
+    let program = Program::compile("this.x").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.default_output = Some(serde_json::json!("missing"));
+
+    let (output, is_truthy) =
+        handle_single(&program, &args, &params, Some(r#"{"x": null}"#)).unwrap();
+    assert_eq!(output, "\"missing\"");
+    assert!(is_truthy);
+
+    let (output, _) = handle_single(&program, &args, &params, Some(r#"{"x": 1}"#)).unwrap();
+    assert_eq!(output, "1");
+}
+
+#[test]
+fn test_env_requires_allow_env() {
+    // SAFETY: no other test reads or writes this variable
+    unsafe { std::env::set_var("CELQ_TEST_ALLOW_ENV", "1") };
+    let program = Program::compile("env('CELQ_TEST_ALLOW_ENV') == '1'").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+
+    assert!(handle_json(&program, &args, &params, None).is_err());
+
+    params.allow_env = true;
+    let (output, _) = handle_single(&program, &args, &params, None).unwrap();
+    assert_eq!(output, "true");
+}
+
+#[test]
+fn test_read_file_requires_allow_fs() {
+    let program = Program::compile("readFile('Cargo.toml').contains('celq')").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+
+    assert!(handle_json(&program, &args, &params, None).is_err());
+
+    params.allow_fs = true;
+    let (output, _) = handle_single(&program, &args, &params, None).unwrap();
+    assert_eq!(output, "true");
+}
+
+#[test]
+fn test_env_input() {
+    // SAFETY: no other test reads or writes this variable
+    unsafe { std::env::set_var("CELQ_TEST_ENV_INPUT", "eu-west-1") };
+    let program = Program::compile("this.CELQ_TEST_ENV_INPUT").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.env_input = true;
+
+    let results = collect_input(&program, &args, &params).unwrap();
+
+    assert_eq!(results, vec![("\"eu-west-1\"".to_string(), true)]);
+}
+
+#[test]
+fn test_now_is_fixed_for_the_run() {
+    let program = Program::compile("now() == timestamp('1970-01-01T00:00:00Z')").unwrap();
+    let args = BTreeMap::new();
+    let params = default_params();
+
+    let reader = BufReader::new("{}\n{}\n".as_bytes());
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
+
+    assert_eq!(
+        results,
+        vec![("true".to_string(), true), ("true".to_string(), true)]
+    );
+}
+
+#[test]
+fn test_blank_separated_documents() {
+    let program = Program::compile("this.name").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.blank_separated = true;
+
+    let input = "{\n  \"name\": \"a\"\n}\n\n\n{\n  \"name\": \"b\"\n}\n";
+    let reader = BufReader::new(input.as_bytes());
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
+
+    let outputs: Vec<&str> = results.iter().map(|(output, _)| output.as_str()).collect();
+    assert_eq!(outputs, vec!["\"a\"", "\"b\""]);
+}
+
+#[test]
+fn test_blank_separated_error_names_line() {
+    let program = Program::compile("this").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.blank_separated = true;
+
+    let input = "{}\n\n{\n  bad\n}\n";
+    let reader = BufReader::new(input.as_bytes());
+    let err = collect_buffer(&program, &args, &params, reader).unwrap_err();
+
+    assert!(format!("{:#}", err).contains("line 3"));
+}
+
+#[test]
+fn test_ndjson_error_names_line() {
+    let program = Program::compile("1 / this.a").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+
+    // Both the parallel and the streaming path report the failing line
+    let input = "{\"a\": 1}\n\n{\"a\": 0}\n{\"a\": 2}\n";
+    for parallelism in [4, 1] {
+        params.parallelism = parallelism;
+        let reader = BufReader::new(input.as_bytes());
+        let err = collect_buffer(&program, &args, &params, reader).unwrap_err();
+        assert!(format!("{:#}", err).contains("line 3"));
+    }
+
+    // A malformed last line is reported as well, rather than the whole input as one document
+    params.parallelism = 4;
+    let input = "{\"a\": 1}\n{\"a\": }\n";
+    let reader = BufReader::new(input.as_bytes());
+    let err = collect_buffer(&program, &args, &params, reader).unwrap_err();
+    assert!(format!("{:#}", err).contains("Failed to process line 2"));
+}
+
+#[test]
+fn test_limit_stops_reading() {
+    let dir = tempfile::tempdir().unwrap();
+    let first = dir.path().join("first.json");
+    let second = dir.path().join("second.json");
+    // The malformed line after the second match is never parsed, nor is the next file
+    std::fs::write(&first, "1\n2\n3\nnot json\n").unwrap();
+    std::fs::write(&second, "4\n").unwrap();
+
+    let program = Program::compile("this > 1").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.parallelism = 1;
+    params.limit = Some(Arc::new(std::sync::atomic::AtomicUsize::new(2)));
+    params.files = vec![first, second];
+
+    let results = collect_input(&program, &args, &params).unwrap();
+    assert_eq!(
+        results,
+        vec![("true".to_string(), true), ("true".to_string(), true)]
+    );
+}
+
+#[test]
+fn test_raw_input_lines() {
+    let program = Program::compile("this + '!'").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.raw_input = true;
+
+    let input = "{\"a\": 1}\n\nplain\n";
+    let reader = BufReader::new(input.as_bytes());
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
+
+    let outputs: Vec<&str> = results.iter().map(|(output, _)| output.as_str()).collect();
+    assert_eq!(outputs, vec![r#""{\"a\": 1}!""#, r#""!""#, r#""plain!""#]);
+}
+
+#[test]
+fn test_raw_input_slurp() {
+    let program = Program::compile("this").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.raw_input = true;
+    params.slurp = true;
+
+    let input = "first\nsecond\n";
+    let reader = BufReader::new(input.as_bytes());
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
+
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].0, r#""first\nsecond\n""#);
+}
+
+#[test]
+fn test_seq_records() {
+    let program = Program::compile("this.a").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.seq = true;
+
+    // Records may span lines, and empty records are skipped
+    let input = "\x1e{\"a\": 1}\n\x1e\x1e{\n  \"a\": 2\n}\n";
+    let reader = BufReader::new(input.as_bytes());
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
+
+    let outputs: Vec<&str> = results.iter().map(|(output, _)| output.as_str()).collect();
+    assert_eq!(outputs, vec!["1", "2"]);
+}
+
+#[test]
+fn test_seq_malformed_records() {
+    let program = Program::compile("this.a").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.seq = true;
+
+    let input = "\x1e{\"a\": 1}\n\x1e{\"a\":\n\x1e{\"a\": 3}\n";
+    let reader = BufReader::new(input.as_bytes());
+    assert!(collect_buffer(&program, &args, &params, reader).is_err());
+
+    params.error_output = true;
+    let reader = BufReader::new(input.as_bytes());
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
+    assert_eq!(results.len(), 3);
+    assert!(results[1].0.contains(r#""line":2"#));
+    assert_eq!(results[2].0, "3");
+}
+
+#[test]
+#[cfg(feature = "from-csv")]
+fn test_csv_input() {
+    let program = Program::compile("this").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.csv_input = true;
+    params.sort_keys = true;
+
+    let input = "name,age\n\"Smith, J\",42\n";
+    let reader = BufReader::new(input.as_bytes());
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
+
+    let outputs: Vec<&str> = results.iter().map(|(output, _)| output.as_str()).collect();
+    assert_eq!(outputs, vec![r#"{"age":"42","name":"Smith, J"}"#]);
+}
+
+#[test]
+#[cfg(feature = "from-csv")]
+fn test_csv_infer_types() {
+    let program = Program::compile("[this.a, this.b, this.c, this.d, this.e]").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.csv_input = true;
+    params.csv_infer_types = true;
+
+    let input = "a,b,c,d,e\ntrue,-7,2.5,NaN,\n";
+    let reader = BufReader::new(input.as_bytes());
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
+
+    let outputs: Vec<&str> = results.iter().map(|(output, _)| output.as_str()).collect();
+    assert_eq!(outputs, vec![r#"[true,-7,2.5,"NaN",""]"#]);
+}
+
+#[test]
+#[cfg(feature = "from-csv")]
+fn test_csv_input_rejects_ragged_rows() {
+    let program = Program::compile("this").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.csv_input = true;
+
+    let reader = BufReader::new("a,b\n1\n".as_bytes());
+    let err = collect_buffer(&program, &args, &params, reader).unwrap_err();
+
+    assert!(format!("{:#}", err).contains("Failed to read CSV record"));
+}
+
+#[test]
+fn test_explode_keys() {
+    let program = Program::compile("{'b': 2, 'a': 1, 'c': [3]}").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.explode_keys = true;
+
+    let outputs: Vec<String> = handle_json(&program, &args, &params, None)
+        .unwrap()
+        .into_iter()
+        .map(|record| formatted(record, &params).0)
+        .collect();
+
+    assert_eq!(
+        outputs,
+        vec![
+            r#"{"key":"a","value":1}"#,
+            r#"{"key":"b","value":2}"#,
+            r#"{"key":"c","value":[3]}"#,
+        ]
+    );
+}
+
+#[test]
+fn test_flatten_nested_lists() {
+    let program = Program::compile("[[1, 'x'], [], [[{'b': 0}]], 0]").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.flatten = true;
+
+    let outputs = handle_json(&program, &args, &params, None).unwrap();
+
+    assert_eq!(
+        format_records(outputs, &params),
+        vec![
+            ("1".to_string(), true),
+            ("\"x\"".to_string(), true),
+            (r#"{"b":0}"#.to_string(), true),
+            ("0".to_string(), false),
+        ]
+    );
+}
+
+#[test]
+fn test_flatten_keeps_non_lists() {
+    let program = Program::compile("'s'").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.flatten = true;
+    params.raw_output = true;
+
+    let (output, _) = handle_single(&program, &args, &params, None).unwrap();
+    assert_eq!(output, "s");
+}
+
+#[test]
+fn test_explode_keys_keeps_non_maps() {
+    let program = Program::compile("[1, 2]").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.explode_keys = true;
+
+    let (output, _) = handle_single(&program, &args, &params, None).unwrap();
+    assert_eq!(output, "[1,2]");
+}
+
+#[test]
+fn test_stamp_adds_rfc3339_field() {
+    let program = Program::compile("{'x': 1}").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.null_input = true;
+    params.stamp = Some("processed_at".to_string());
+
+    let outputs = collect_input(&program, &args, &params).unwrap();
+    let (output, _) = &outputs[0];
+    let record: serde_json::Value = serde_json::from_str(output).unwrap();
+    let stamp = record["processed_at"].as_str().unwrap();
+
+    assert!(chrono::DateTime::parse_from_rfc3339(stamp).is_ok());
+    assert_eq!(record["x"], 1);
+}
+
+#[test]
+fn test_stamp_skips_non_maps() {
+    let program = Program::compile("[1]").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.null_input = true;
+    params.stamp = Some("processed_at".to_string());
+
+    let outputs = collect_input(&program, &args, &params).unwrap();
+    assert_eq!(outputs, vec![("[1]".to_string(), true)]);
+}
+
+#[test]
+fn test_stamp_applies_to_exploded_records() {
+    let program = Program::compile("{'b': 2, 'a': 1}").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.null_input = true;
+    params.explode_keys = true;
+    params.stamp = Some("t".to_string());
+
+    // Each exploded record is stamped, and the stamp is not exploded into a record of its own
+    let outputs = collect_input(&program, &args, &params).unwrap();
+    assert_eq!(outputs.len(), 2);
+    for (output, _) in &outputs {
+        let record: serde_json::Value = serde_json::from_str(output).unwrap();
+        assert_ne!(record["key"], "t");
+        assert!(record["t"].is_string());
+    }
+}
+
+#[test]
+fn test_merge_args_into_root() {
+    let program = Program::compile("this.x + this.y").unwrap();
+    let mut args = BTreeMap::new();
+    args.insert("x".to_string(), CelValue::Int(1));
+    args.insert("y".to_string(), CelValue::Int(100));
+    let mut params = default_params();
+    params.merge_args_into_root = true;
+
+    // The input's own fields take precedence over arguments with the same name
+    let (output, _) = handle_single(&program, &args, &params, Some(r#"{"y": 2}"#)).unwrap();
+    assert_eq!(output, "3");
+}
+
+#[test]
+fn test_precheck_passes() {
+    let program = Program::compile("this.x").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.parallelism = 1;
+    params.precheck = Some(Arc::new(Program::compile("has(this.x)").unwrap()));
+
+    let reader = BufReader::new("{\"x\": 1}\n{\"x\": 2}\n".as_bytes());
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
+    assert_eq!(results.len(), 2);
+}
+
+#[test]
+fn test_precheck_failure_aborts_before_processing() {
+    let program = Program::compile("this.x").unwrap();
+    let args = BTreeMap::new();
+
+    for parallelism in [1, -1] {
+        let mut params = default_params();
+        params.parallelism = parallelism;
+        params.precheck = Some(Arc::new(Program::compile("this.version == 2").unwrap()));
+
+        let input = "{\"version\": 1, \"x\": 1}\n{\"version\": 2, \"x\": 2}\n";
+        let mut emitted = Vec::new();
+        let err = handle_buffer(
+            &program,
+            &args,
+            &params,
+            BufReader::new(input.as_bytes()),
+            &mut |record| {
+                emitted.push(formatted(record, &params).0);
+                Ok(())
+            },
+        )
+        .unwrap_err();
+
+        assert!(err.to_string().contains("--precheck failed"));
+        assert!(emitted.is_empty());
+    }
+}
+
+#[test]
+fn test_precheck_on_multi_line_document() {
+    let program = Program::compile("this.x").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.precheck = Some(Arc::new(Program::compile("this.x > 0").unwrap()));
+
+    let reader = BufReader::new("{\n  \"x\": 5\n}\n".as_bytes());
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
+    assert_eq!(results, vec![("5".to_string(), true)]);
+}
+
+fn indexed(input: &str, index_dup: IndexDup) -> Result<Vec<(String, bool)>> {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("records.json");
+    std::fs::write(&path, input).unwrap();
+
+    let program = Program::compile("this.name").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.files = vec![path];
+    params.index_by = Some(Arc::new(Program::compile("this.id").unwrap()));
+    params.index_dup = index_dup;
+
+    collect_input(&program, &args, &params)
+}
+
+#[test]
+fn test_index_by_collects_one_object() {
+    let input = "{\"id\": \"a\", \"name\": \"Ada\"}\n{\"id\": 7, \"name\": \"Grace\"}\n";
+    let results = indexed(input, IndexDup::Last).unwrap();
+
+    assert_eq!(
+        results,
+        vec![(r#"{"a":"Ada","7":"Grace"}"#.to_string(), true)]
+    );
+}
+
+#[test]
+fn test_index_by_duplicates() {
+    let input = "{\"id\": 1, \"name\": \"first\"}\n{\"id\": 1, \"name\": \"second\"}\n";
+
+    let results = indexed(input, IndexDup::First).unwrap();
+    assert_eq!(results[0].0, r#"{"1":"first"}"#);
+
+    let results = indexed(input, IndexDup::Last).unwrap();
+    assert_eq!(results[0].0, r#"{"1":"second"}"#);
+
+    let err = indexed(input, IndexDup::Error).unwrap_err();
+    assert!(format!("{:#}", err).contains("Duplicate --index-by key '1'"));
+}
+
+#[test]
+fn test_index_by_line() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("records.json");
+    std::fs::write(&path, "{\"x\": 1}\n{\"x\": 2}\n\n{\"x\": 3}\n").unwrap();
+
+    let program = Program::compile("this.x * 10").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.files = vec![path];
+    params.index_by_line = true;
+
+    for parallelism in [1, 4] {
+        params.parallelism = parallelism;
+        let results = collect_input(&program, &args, &params).unwrap();
+        assert_eq!(
+            results,
+            vec![(r#"{"1":10,"2":20,"4":30}"#.to_string(), true)]
+        );
+    }
+}
+
+#[test]
+fn test_index_by_line_keeps_error_reports() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("records.json");
+    std::fs::write(&path, "{\"x\": 1}\n{\"x\": \"a\"}\n").unwrap();
+
+    let program = Program::compile("this.x * 10").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.files = vec![path];
+    params.index_by_line = true;
+    params.error_output = true;
+
+    let results = collect_input(&program, &args, &params).unwrap();
+    assert_eq!(results.len(), 1);
+    let index: serde_json::Value = serde_json::from_str(&results[0].0).unwrap();
+    assert_eq!(index["1"], 10);
+    assert_eq!(index["2"]["line"], 2);
+    assert_eq!(index["2"]["input"], "{\"x\": \"a\"}");
+}
+
+#[test]
+fn test_find_stops_at_first_truthy_result() {
+    let program = Program::compile("this.v > 3 ? this.v * 10 : false").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.find = true;
+
+    // Reading stops at the match, so the malformed line after it is never parsed
+    let input = "{\"v\": 1}\n{\"v\": 5}\n{\"v\": 9}\nnot json\n";
+    let results = collect_buffer(&program, &args, &params, BufReader::new(input.as_bytes()));
+    assert_eq!(results.unwrap(), vec![("50".to_string(), true)]);
+
+    params.find_input = true;
+    let results = collect_buffer(&program, &args, &params, BufReader::new(input.as_bytes()));
+    assert_eq!(results.unwrap(), vec![(r#"{"v":5}"#.to_string(), true)]);
+}
+
+#[test]
+fn test_find_without_match() {
+    let program = Program::compile("this.v > 3").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.find = true;
+
+    let reader = BufReader::new("{\"v\": 1}\n{\"v\": 2}\n".as_bytes());
+    let results = collect_buffer(&program, &args, &params, reader).unwrap();
+    assert!(results.is_empty());
+}
+
+#[test]
+fn test_fromjson_parses_string_results() {
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.fromjson = true;
+
+    let program = Program::compile(r#""{\"a\": [1, \"x\"]}""#).unwrap();
+    let (output, _) = handle_single(&program, &args, &params, None).unwrap();
+    assert_eq!(output, r#"{"a":[1,"x"]}"#);
+
+    // Non-string results are output as usual
+    let program = Program::compile("[1, 2]").unwrap();
+    let (output, _) = handle_single(&program, &args, &params, None).unwrap();
+    assert_eq!(output, "[1,2]");
+
+    let program = Program::compile(r#""not json""#).unwrap();
+    let err = handle_json(&program, &args, &params, None).unwrap_err();
+    assert!(err.to_string().contains("--fromjson"));
+}
+
+#[test]
+fn test_fromjson_then_raw_output() {
+    let program = Program::compile(r#""\"hi\"""#).unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.fromjson = true;
+    params.raw_output = true;
+
+    let (output, _) = handle_single(&program, &args, &params, None).unwrap();
+    assert_eq!(output, "hi");
+}
+
+#[test]
+fn test_expr_stages_form_a_pipeline() {
+    let program = Program::compile("this.items").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.stages = vec![
+        Arc::new(Program::compile("this.map(x, x.id)").unwrap()),
+        Arc::new(Program::compile("size(this)").unwrap()),
+    ];
+
+    let json = r#"{"items": [{"id": 1}, {"id": 2}]}"#;
+    let (output, _) = handle_single(&program, &args, &params, Some(json)).unwrap();
+    assert_eq!(output, "2");
+}
+
+#[test]
+fn test_expr_stage_error_names_stage() {
+    let program = Program::compile("this.items").unwrap();
+    let args = BTreeMap::new();
+    let mut params = default_params();
+    params.stages = vec![Arc::new(Program::compile("this.missing").unwrap())];
+
+    let err = handle_json(&program, &args, &params, Some(r#"{"items": {}}"#)).unwrap_err();
+    assert!(err.to_string().contains("--expr stage 2"));
+}
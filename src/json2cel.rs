@@ -4,16 +4,58 @@ use serde_json::Value as JsonValue;
 use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 
+/// How `--number-mode` converts JSON numbers to CEL numbers
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum NumberMode {
+    /// Convert numbers with a fractional part or exponent to floats and the rest to ints,
+    /// or uints if they do not fit
+    Auto,
+    /// Convert integral numbers, including ones like 1.0, to ints
+    Int,
+    /// Convert every number to a float
+    Float,
+}
+
+/// How `json_to_cel_variables` parses its input and binds the variables
+#[derive(Clone, Copy, Debug)]
+pub struct JsonOptions<'a> {
+    /// Variable name for the whole input
+    pub root_var: &'a str,
+    /// Read every line as a JSON value, and bind the list of them
+    pub slurp: bool,
+    /// Parse the input as JSON5 instead of JSON
+    pub from_json5: bool,
+    /// Parse the input as TOML instead of JSON
+    pub from_toml: bool,
+    /// Parse the input as YAML instead of JSON
+    pub from_yaml: bool,
+    /// How JSON numbers are converted to CEL numbers
+    pub number_mode: NumberMode,
+    /// Top-level fields that are also bound as standalone variables
+    pub expand_only: &'a [String],
+    /// Bind every top-level field as a standalone variable
+    pub expose_fields: bool,
+}
+
 /// Convert a JSON string into a BTreeMap of CEL values.
-/// The top-level JSON object is placed under the "this" key.
+/// The top-level JSON object is placed under the root variable, which takes precedence over
+/// any field bound as a standalone variable. Callers binding fields with `expose_fields`
+/// decide which of them are usable names.
 pub fn json_to_cel_variables(
     json_str: &str,
-    root_var: &str,
-    slurp: bool,
-    from_json5: bool,
-    from_toml: bool,
-    from_yaml: bool,
+    options: &JsonOptions,
 ) -> Result<BTreeMap<String, CelValue>, serde_json::Error> {
+    let JsonOptions {
+        root_var,
+        slurp,
+        from_json5,
+        from_toml,
+        from_yaml,
+        number_mode,
+        expand_only,
+        expose_fields,
+    } = *options;
+
     let json_value: JsonValue = if !slurp && !from_json5 && !from_toml && !from_yaml {
         serde_json::from_str(json_str)?
     } else if from_json5 {
@@ -50,27 +92,69 @@ pub fn json_to_cel_variables(
 
     let mut variables = BTreeMap::new();
 
+    // Bind the requested top-level fields, the root variable takes precedence
+    if let JsonValue::Object(map) = &json_value {
+        for field in expand_only {
+            if let Some(value) = map.get(field) {
+                variables.insert(
+                    field.clone(),
+                    json_value_to_cel_value_with_numbers(value, number_mode),
+                );
+            }
+        }
+
+        if expose_fields {
+            for (field, value) in map {
+                variables.insert(
+                    field.clone(),
+                    json_value_to_cel_value_with_numbers(value, number_mode),
+                );
+            }
+        }
+    }
+
     // Convert the entire JSON value and place it under "this"
-    let cel_value = json_value_to_cel_value(&json_value);
+    let cel_value = json_value_to_cel_value_with_numbers(&json_value, number_mode);
     variables.insert(root_var.to_string(), cel_value);
 
     Ok(variables)
 }
 
 /// Convert a serde_json::Value to a cel::objects::Value
-fn json_value_to_cel_value(value: &JsonValue) -> CelValue {
+pub fn json_value_to_cel_value(value: &JsonValue) -> CelValue {
+    json_value_to_cel_value_with_numbers(value, NumberMode::Auto)
+}
+
+/// Convert a serde_json::Value to a cel::objects::Value, converting numbers as `number_mode` says
+pub fn json_value_to_cel_value_with_numbers(
+    value: &JsonValue,
+    number_mode: NumberMode,
+) -> CelValue {
     match value {
         JsonValue::Null => CelValue::Null,
 
         JsonValue::Bool(b) => CelValue::Bool(*b),
 
         JsonValue::Number(n) => {
-            if let Some(i) = n.as_i64() {
+            if number_mode == NumberMode::Float
+                && let Some(f) = n.as_f64()
+            {
+                CelValue::Float(f)
+            } else if let Some(i) = n.as_i64() {
                 CelValue::Int(i)
             } else if let Some(u) = n.as_u64() {
                 CelValue::UInt(u)
             } else if let Some(f) = n.as_f64() {
-                CelValue::Float(f)
+                // Integral floats that fit are ints, e.g. 1.0 or 1e3
+                if number_mode == NumberMode::Int
+                    && f.fract() == 0.0
+                    && f >= i64::MIN as f64
+                    && f < i64::MAX as f64
+                {
+                    CelValue::Int(f as i64)
+                } else {
+                    CelValue::Float(f)
+                }
             } else {
                 // Fallback, should not happen
                 CelValue::Null
@@ -80,7 +164,10 @@ fn json_value_to_cel_value(value: &JsonValue) -> CelValue {
         JsonValue::String(s) => CelValue::String(Arc::new(s.clone())),
 
         JsonValue::Array(arr) => {
-            let cel_vec: Vec<CelValue> = arr.iter().map(json_value_to_cel_value).collect();
+            let cel_vec: Vec<CelValue> = arr
+                .iter()
+                .map(|item| json_value_to_cel_value_with_numbers(item, number_mode))
+                .collect();
             CelValue::List(Arc::new(cel_vec))
         }
 
@@ -88,7 +175,7 @@ fn json_value_to_cel_value(value: &JsonValue) -> CelValue {
             let mut cel_map = HashMap::new();
             for (key, val) in map {
                 let cel_key = Key::String(Arc::new(key.clone()));
-                let cel_val = json_value_to_cel_value(val);
+                let cel_val = json_value_to_cel_value_with_numbers(val, number_mode);
                 cel_map.insert(cel_key, cel_val);
             }
             CelValue::Map(cel_map.into())
@@ -96,6 +183,50 @@ fn json_value_to_cel_value(value: &JsonValue) -> CelValue {
     }
 }
 
+/// Find the first integer literal of a JSON text that fits neither an `i64` nor a `u64`
+///
+/// serde_json silently reads such integers as floats, so the text itself is scanned. Numbers
+/// with a fraction or an exponent are floats by intent and are never reported.
+pub fn find_out_of_range_integer(json_str: &str) -> Option<&str> {
+    let bytes = json_str.as_bytes();
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut idx = 0;
+
+    while idx < bytes.len() {
+        let byte = bytes[idx];
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            idx += 1;
+        } else if byte == b'"' {
+            in_string = true;
+            idx += 1;
+        } else if byte == b'-' || byte.is_ascii_digit() {
+            // Outside of strings, digits and minus signs only appear in numbers
+            let start = idx;
+            while idx < bytes.len()
+                && matches!(bytes[idx], b'0'..=b'9' | b'-' | b'+' | b'.' | b'e' | b'E')
+            {
+                idx += 1;
+            }
+            let number = &json_str[start..idx];
+            let is_integer = !number.contains(['.', 'e', 'E']);
+            if is_integer && number.parse::<i64>().is_err() && number.parse::<u64>().is_err() {
+                return Some(number);
+            }
+        } else {
+            idx += 1;
+        }
+    }
+
+    None
+}
+
 fn slurp_json_lines(json_str: Option<&str>) -> Result<JsonValue, serde_json::Error> {
     let mut values = Vec::new();
 
@@ -1,28 +1,31 @@
 use super::*;
 
-const ROOT_VAR: &str = "this";
-const NO_SLURP: bool = false;
-const NO_JSON5: bool = false;
-const NO_TOML: bool = false;
-const NO_YAML: bool = false;
+const OPTIONS: JsonOptions = JsonOptions {
+    root_var: "this",
+    slurp: false,
+    from_json5: false,
+    from_toml: false,
+    from_yaml: false,
+    number_mode: NumberMode::Auto,
+    expand_only: &[],
+    expose_fields: false,
+};
 
 #[test]
 fn test_null() {
-    let vars =
-        json_to_cel_variables("null", ROOT_VAR, NO_SLURP, NO_JSON5, NO_TOML, NO_YAML).unwrap();
+    let vars = json_to_cel_variables("null", &OPTIONS).unwrap();
     assert!(matches!(vars.get("this").unwrap(), CelValue::Null));
 }
 
 #[test]
 fn test_number() {
-    let vars = json_to_cel_variables("42", ROOT_VAR, NO_SLURP, NO_JSON5, NO_TOML, NO_YAML).unwrap();
+    let vars = json_to_cel_variables("42", &OPTIONS).unwrap();
     assert!(matches!(vars.get("this").unwrap(), CelValue::Int(42)));
 }
 
 #[test]
 fn test_string() {
-    let vars = json_to_cel_variables(r#""hello""#, ROOT_VAR, NO_SLURP, NO_JSON5, NO_TOML, NO_YAML)
-        .unwrap();
+    let vars = json_to_cel_variables(r#""hello""#, &OPTIONS).unwrap();
     if let CelValue::String(s) = vars.get("this").unwrap() {
         assert_eq!(s.as_str(), "hello");
     } else {
@@ -32,15 +35,13 @@ fn test_string() {
 
 #[test]
 fn test_bool() {
-    let vars =
-        json_to_cel_variables("true", ROOT_VAR, NO_SLURP, NO_JSON5, NO_TOML, NO_YAML).unwrap();
+    let vars = json_to_cel_variables("true", &OPTIONS).unwrap();
     assert!(matches!(vars.get("this").unwrap(), CelValue::Bool(true)));
 }
 
 #[test]
 fn test_array() {
-    let vars =
-        json_to_cel_variables("[1, 2, 3]", ROOT_VAR, NO_SLURP, NO_JSON5, NO_TOML, NO_YAML).unwrap();
+    let vars = json_to_cel_variables("[1, 2, 3]", &OPTIONS).unwrap();
     if let CelValue::List(list) = vars.get("this").unwrap() {
         assert_eq!(list.len(), 3);
     } else {
@@ -50,15 +51,7 @@ fn test_array() {
 
 #[test]
 fn test_object() {
-    let vars = json_to_cel_variables(
-        r#"{"x": 10, "y": 20}"#,
-        ROOT_VAR,
-        NO_SLURP,
-        NO_JSON5,
-        NO_TOML,
-        NO_YAML,
-    )
-    .unwrap();
+    let vars = json_to_cel_variables(r#"{"x": 10, "y": 20}"#, &OPTIONS).unwrap();
 
     // Should have "this"
     assert_eq!(vars.len(), 1);
@@ -69,15 +62,7 @@ fn test_object() {
 
 #[test]
 fn test_nested_object() {
-    let vars = json_to_cel_variables(
-        r#"{"outer": {"inner": 42}}"#,
-        ROOT_VAR,
-        NO_SLURP,
-        NO_JSON5,
-        NO_TOML,
-        NO_YAML,
-    )
-    .unwrap();
+    let vars = json_to_cel_variables(r#"{"outer": {"inner": 42}}"#, &OPTIONS).unwrap();
 
     // Should have "this"
     assert_eq!(vars.len(), 1);
@@ -107,8 +92,14 @@ fn test_json5_with_comment() {
         "x": 42
     }
     "#;
-    let vars =
-        json_to_cel_variables(json5_input, ROOT_VAR, NO_SLURP, true, NO_TOML, NO_YAML).unwrap();
+    let vars = json_to_cel_variables(
+        json5_input,
+        &JsonOptions {
+            from_json5: true,
+            ..OPTIONS
+        },
+    )
+    .unwrap();
 
     if let CelValue::Map(map) = vars.get("this").unwrap() {
         let x_key = Key::String(Arc::new("x".to_string()));
@@ -117,3 +108,136 @@ fn test_json5_with_comment() {
         panic!("Expected map");
     }
 }
+
+#[test]
+fn test_expand_only() {
+    let expand = vec!["x".to_string(), "missing".to_string()];
+    let vars = json_to_cel_variables(
+        r#"{"x": 10, "y": 20}"#,
+        &JsonOptions {
+            expand_only: &expand,
+            ..OPTIONS
+        },
+    )
+    .unwrap();
+
+    // Only "x" is bound next to "this"
+    assert_eq!(vars.len(), 2);
+    assert!(matches!(vars.get("x").unwrap(), CelValue::Int(10)));
+    assert!(!vars.contains_key("y"));
+    assert!(!vars.contains_key("missing"));
+    assert!(matches!(vars.get("this").unwrap(), CelValue::Map(_)));
+}
+
+#[test]
+fn test_expand_only_root_var_wins() {
+    let expand = vec!["this".to_string()];
+    let vars = json_to_cel_variables(
+        r#"{"this": 1}"#,
+        &JsonOptions {
+            expand_only: &expand,
+            ..OPTIONS
+        },
+    )
+    .unwrap();
+
+    assert!(matches!(vars.get("this").unwrap(), CelValue::Map(_)));
+}
+
+#[test]
+fn test_expose_fields() {
+    let vars = json_to_cel_variables(
+        r#"{"x": 10, "this": 30, "not-ident": 40}"#,
+        &JsonOptions {
+            expose_fields: true,
+            ..OPTIONS
+        },
+    )
+    .unwrap();
+
+    // Every field is bound, but the root variable takes precedence
+    assert_eq!(vars.len(), 3);
+    assert!(matches!(vars.get("x").unwrap(), CelValue::Int(10)));
+    assert!(matches!(vars.get("not-ident").unwrap(), CelValue::Int(40)));
+    assert!(matches!(vars.get("this").unwrap(), CelValue::Map(_)));
+}
+
+#[test]
+fn test_number_mode_float() {
+    let vars = json_to_cel_variables(
+        "[1, 1.5, 18446744073709551615]",
+        &JsonOptions {
+            number_mode: NumberMode::Float,
+            ..OPTIONS
+        },
+    )
+    .unwrap();
+
+    let CelValue::List(list) = vars.get("this").unwrap() else {
+        panic!("Expected list");
+    };
+    assert_eq!(
+        list.as_slice(),
+        &[
+            CelValue::Float(1.0),
+            CelValue::Float(1.5),
+            CelValue::Float(18446744073709551615.0)
+        ]
+    );
+}
+
+#[test]
+fn test_number_mode_int() {
+    let vars = json_to_cel_variables(
+        "[1.0, 1e3, 1.5, -2.0, 18446744073709551615, 1e300]",
+        &JsonOptions {
+            number_mode: NumberMode::Int,
+            ..OPTIONS
+        },
+    )
+    .unwrap();
+
+    // Only integral values that fit in an int are converted
+    let CelValue::List(list) = vars.get("this").unwrap() else {
+        panic!("Expected list");
+    };
+    assert_eq!(
+        list.as_slice(),
+        &[
+            CelValue::Int(1),
+            CelValue::Int(1000),
+            CelValue::Float(1.5),
+            CelValue::Int(-2),
+            CelValue::UInt(u64::MAX),
+            CelValue::Float(1e300)
+        ]
+    );
+}
+
+#[test]
+fn test_find_out_of_range_integer_boundaries() {
+    assert_eq!(find_out_of_range_integer("-9223372036854775808"), None);
+    assert_eq!(
+        find_out_of_range_integer("-9223372036854775809"),
+        Some("-9223372036854775809")
+    );
+    assert_eq!(find_out_of_range_integer("18446744073709551615"), None);
+    assert_eq!(
+        find_out_of_range_integer(r#"{"id": 18446744073709551616}"#),
+        Some("18446744073709551616")
+    );
+}
+
+#[test]
+fn test_find_out_of_range_integer_ignores_floats_and_strings() {
+    assert_eq!(
+        find_out_of_range_integer(r#"[1e300, -1.5E30, 18446744073709551616.0]"#),
+        None
+    );
+    assert_eq!(
+        find_out_of_range_integer(
+            r#"{"big": "18446744073709551616", "q\"": "-99999999999999999999"}"#
+        ),
+        None
+    );
+}
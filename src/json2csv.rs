@@ -0,0 +1,119 @@
+#[cfg(feature = "to-csv")]
+use anyhow::Context;
+use anyhow::{Result, bail};
+use serde_json::Value as JsonValue;
+use std::collections::BTreeSet;
+
+/// Render a JSON list of objects as CSV, with a header row
+///
+/// # Arguments
+/// * `value` - The JSON list holding the rows
+///
+/// # Returns
+/// * Ok(String) - The header and one line per row. The columns are the union of the keys of
+///   every row, in sorted order
+/// * Err(anyhow::Error) - If the value is not a list of objects
+pub fn json_to_csv(value: &JsonValue) -> Result<String> {
+    let JsonValue::Array(rows) = value else {
+        bail!("--csv-output requires every result to be a list of maps");
+    };
+
+    // CEL maps are unordered, so the columns are sorted to keep the header stable
+    let mut columns = BTreeSet::new();
+    for row in rows {
+        let JsonValue::Object(row) = row else {
+            bail!("--csv-output requires every result to be a list of maps");
+        };
+        columns.extend(row.keys().map(String::as_str));
+    }
+
+    write_csv(&columns.into_iter().collect::<Vec<_>>(), rows)
+}
+
+#[cfg(feature = "to-csv")]
+fn write_csv(columns: &[&str], rows: &[JsonValue]) -> Result<String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    if !columns.is_empty() {
+        writer
+            .write_record(columns)
+            .context("Failed to write CSV header")?;
+    }
+
+    for row in rows {
+        // Keys missing from a row are written as empty cells
+        let cells = columns
+            .iter()
+            .map(|column| row.get(column).map_or_else(String::new, csv_cell));
+        writer
+            .write_record(cells)
+            .context("Failed to write CSV row")?;
+    }
+
+    let bytes = writer.into_inner().context("Failed to write CSV")?;
+    let csv = String::from_utf8(bytes).context("CSV output is not valid UTF-8")?;
+    Ok(csv.trim_end_matches('\n').to_string())
+}
+
+#[cfg(not(feature = "to-csv"))]
+fn write_csv(_columns: &[&str], _rows: &[JsonValue]) -> Result<String> {
+    bail!("Binary was compiled without CSV support");
+}
+
+/// Render a JSON value as a CSV cell. Lists and maps are written as their JSON text
+#[cfg(feature = "to-csv")]
+fn csv_cell(value: &JsonValue) -> String {
+    match value {
+        JsonValue::Null => String::new(),
+        JsonValue::String(s) => s.clone(),
+        _ => value.to_string(),
+    }
+}
+
+/// Render a JSON list of scalars as one line of tab-separated fields, like jq's `@tsv`
+///
+/// Backslashes, tabs, newlines, and carriage returns in strings are escaped as `\\`, `\t`, `\n`,
+/// and `\r`, so every result stays on one line. `null` is written as an empty field.
+///
+/// # Arguments
+/// * `value` - The JSON list holding the fields
+///
+/// # Returns
+/// * Ok(String) - The fields joined by tabs
+/// * Err(anyhow::Error) - If the value is not a list, or contains lists or maps
+pub fn json_to_tsv(value: &JsonValue) -> Result<String> {
+    let JsonValue::Array(fields) = value else {
+        bail!("--tab requires every result to be a list");
+    };
+
+    let fields = fields
+        .iter()
+        .map(|field| match field {
+            JsonValue::Null => Ok(String::new()),
+            JsonValue::String(s) => Ok(escape_tsv(s)),
+            JsonValue::Bool(_) | JsonValue::Number(_) => Ok(field.to_string()),
+            JsonValue::Array(_) | JsonValue::Object(_) => {
+                bail!("--tab cannot output nested lists or maps")
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(fields.join("\t"))
+}
+
+fn escape_tsv(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+#[path = "json2csv_test.rs"]
+mod test;
@@ -0,0 +1,60 @@
+use super::*;
+use serde_json::json;
+
+#[test]
+#[cfg(feature = "to-csv")]
+fn test_header_is_union_of_keys() {
+    let rows = json!([{"name": "Ada", "age": 36}, {"name": "Grace", "team": "navy"}]);
+    assert_eq!(
+        json_to_csv(&rows).unwrap(),
+        "age,name,team\n36,Ada,\n,Grace,navy"
+    );
+}
+
+#[test]
+#[cfg(feature = "to-csv")]
+fn test_cells_are_quoted_when_needed() {
+    let rows = json!([{"name": "Smith, J", "quote": "say \"hi\"", "missing": null}]);
+    assert_eq!(
+        json_to_csv(&rows).unwrap(),
+        "missing,name,quote\n,\"Smith, J\",\"say \"\"hi\"\"\""
+    );
+}
+
+#[test]
+#[cfg(feature = "to-csv")]
+fn test_nested_values_are_json_encoded() {
+    let rows = json!([{"tags": ["a", "b"], "active": true}]);
+    assert_eq!(
+        json_to_csv(&rows).unwrap(),
+        "active,tags\ntrue,\"[\"\"a\"\",\"\"b\"\"]\""
+    );
+}
+
+#[test]
+fn test_requires_list_of_maps() {
+    assert!(json_to_csv(&json!({"a": 1})).is_err());
+    assert!(json_to_csv(&json!([{"a": 1}, 2])).is_err());
+}
+
+#[test]
+fn test_tsv_fields() {
+    let fields = json!(["Ada", 36, true, null, 1.5]);
+    assert_eq!(json_to_tsv(&fields).unwrap(), "Ada\t36\ttrue\t\t1.5");
+}
+
+#[test]
+fn test_tsv_escapes_separators() {
+    let fields = json!(["a\tb", "line\nbreak\r", "back\\slash"]);
+    assert_eq!(
+        json_to_tsv(&fields).unwrap(),
+        "a\\tb\tline\\nbreak\\r\tback\\\\slash"
+    );
+}
+
+#[test]
+fn test_tsv_rejects_nested_values() {
+    assert!(json_to_tsv(&json!(["a", [1]])).is_err());
+    assert!(json_to_tsv(&json!(["a", {"b": 1}])).is_err());
+    assert!(json_to_tsv(&json!("a")).is_err());
+}
@@ -0,0 +1,75 @@
+use anyhow::{Result, bail};
+use serde_json::Value as JsonValue;
+
+/// Render a JSON object as an SQL `INSERT` statement
+///
+/// # Arguments
+/// * `value` - The JSON object holding the row
+/// * `table` - Name of the table, written as given
+/// * `columns` - Columns to insert, in order. If empty, the keys of the object are used in sorted order
+///
+/// # Returns
+/// * Ok(String) - The `INSERT INTO ... VALUES (...);` statement
+/// * Err(anyhow::Error) - If the value is not an object
+pub fn json_to_sql_insert(value: &JsonValue, table: &str, columns: &[String]) -> Result<String> {
+    let JsonValue::Object(row) = value else {
+        bail!("--sql-insert requires every result to be a map");
+    };
+
+    let columns: Vec<&str> = if columns.is_empty() {
+        let mut keys: Vec<&str> = row.keys().map(String::as_str).collect();
+        keys.sort();
+        keys
+    } else {
+        columns.iter().map(String::as_str).collect()
+    };
+
+    let column_list: Vec<String> = columns.iter().map(|c| quote_identifier(c)).collect();
+    // Columns missing from the row are inserted as NULL
+    let value_list: Vec<String> = columns
+        .iter()
+        .map(|c| row.get(*c).map_or_else(|| "NULL".to_string(), sql_literal))
+        .collect();
+
+    Ok(format!(
+        "INSERT INTO {} ({}) VALUES ({});",
+        table,
+        column_list.join(", "),
+        value_list.join(", ")
+    ))
+}
+
+/// Quote an identifier with double quotes, unless it is a plain identifier
+fn quote_identifier(name: &str) -> String {
+    let mut chars = name.chars();
+    let is_plain = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+    if is_plain {
+        name.to_string()
+    } else {
+        format!("\"{}\"", name.replace('"', "\"\""))
+    }
+}
+
+/// Render a JSON value as an SQL literal. Lists and maps are inserted as their JSON text
+fn sql_literal(value: &JsonValue) -> String {
+    match value {
+        JsonValue::Null => "NULL".to_string(),
+        JsonValue::Bool(true) => "TRUE".to_string(),
+        JsonValue::Bool(false) => "FALSE".to_string(),
+        JsonValue::Number(n) => n.to_string(),
+        JsonValue::String(s) => quote_string(s),
+        JsonValue::Array(_) | JsonValue::Object(_) => quote_string(&value.to_string()),
+    }
+}
+
+fn quote_string(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+#[cfg(test)]
+#[path = "json2sql_test.rs"]
+mod test;
@@ -0,0 +1,40 @@
+use super::*;
+use serde_json::json;
+
+#[test]
+fn test_apostrophe_is_escaped() {
+    let row = json!({"name": "O'Brien", "age": 42});
+    assert_eq!(
+        json_to_sql_insert(&row, "people", &[]).unwrap(),
+        "INSERT INTO people (age, name) VALUES (42, 'O''Brien');"
+    );
+}
+
+#[test]
+fn test_explicit_columns() {
+    let row = json!({"name": "Ada", "active": true, "tags": ["a", "b"]});
+    let columns = vec![
+        "name".to_string(),
+        "tags".to_string(),
+        "active".to_string(),
+        "email".to_string(),
+    ];
+    assert_eq!(
+        json_to_sql_insert(&row, "users", &columns).unwrap(),
+        r#"INSERT INTO users (name, tags, active, email) VALUES ('Ada', '["a","b"]', TRUE, NULL);"#
+    );
+}
+
+#[test]
+fn test_identifiers_are_quoted_when_needed() {
+    let row = json!({"first name": null, "say \"hi\"": 1.5});
+    assert_eq!(
+        json_to_sql_insert(&row, "t", &[]).unwrap(),
+        r#"INSERT INTO t ("first name", "say ""hi""") VALUES (NULL, 1.5);"#
+    );
+}
+
+#[test]
+fn test_non_object_is_rejected() {
+    assert!(json_to_sql_insert(&json!([1, 2]), "t", &[]).is_err());
+}
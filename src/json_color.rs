@@ -0,0 +1,91 @@
+use serde_json::Value as JsonValue;
+
+// The same colors as jq's defaults
+const NULL_COLOR: &str = "1;30";
+const SCALAR_COLOR: &str = "0;39";
+const STRING_COLOR: &str = "0;32";
+const CONTAINER_COLOR: &str = "1;39";
+const KEY_COLOR: &str = "34;1";
+
+/// Render a JSON value with ANSI colors, laid out like serde_json does
+///
+/// # Arguments
+/// * `value` - The JSON value to render
+/// * `indent` - The string to indent each level with, or None for a single line
+///
+/// # Returns
+/// The colored JSON text. Stripping the escape sequences gives the same text as
+/// `serde_json::to_string` or its pretty-printing equivalent
+pub fn to_colored_string(value: &JsonValue, indent: Option<&str>) -> String {
+    let mut out = String::new();
+    write_value(&mut out, value, indent, 0);
+    out
+}
+
+fn write_value(out: &mut String, value: &JsonValue, indent: Option<&str>, depth: usize) {
+    match value {
+        JsonValue::Null => paint(out, NULL_COLOR, "null"),
+        JsonValue::Bool(b) => paint(out, SCALAR_COLOR, if *b { "true" } else { "false" }),
+        JsonValue::Number(n) => paint(out, SCALAR_COLOR, &n.to_string()),
+        JsonValue::String(s) => paint(out, STRING_COLOR, &quote(s)),
+        JsonValue::Array(items) => {
+            paint(out, CONTAINER_COLOR, "[");
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    paint(out, CONTAINER_COLOR, ",");
+                }
+                newline(out, indent, depth + 1);
+                write_value(out, item, indent, depth + 1);
+            }
+            if !items.is_empty() {
+                newline(out, indent, depth);
+            }
+            paint(out, CONTAINER_COLOR, "]");
+        }
+        JsonValue::Object(fields) => {
+            paint(out, CONTAINER_COLOR, "{");
+            for (i, (key, field)) in fields.iter().enumerate() {
+                if i > 0 {
+                    paint(out, CONTAINER_COLOR, ",");
+                }
+                newline(out, indent, depth + 1);
+                paint(out, KEY_COLOR, &quote(key));
+                paint(
+                    out,
+                    CONTAINER_COLOR,
+                    if indent.is_some() { ": " } else { ":" },
+                );
+                write_value(out, field, indent, depth + 1);
+            }
+            if !fields.is_empty() {
+                newline(out, indent, depth);
+            }
+            paint(out, CONTAINER_COLOR, "}");
+        }
+    }
+}
+
+fn paint(out: &mut String, color: &str, text: &str) {
+    out.push_str("\x1b[");
+    out.push_str(color);
+    out.push('m');
+    out.push_str(text);
+    out.push_str("\x1b[0m");
+}
+
+/// Start a new line indented to `depth`, when pretty-printing
+fn newline(out: &mut String, indent: Option<&str>, depth: usize) {
+    if let Some(indent) = indent {
+        out.push('\n');
+        out.push_str(&indent.repeat(depth));
+    }
+}
+
+fn quote(s: &str) -> String {
+    // Serializing a string cannot fail
+    serde_json::to_string(s).unwrap_or_default()
+}
+
+#[cfg(test)]
+#[path = "json_color_test.rs"]
+mod test;
@@ -0,0 +1,49 @@
+use super::*;
+use serde_json::json;
+
+fn strip_colors(s: &str) -> String {
+    let mut out = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            chars.by_ref().find(|&c| c == 'm');
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+#[test]
+fn test_colors_scalars_like_jq() {
+    assert_eq!(
+        to_colored_string(&json!(null), None),
+        "\x1b[1;30mnull\x1b[0m"
+    );
+    assert_eq!(to_colored_string(&json!(1.5), None), "\x1b[0;39m1.5\x1b[0m");
+    assert_eq!(
+        to_colored_string(&json!("a\"b"), None),
+        "\x1b[0;32m\"a\\\"b\"\x1b[0m"
+    );
+}
+
+#[test]
+fn test_colors_keys() {
+    assert_eq!(
+        to_colored_string(&json!({"a": true}), None),
+        "\x1b[1;39m{\x1b[0m\x1b[34;1m\"a\"\x1b[0m\x1b[1;39m:\x1b[0m\x1b[0;39mtrue\x1b[0m\x1b[1;39m}\x1b[0m"
+    );
+}
+
+#[test]
+fn test_layout_matches_serde_json() {
+    let value = json!({"name": "Ada", "tags": ["x", 1, null], "empty": [], "nested": {"n": {}}});
+    assert_eq!(
+        strip_colors(&to_colored_string(&value, None)),
+        serde_json::to_string(&value).unwrap()
+    );
+    assert_eq!(
+        strip_colors(&to_colored_string(&value, Some("  "))),
+        serde_json::to_string_pretty(&value).unwrap()
+    );
+}
@@ -0,0 +1,55 @@
+use serde_json::{Map, Value as JsonValue, json};
+
+/// Compute an RFC 6902 JSON Patch that transforms `before` into `after`.
+/// Objects are compared key by key in sorted key order, any other differing
+/// value is replaced as a whole.
+pub fn json_diff(before: &JsonValue, after: &JsonValue) -> Vec<JsonValue> {
+    let mut operations = Vec::new();
+    diff_values(before, after, "", &mut operations);
+    operations
+}
+
+fn diff_values(before: &JsonValue, after: &JsonValue, path: &str, ops: &mut Vec<JsonValue>) {
+    match (before, after) {
+        (JsonValue::Object(before_map), JsonValue::Object(after_map)) => {
+            diff_objects(before_map, after_map, path, ops);
+        }
+        _ if before != after => {
+            ops.push(json!({"op": "replace", "path": path, "value": after}));
+        }
+        _ => {}
+    }
+}
+
+fn diff_objects(
+    before: &Map<String, JsonValue>,
+    after: &Map<String, JsonValue>,
+    path: &str,
+    ops: &mut Vec<JsonValue>,
+) {
+    let mut removed: Vec<&String> = before.keys().filter(|k| !after.contains_key(*k)).collect();
+    removed.sort();
+    for key in removed {
+        let child_path = format!("{}/{}", path, escape_pointer_token(key));
+        ops.push(json!({"op": "remove", "path": child_path}));
+    }
+
+    let mut keys: Vec<&String> = after.keys().collect();
+    keys.sort();
+    for key in keys {
+        let child_path = format!("{}/{}", path, escape_pointer_token(key));
+        match before.get(key) {
+            Some(before_value) => diff_values(before_value, &after[key], &child_path, ops),
+            None => ops.push(json!({"op": "add", "path": child_path, "value": after[key]})),
+        }
+    }
+}
+
+/// Escape a key as a JSON Pointer reference token (RFC 6901)
+fn escape_pointer_token(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+#[cfg(test)]
+#[path = "json_diff_test.rs"]
+mod test;
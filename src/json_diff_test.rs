@@ -0,0 +1,41 @@
+use super::*;
+
+#[test]
+fn test_identical_values() {
+    let value = json!({"a": 1, "b": [1, 2]});
+    assert!(json_diff(&value, &value).is_empty());
+}
+
+#[test]
+fn test_added_field() {
+    let before = json!({"a": 1});
+    let after = json!({"a": 1, "b": 2});
+    assert_eq!(
+        json_diff(&before, &after),
+        vec![json!({"op": "add", "path": "/b", "value": 2})]
+    );
+}
+
+#[test]
+fn test_removed_and_replaced_fields() {
+    let before = json!({"a": 1, "b": 2, "c": {"d": true}});
+    let after = json!({"a": 3, "c": {"d": false}});
+    assert_eq!(
+        json_diff(&before, &after),
+        vec![
+            json!({"op": "remove", "path": "/b"}),
+            json!({"op": "replace", "path": "/a", "value": 3}),
+            json!({"op": "replace", "path": "/c/d", "value": false}),
+        ]
+    );
+}
+
+#[test]
+fn test_escaped_pointer() {
+    let before = json!({});
+    let after = json!({"a/b~c": 1});
+    assert_eq!(
+        json_diff(&before, &after),
+        vec![json!({"op": "add", "path": "/a~1b~0c", "value": 1})]
+    );
+}
@@ -1,38 +1,133 @@
 use anyhow::{Context, Result};
-use cel::Program;
-use cel::parser::ParseErrors;
+use cel::parser::{ParseError, ParseErrors};
+use cel::{Program, Value as CelValue};
 use clap::Parser;
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::io::{BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering as AtomicOrdering};
+use std::time::SystemTime;
 use std::{fs, io, process};
+use tempfile::NamedTempFile;
 
 mod args2cel;
 mod cel2json;
 mod cli;
+mod complexity;
+mod functions;
 mod input_handler;
 mod json2cel;
+mod json2csv;
+mod json2sql;
+mod json_color;
+mod json_diff;
+mod optimizer;
+mod output_handler;
 
-use args2cel::args_to_cel_variables;
+use args2cel::{
+    CELQ_ARGS_VAR, arg_file_to_cel_variables, args_to_cel_variables, celq_args_variable,
+    env_args_to_cel_variables, positional_args_to_cel_values,
+};
 pub use cel2json::cel_value_to_json_value;
 pub use cli::Argument;
 use cli::Cli;
+use cli::ColorMode;
+use cli::FollowStop;
 pub use cli::InputParameters;
+use cli::RegexCache;
+use cli::SkippedFields;
 use input_handler::handle_input;
 pub use json2cel::json_to_cel_variables;
+use output_handler::{DedupeWindow, OutputHandler};
 
 #[cfg(feature = "mimalloc")]
 #[global_allocator]
 static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
 fn main() -> io::Result<()> {
-    let cli = Cli::parse();
+    let mut cli = Cli::parse();
 
-    // Compile the CEL program
-    let program = match compile_expression(cli.expression.as_deref(), cli.from_file.as_ref()) {
-        Ok(prog) => prog,
+    // Convert CLI arguments to CEL variables, so unreadable @file values fail before compiling
+    let to_tuples = |args: &[Argument]| -> Vec<(String, String, String)> {
+        args.iter()
+            .map(|a| {
+                let type_name = a.type_name.as_ref().unwrap_or(&cli.arg_type_default);
+                (a.name.clone(), type_name.clone(), a.value.clone())
+            })
+            .collect()
+    };
+    let arg_tuples = to_tuples(&cli.args);
+    let env_arg_tuples = to_tuples(&cli.arg_envs);
+
+    // Argument files are applied first and arguments from the environment last, so explicit
+    // arguments win over files and the environment wins over both
+    let arg_variables = cli
+        .arg_files
+        .iter()
+        .try_fold(BTreeMap::new(), |mut vars, path| {
+            vars.extend(arg_file_to_cel_variables(path)?);
+            anyhow::Ok(vars)
+        })
+        .and_then(|mut vars| {
+            vars.extend(args_to_cel_variables(&arg_tuples)?);
+            vars.extend(env_args_to_cel_variables(&env_arg_tuples)?);
+            Ok(vars)
+        })
+        .and_then(|mut vars| {
+            // With --args or --jsonargs the positional arguments are values, not input files
+            if cli.positional_args || cli.positional_jsonargs {
+                let positional = cli
+                    .files
+                    .drain(..)
+                    .map(|path| {
+                        path.into_os_string().into_string().map_err(|arg| {
+                            anyhow::anyhow!(
+                                "positional argument {} is not valid UTF-8",
+                                arg.to_string_lossy()
+                            )
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let positional =
+                    positional_args_to_cel_values(&positional, cli.positional_jsonargs)?;
+                // An explicit argument with the same name takes precedence
+                if !vars.contains_key(CELQ_ARGS_VAR) {
+                    let celq_args = celq_args_variable(&vars, positional);
+                    vars.insert(CELQ_ARGS_VAR.to_string(), celq_args);
+                }
+            }
+            Ok(vars)
+        });
+    // --input paths are read before the input files, and are never positional arguments
+    cli.files.splice(0..0, cli.input.drain(..));
+
+    let arg_variables = match arg_variables {
+        Ok(vars) => vars,
+        Err(e) => {
+            eprintln!("Argument conversion failed: {}", e);
+            process::exit(2);
+        }
+    };
+
+    // Compile the CEL program, and with --expr the stages of the pipeline after it
+    let (first_expr, stage_exprs) = match cli.exprs.split_first() {
+        Some((first, rest)) => (Some(first.as_str()), rest),
+        None => (cli.expression.as_deref(), &[][..]),
+    };
+    let compiled = compile_expression(first_expr, cli.from_file.as_ref()).and_then(|program| {
+        let stages = stage_exprs
+            .iter()
+            .map(|expr| Program::compile(expr).map_err(|e| anyhow::anyhow!(e)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok((program, stages))
+    });
+    let (program, stages) = match compiled {
+        Ok(compiled) => compiled,
         Err(err) => {
             if let Some(parse_errors) = err.downcast_ref::<ParseErrors>() {
                 for error in &parse_errors.errors {
-                    eprintln!("  Error: {:?}", error);
+                    eprintln!("  Error: {}", format_parse_error(error));
                 }
             } else {
                 eprintln!("Error: {err:#}");
@@ -41,52 +136,327 @@ fn main() -> io::Result<()> {
         }
     };
 
-    // Convert CLI arguments to CEL variables
-    let arg_tuples: Vec<(String, String, String)> = cli
-        .args
-        .iter()
-        .map(|a| (a.name.clone(), a.type_name.clone(), a.value.clone()))
-        .collect();
+    // Each stage of a pipeline is checked and optimized on its own
+    let programs = || std::iter::once(&program).chain(&stages);
 
-    let arg_variables = match args_to_cel_variables(&arg_tuples) {
-        Ok(vars) => vars,
+    if let Some(max_complexity) = cli.max_complexity {
+        for program in programs() {
+            let complexity = complexity::complexity(program.expression());
+            if complexity > max_complexity {
+                eprintln!(
+                    "Error: expression complexity {} exceeds --max-complexity {}",
+                    complexity, max_complexity
+                );
+                process::exit(2);
+            }
+        }
+    }
+
+    if cli.check {
+        return Ok(());
+    }
+
+    if cli.optimize {
+        for program in programs() {
+            match optimizer::optimize(program) {
+                Ok(optimized) => println!("{}", optimized),
+                Err(e) => {
+                    eprintln!("Error: {e:#}");
+                    process::exit(2);
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    // The key expressions run once per record, so they are compiled up front
+    let index_by = match cli.index_by.as_deref().map(Program::compile).transpose() {
+        Ok(index_by) => index_by.map(Arc::new),
         Err(e) => {
-            eprintln!("Argument conversion failed: {}", e);
+            eprintln!("Error: failed to compile --index-by expression: {}", e);
+            process::exit(2);
+        }
+    };
+    let group_by = match cli.group_by.as_deref().map(Program::compile).transpose() {
+        Ok(group_by) => group_by.map(Arc::new),
+        Err(e) => {
+            eprintln!("Error: failed to compile --group-by expression: {}", e);
+            process::exit(2);
+        }
+    };
+    let presort_by = match cli.presort_by.as_deref().map(Program::compile).transpose() {
+        Ok(presort_by) => presort_by.map(Arc::new),
+        Err(e) => {
+            eprintln!("Error: failed to compile --presort-by expression: {}", e);
+            process::exit(2);
+        }
+    };
+    let precheck = match cli.precheck.as_deref().map(Program::compile).transpose() {
+        Ok(precheck) => precheck.map(Arc::new),
+        Err(e) => {
+            eprintln!("Error: failed to compile --precheck expression: {}", e);
             process::exit(2);
         }
     };
 
+    // Every variable the expressions reference is bound to null unless something else binds it,
+    // including the key and precheck expressions, which share the context of the program
+    let null_variables = if cli.null_for_missing {
+        let key_programs = [&presort_by, &index_by, &group_by, &precheck];
+        let mut names: Vec<String> = programs()
+            .chain(key_programs.into_iter().flatten().map(Arc::as_ref))
+            .flat_map(|program| {
+                let references = program.references();
+                references
+                    .variables()
+                    .into_iter()
+                    .map(str::to_string)
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    } else {
+        Vec::new()
+    };
+
+    // Files written by --in-place and raw bytes are never colored
+    let color = !cli.in_place
+        && !cli.output_raw_bytes
+        && match cli.color {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            ColorMode::Auto => {
+                std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+                    && cli.output_file.is_none()
+                    && io::stdout().is_terminal()
+            }
+        };
+
     let input_params = InputParameters {
         root_var: cli.root_var,
+        expand_only: cli.expand_only,
+        expose_fields: cli.expose_fields.then(SkippedFields::default),
+        null_variables,
         null_input: cli.null_input,
+        env_input: cli.env_input,
         slurp: cli.slurp,
         from_json5: cli.from_json5,
         from_toml: cli.from_toml,
         from_yaml: cli.from_yaml,
+        number_mode: cli.number_mode,
+        bignum_error: cli.bignum_error,
+        grok: cli.grok,
         parallelism: cli.parallelism,
+        presort_by,
+        group_by,
+        batch_size: cli.batch_size,
+        max_line_bytes: cli.max_line_bytes,
+        find: cli.find,
+        find_input: cli.find_input,
         sort_keys: cli.sort_keys,
+        numeric_key_sort: cli.numeric_key_sort,
         pretty_print: cli.pretty_print,
+        indent: if cli.tab_indent || cli.indent == 0 {
+            "\t".to_string()
+        } else {
+            " ".repeat(cli.indent)
+        },
         raw_output: cli.raw_output,
+        ascii_output: cli.ascii_output,
+        output_raw_bytes: cli.output_raw_bytes,
+        fromjson: cli.fromjson,
+        iso_durations: cli.iso_durations,
+        float_precision: cli.float_precision,
+        diff: cli.diff,
+        with_filename: cli.with_filename,
+        header_line: cli.header_line,
+        continue_on_error: cli.continue_on_error,
+        truncate_strings: cli.truncate_strings,
+        truncate_marker: cli.truncate_marker,
+        error_output: cli.error_output,
+        validate: cli.validate,
+        verbose: cli.verbose,
+        args_from_stdin_header: cli.args_from_stdin_header,
+        compact: cli.compact,
+        color,
+        type_of: cli.type_of,
+        limit: cli
+            .limit
+            .map(|limit| Arc::new(AtomicUsize::new(limit.get()))),
+        length_prefixed_input: cli.length_prefixed_input,
+        blank_separated: cli.blank_separated,
+        raw_input: cli.raw_input,
+        seq: cli.seq,
+        csv_input: cli.csv_input,
+        csv_infer_types: cli.csv_infer_types,
+        files: cli.files,
+        gzip: cli.gzip,
+        follow: cli.follow.then(FollowStop::default),
+        sql_insert: cli.sql_insert,
+        sql_columns: cli.sql_columns,
+        csv_output: cli.csv_output,
+        tab: cli.tab,
+        default_output: cli.default_output,
+        allow_env: cli.allow_env,
+        allow_fs: cli.allow_fs,
+        explode_keys: cli.explode_keys,
+        flatten: cli.flatten,
+        stamp: cli.stamp,
+        merge_args_into_root: cli.merge_args_into_root,
+        stages: stages.into_iter().map(Arc::new).collect(),
+        precheck,
+        index_by,
+        index_dup: cli.index_dup,
+        index_by_line: cli.index_by_line,
+        now: chrono::DateTime::<chrono::Utc>::from(SystemTime::now()).into(),
+        uuid_state: cli.seed.map(|seed| Arc::new(AtomicU64::new(seed))),
+        regex_cache: RegexCache::default(),
+    };
+
+    // Parallel mode evaluates many lines before any of them is output, so there is nothing to
+    // flush early
+    if cli.unbuffered && cli.parallelism != 1 {
+        eprintln!("Error: --unbuffered requires -j 1");
+        process::exit(2);
+    }
+
+    // Line numbers restart in every file, so records of different files would share keys
+    if cli.index_by_line && !cli.in_place && input_params.files.len() > 1 {
+        eprintln!("Error: --index-by-line requires a single input file");
+        process::exit(2);
+    }
+
+    if let Some(stop) = &input_params.follow {
+        if input_params.files.len() != 1 {
+            eprintln!("Error: --follow requires a single input file");
+            process::exit(2);
+        }
+        // Ctrl-C stops following instead of killing celq, so the last outputs are flushed
+        let stop = Arc::clone(stop);
+        if let Err(e) = ctrlc::set_handler(move || stop.store(true, AtomicOrdering::Relaxed)) {
+            eprintln!("Error: failed to handle Ctrl-C: {}", e);
+            process::exit(2);
+        }
+    }
+
+    // Print all outputs as they are produced, unless void, boolean or count mode is enabled
+    // --changes-only is a window holding just the previous output, and --dedup one holding all
+    let dedupe_window = || match cli.dedupe_window {
+        Some(window_size) => Some(DedupeWindow::new(window_size.get())),
+        None if cli.dedup => Some(DedupeWindow::unbounded()),
+        None => cli.changes_only.then(|| DedupeWindow::new(1)),
     };
+    let terminator = if cli.nul_output { b'\0' } else { b'\n' };
 
-    match handle_input(&program, &arg_variables, &input_params) {
-        Ok(results) => {
-            // Print all outputs, unless void mode is enabled
-            if !cli.void {
-                for (output, _) in &results {
-                    println!("{}", output);
+    // With --in-place every file gets its own outputs, so nothing is printed
+    if cli.in_place {
+        if input_params.files.is_empty() {
+            eprintln!("Error: --in-place requires input files");
+            process::exit(2);
+        }
+        let mut type_counts = BTreeMap::new();
+        for path in &input_params.files {
+            let new_output_handler = |writer| {
+                OutputHandler::new(
+                    writer,
+                    true,
+                    terminator,
+                    !cli.no_trailing_newline,
+                    dedupe_window(),
+                )
+            };
+            let result = rewrite_in_place(
+                &program,
+                &arg_variables,
+                &input_params,
+                path,
+                new_output_handler,
+            );
+            match result {
+                Ok(file_type_counts) => {
+                    for (type_name, count) in file_type_counts {
+                        *type_counts.entry(type_name).or_default() += count;
+                    }
                 }
+                Err(e) => {
+                    eprintln!("✗ Execution failed: {:#}", e);
+                    process::exit(2);
+                }
+            }
+        }
+        if cli.type_summary {
+            print_type_summary(&type_counts);
+        }
+        return Ok(());
+    }
+
+    // The file is only flushed at the end, so it is written in large blocks
+    let writer: Box<dyn Write> = match &cli.output_file {
+        Some(path) => match fs::File::create(path) {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(e) => {
+                eprintln!(
+                    "Error: failed to create output file `{}`: {}",
+                    path.display(),
+                    e
+                );
+                process::exit(2);
+            }
+        },
+        None => Box::new(io::stdout().lock()),
+    };
+    let mut output_handler = OutputHandler::new(
+        writer,
+        !cli.void && !cli.boolean && !cli.count,
+        terminator,
+        !cli.no_trailing_newline,
+        dedupe_window(),
+    );
+
+    let result = handle_input(&program, &arg_variables, &input_params, &mut |output| {
+        output_handler.emit(output)?;
+        if cli.unbuffered || cli.follow {
+            output_handler.flush()?;
+        }
+        Ok(())
+    })
+    .and_then(|()| {
+        if cli.count {
+            output_handler.write_truthy_count()?;
+        }
+        output_handler.flush()
+    });
+
+    match result {
+        Ok(()) => {
+            if cli.type_summary {
+                print_type_summary(output_handler.type_counts());
             }
 
-            // If boolean mode is enabled, exit with appropriate code based on last result
+            // In boolean mode the exit code is the output: 0 only if every result is truthy
             if cli.boolean {
-                let is_truthy = results.last().map(|(_, truthy)| *truthy).unwrap_or(false);
-                let exit_code = if is_truthy { 0 } else { 1 };
+                let exit_code = if output_handler.all_truthy() { 0 } else { 1 };
+                process::exit(exit_code);
+            }
+
+            // In validation mode only failures are output, and any of them fails the run
+            if cli.validate {
+                let exit_code = if output_handler.any_falsy() { 1 } else { 0 };
+                process::exit(exit_code);
+            }
+
+            // In exit status mode the output is kept, and only the last result decides the code
+            if cli.exit_status {
+                let exit_code = if output_handler.last_truthy() { 0 } else { 1 };
                 process::exit(exit_code);
             }
         }
         Err(e) => {
-            eprintln!("✗ Execution failed: {}", e);
+            // Outputs emitted before the failure have already been printed
+            let _ = output_handler.flush();
+            eprintln!("✗ Execution failed: {:#}", e);
             process::exit(2);
         }
     }
@@ -94,6 +464,111 @@ fn main() -> io::Result<()> {
     Ok(())
 }
 
+/// Replace an input file with the outputs of the CEL program over it, like `sed -i`
+///
+/// The outputs are written to a temporary file in the same directory, which is synced to disk
+/// and replaces the input by a rename once every record was processed. On any error the
+/// temporary file is removed, and the input is left untouched.
+///
+/// # Returns
+/// * Ok(BTreeMap) - The number of results of each CEL type written to the file
+/// * Err(anyhow::Error) - Any error that occurred
+fn rewrite_in_place(
+    program: &Program,
+    arg_variables: &BTreeMap<String, CelValue>,
+    input_params: &InputParameters,
+    path: &Path,
+    new_output_handler: impl FnOnce(BufWriter<NamedTempFile>) -> OutputHandler<BufWriter<NamedTempFile>>,
+) -> Result<BTreeMap<&'static str, usize>> {
+    // The rewritten file would be plain text under a name promising gzip
+    anyhow::ensure!(
+        path.extension().is_none_or(|ext| ext != "gz"),
+        "Cannot rewrite compressed file `{}` in place",
+        path.display()
+    );
+    anyhow::ensure!(
+        path.file_name().is_some(),
+        "`{}` is not a file",
+        path.display()
+    );
+
+    // The temporary file must be on the same filesystem as the input for the rename
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let temp_file = NamedTempFile::new_in(dir).with_context(|| {
+        format!(
+            "Failed to create a temporary file next to `{}`",
+            path.display()
+        )
+    })?;
+
+    let input_params = InputParameters {
+        files: vec![path.to_path_buf()],
+        ..input_params.clone()
+    };
+    let mut output_handler = new_output_handler(BufWriter::new(temp_file));
+    handle_input(program, arg_variables, &input_params, &mut |output| {
+        output_handler.emit(output)
+    })?;
+    let type_counts = output_handler.type_counts().clone();
+
+    // From here on, dropping the temporary file on an error removes it
+    let temp_file = output_handler
+        .into_writer()
+        .into_inner()
+        .map_err(|e| e.into_error())
+        .with_context(|| format!("Failed to write the outputs for `{}`", path.display()))?;
+    temp_file
+        .as_file()
+        .sync_all()
+        .with_context(|| format!("Failed to write the outputs for `{}`", path.display()))?;
+
+    // The rewritten file keeps the permissions of the original
+    let permissions = fs::metadata(path)?.permissions();
+    fs::set_permissions(temp_file.path(), permissions)?;
+    temp_file
+        .persist(path)
+        .with_context(|| format!("Failed to replace `{}`", path.display()))?;
+    Ok(type_counts)
+}
+
+/// Print how many results had each CEL type to stderr, one `type: count` line per type
+fn print_type_summary(type_counts: &BTreeMap<&'static str, usize>) {
+    for (type_name, count) in type_counts.iter() {
+        eprintln!("{}: {}", type_name, count);
+    }
+}
+
+/// Format a parse error with the line of the expression it is on and a caret under its column
+///
+/// ```text
+/// line 1, column 9: Syntax error: mismatched input '<EOF>' expecting ...
+///   | this.x >
+///   |         ^
+/// ```
+fn format_parse_error(error: &ParseError) -> String {
+    let (line, column) = error.pos;
+    let mut message = format!("line {}, column {}: {}", line, column, error.msg);
+
+    let snippet = error
+        .source_info
+        .as_ref()
+        .and_then(|info| info.snippet(line - 1));
+    if let Some(snippet) = snippet {
+        // Tabs are kept in the padding, so the caret lines up however wide they are shown
+        let padding: String = snippet
+            .chars()
+            .take(usize::try_from(column - 1).unwrap_or(0))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        message.push_str(&format!("\n  | {}\n  | {}^", snippet, padding));
+    }
+
+    message
+}
+
 fn compile_expression(expression: Option<&str>, from_file: Option<&PathBuf>) -> Result<Program> {
     let source = if let Some(path) = from_file {
         fs::read_to_string(path)
@@ -0,0 +1,461 @@
+use anyhow::{Result, bail};
+use cel::common::ast::{
+    CallExpr, ComprehensionExpr, EntryExpr, Expr, IdedEntryExpr, ListExpr, MapEntryExpr, MapExpr,
+    SelectExpr, StructExpr, operators,
+};
+use cel::common::value::CelVal;
+use cel::parser::Expression;
+use cel::{Context, Program, Value as CelValue};
+
+/// Fold the constant subexpressions of a compiled program and render the result as CEL source
+///
+/// # Arguments
+/// * `program` - The compiled CEL program
+///
+/// # Returns
+/// * Ok(String) - The simplified expression
+/// * Err(anyhow::Error) - If the folded expression cannot be written back as CEL
+pub fn optimize(program: &Program) -> Result<String> {
+    let folded = fold_constants(program.expression());
+    render_expression(&folded)
+}
+
+/// Replace every call or field selection whose operands are all constants with its value.
+/// Subexpressions that fail to evaluate (e.g. `1 / 0`) are kept so the error surfaces at runtime.
+pub fn fold_constants(expr: &Expression) -> Expression {
+    let context = Context::default();
+    fold(expr, &context)
+}
+
+fn fold(expr: &Expression, context: &Context) -> Expression {
+    let folded = match &expr.expr {
+        Expr::Call(call) => Expr::Call(CallExpr {
+            func_name: call.func_name.clone(),
+            target: call
+                .target
+                .as_ref()
+                .map(|target| Box::new(fold(target, context))),
+            args: call.args.iter().map(|arg| fold(arg, context)).collect(),
+        }),
+        Expr::Select(select) => Expr::Select(SelectExpr {
+            operand: Box::new(fold(&select.operand, context)),
+            field: select.field.clone(),
+            test: select.test,
+        }),
+        Expr::List(list) => Expr::List(ListExpr {
+            elements: list.elements.iter().map(|e| fold(e, context)).collect(),
+            optional_indices: list.optional_indices.clone(),
+        }),
+        Expr::Map(map) => Expr::Map(MapExpr {
+            entries: map
+                .entries
+                .iter()
+                .map(|entry| fold_entry(entry, context))
+                .collect(),
+        }),
+        Expr::Struct(st) => Expr::Struct(StructExpr {
+            type_name: st.type_name.clone(),
+            entries: st
+                .entries
+                .iter()
+                .map(|entry| fold_entry(entry, context))
+                .collect(),
+        }),
+        Expr::Comprehension(comp) => Expr::Comprehension(Box::new(ComprehensionExpr {
+            iter_range: fold(&comp.iter_range, context),
+            iter_var: comp.iter_var.clone(),
+            iter_var2: comp.iter_var2.clone(),
+            accu_var: comp.accu_var.clone(),
+            accu_init: fold(&comp.accu_init, context),
+            loop_cond: fold(&comp.loop_cond, context),
+            loop_step: fold(&comp.loop_step, context),
+            result: fold(&comp.result, context),
+        })),
+        other => other.clone(),
+    };
+
+    let folded = Expression {
+        id: expr.id,
+        expr: folded,
+    };
+
+    let foldable = match &folded.expr {
+        Expr::Call(call) => {
+            call.target.as_deref().is_none_or(is_constant) && call.args.iter().all(is_constant)
+        }
+        Expr::Select(select) => !select.test && is_constant(&select.operand),
+        _ => false,
+    };
+
+    if foldable
+        && let Ok(value) = context.resolve(&folded)
+        && let Some(literal) = value_to_literal(value)
+    {
+        return Expression {
+            id: expr.id,
+            expr: Expr::Literal(literal),
+        };
+    }
+
+    folded
+}
+
+fn fold_entry(entry: &IdedEntryExpr, context: &Context) -> IdedEntryExpr {
+    let expr = match &entry.expr {
+        EntryExpr::MapEntry(map_entry) => EntryExpr::MapEntry(MapEntryExpr {
+            key: fold(&map_entry.key, context),
+            value: fold(&map_entry.value, context),
+            optional: map_entry.optional,
+        }),
+        EntryExpr::StructField(field) => {
+            let mut field = field.clone();
+            field.value = fold(&field.value, context);
+            EntryExpr::StructField(field)
+        }
+    };
+    IdedEntryExpr { id: entry.id, expr }
+}
+
+fn is_constant(expr: &Expression) -> bool {
+    match &expr.expr {
+        Expr::Literal(_) => true,
+        Expr::List(list) => {
+            list.optional_indices.is_empty() && list.elements.iter().all(is_constant)
+        }
+        Expr::Map(map) => map.entries.iter().all(|entry| match &entry.expr {
+            EntryExpr::MapEntry(e) => !e.optional && is_constant(&e.key) && is_constant(&e.value),
+            EntryExpr::StructField(_) => false,
+        }),
+        _ => false,
+    }
+}
+
+/// Convert an evaluated value back into a literal, if CEL has a literal syntax for it
+fn value_to_literal(value: CelValue) -> Option<CelVal> {
+    match value {
+        CelValue::Int(i) => Some(CelVal::Int(i)),
+        CelValue::UInt(u) => Some(CelVal::UInt(u)),
+        CelValue::Float(f) if f.is_finite() => Some(CelVal::Double(f)),
+        CelValue::String(s) => Some(CelVal::String(s.to_string())),
+        CelValue::Bytes(b) => Some(CelVal::Bytes(b.to_vec())),
+        CelValue::Bool(b) => Some(CelVal::Boolean(b)),
+        CelValue::Null => Some(CelVal::Null),
+        _ => None,
+    }
+}
+
+// Operator precedence, from loosest to tightest binding
+const PREC_CONDITIONAL: u8 = 0;
+const PREC_OR: u8 = 1;
+const PREC_AND: u8 = 2;
+const PREC_RELATION: u8 = 3;
+const PREC_ADDITION: u8 = 4;
+const PREC_MULTIPLICATION: u8 = 5;
+const PREC_UNARY: u8 = 6;
+const PREC_MEMBER: u8 = 7;
+
+fn binary_operator(func_name: &str) -> Option<(&'static str, u8)> {
+    let op = match func_name {
+        operators::LOGICAL_OR => ("||", PREC_OR),
+        operators::LOGICAL_AND => ("&&", PREC_AND),
+        operators::EQUALS => ("==", PREC_RELATION),
+        operators::NOT_EQUALS => ("!=", PREC_RELATION),
+        operators::LESS => ("<", PREC_RELATION),
+        operators::LESS_EQUALS => ("<=", PREC_RELATION),
+        operators::GREATER => (">", PREC_RELATION),
+        operators::GREATER_EQUALS => (">=", PREC_RELATION),
+        operators::IN => ("in", PREC_RELATION),
+        operators::ADD => ("+", PREC_ADDITION),
+        operators::SUBSTRACT => ("-", PREC_ADDITION),
+        operators::MULTIPLY => ("*", PREC_MULTIPLICATION),
+        operators::DIVIDE => ("/", PREC_MULTIPLICATION),
+        operators::MODULO => ("%", PREC_MULTIPLICATION),
+        _ => return None,
+    };
+    Some(op)
+}
+
+fn precedence(expr: &Expression) -> u8 {
+    match &expr.expr {
+        Expr::Call(call) if call.args.len() == 2 && call.target.is_none() => {
+            binary_operator(&call.func_name).map_or(PREC_MEMBER, |(_, prec)| prec)
+        }
+        Expr::Call(call) if call.func_name == operators::CONDITIONAL => PREC_CONDITIONAL,
+        Expr::Call(call)
+            if call.func_name == operators::LOGICAL_NOT || call.func_name == operators::NEGATE =>
+        {
+            PREC_UNARY
+        }
+        Expr::Literal(CelVal::Int(i)) if *i < 0 => PREC_UNARY,
+        Expr::Literal(CelVal::Double(f)) if f.is_sign_negative() => PREC_UNARY,
+        _ => PREC_MEMBER,
+    }
+}
+
+/// Render an expression, wrapping it in parentheses if it binds looser than `min_prec`
+fn render_operand(expr: &Expression, min_prec: u8) -> Result<String> {
+    let rendered = render_expression(expr)?;
+    if precedence(expr) < min_prec {
+        Ok(format!("({})", rendered))
+    } else {
+        Ok(rendered)
+    }
+}
+
+/// Write an expression back as CEL source
+pub fn render_expression(expr: &Expression) -> Result<String> {
+    let rendered = match &expr.expr {
+        Expr::Literal(literal) => render_literal(literal)?,
+        Expr::Ident(name) => name.clone(),
+        Expr::Select(select) => {
+            let selected = format!(
+                "{}.{}",
+                render_operand(&select.operand, PREC_MEMBER)?,
+                select.field
+            );
+            if select.test {
+                format!("has({})", selected)
+            } else {
+                selected
+            }
+        }
+        Expr::Call(call) => render_call(call)?,
+        Expr::List(list) => {
+            let elements = list
+                .elements
+                .iter()
+                .enumerate()
+                .map(|(i, element)| {
+                    let optional = if list.optional_indices.contains(&i) {
+                        "?"
+                    } else {
+                        ""
+                    };
+                    Ok(format!("{}{}", optional, render_expression(element)?))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            format!("[{}]", elements.join(", "))
+        }
+        Expr::Map(map) => format!("{{{}}}", render_entries(&map.entries)?),
+        Expr::Struct(st) => format!("{}{{{}}}", st.type_name, render_entries(&st.entries)?),
+        Expr::Comprehension(comp) => render_comprehension(comp)?,
+        Expr::Unspecified => bail!("cannot render an unspecified expression"),
+    };
+    Ok(rendered)
+}
+
+fn render_call(call: &CallExpr) -> Result<String> {
+    let args = &call.args;
+
+    if call.target.is_none() {
+        if args.len() == 2
+            && let Some((op, prec)) = binary_operator(&call.func_name)
+        {
+            // Binary operators are left-associative, so an equal-precedence right operand needs parentheses
+            return Ok(format!(
+                "{} {} {}",
+                render_operand(&args[0], prec)?,
+                op,
+                render_operand(&args[1], prec + 1)?
+            ));
+        }
+
+        match (call.func_name.as_str(), args.len()) {
+            (operators::CONDITIONAL, 3) => {
+                return Ok(format!(
+                    "{} ? {} : {}",
+                    render_operand(&args[0], PREC_OR)?,
+                    render_operand(&args[1], PREC_OR)?,
+                    render_operand(&args[2], PREC_CONDITIONAL)?
+                ));
+            }
+            (operators::LOGICAL_NOT, 1) => {
+                return Ok(format!("!{}", render_operand(&args[0], PREC_UNARY)?));
+            }
+            (operators::NEGATE, 1) => {
+                return Ok(format!("-{}", render_operand(&args[0], PREC_UNARY)?));
+            }
+            (operators::INDEX, 2) => {
+                return Ok(format!(
+                    "{}[{}]",
+                    render_operand(&args[0], PREC_MEMBER)?,
+                    render_expression(&args[1])?
+                ));
+            }
+            (operators::OPT_INDEX, 2) => {
+                return Ok(format!(
+                    "{}[?{}]",
+                    render_operand(&args[0], PREC_MEMBER)?,
+                    render_expression(&args[1])?
+                ));
+            }
+            (operators::OPT_SELECT, 2) => {
+                if let Expr::Literal(CelVal::String(field)) = &args[1].expr {
+                    return Ok(format!(
+                        "{}.?{}",
+                        render_operand(&args[0], PREC_MEMBER)?,
+                        field
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let rendered_args = args
+        .iter()
+        .map(render_expression)
+        .collect::<Result<Vec<_>>>()?
+        .join(", ");
+
+    match &call.target {
+        Some(target) => Ok(format!(
+            "{}.{}({})",
+            render_operand(target, PREC_MEMBER)?,
+            call.func_name,
+            rendered_args
+        )),
+        None => Ok(format!("{}({})", call.func_name, rendered_args)),
+    }
+}
+
+fn render_entries(entries: &[IdedEntryExpr]) -> Result<String> {
+    let rendered = entries
+        .iter()
+        .map(|entry| match &entry.expr {
+            EntryExpr::MapEntry(e) => Ok(format!(
+                "{}{}: {}",
+                if e.optional { "?" } else { "" },
+                render_expression(&e.key)?,
+                render_expression(&e.value)?
+            )),
+            EntryExpr::StructField(f) => Ok(format!(
+                "{}{}: {}",
+                if f.optional { "?" } else { "" },
+                f.field,
+                render_expression(&f.value)?
+            )),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(rendered.join(", "))
+}
+
+/// Recover the macro call (`all`, `exists`, `exists_one`, `map`, `filter`) that a comprehension was expanded from
+fn render_comprehension(comp: &ComprehensionExpr) -> Result<String> {
+    let accu = comp.accu_var.as_str();
+    let is_accu = |expr: &Expression| matches!(&expr.expr, Expr::Ident(name) if name == accu);
+    let as_call = |expr: &Expression| match &expr.expr {
+        Expr::Call(call) => Some(call.clone()),
+        _ => None,
+    };
+    // The `[@result] + [element]` step produced by the `map` and `filter` macros
+    let appended_element = |step: &Expression| -> Option<Expression> {
+        let call = as_call(step)?;
+        if call.func_name != operators::ADD || call.args.len() != 2 || !is_accu(&call.args[0]) {
+            return None;
+        }
+        match &call.args[1].expr {
+            Expr::List(list) if list.elements.len() == 1 => Some(list.elements[0].clone()),
+            _ => None,
+        }
+    };
+
+    let step = as_call(&comp.loop_step);
+    let (name, args): (&str, Vec<&Expression>) = match (&comp.accu_init.expr, &step) {
+        (Expr::Literal(CelVal::Boolean(false)), Some(step))
+            if step.func_name == operators::LOGICAL_OR && is_accu(&step.args[0]) =>
+        {
+            (operators::EXISTS, vec![&step.args[1]])
+        }
+        (Expr::Literal(CelVal::Boolean(true)), Some(step))
+            if step.func_name == operators::LOGICAL_AND && is_accu(&step.args[0]) =>
+        {
+            (operators::ALL, vec![&step.args[1]])
+        }
+        (Expr::Literal(CelVal::Int(0)), Some(step)) if step.func_name == operators::CONDITIONAL => {
+            (operators::EXISTS_ONE, vec![&step.args[0]])
+        }
+        (Expr::List(init), Some(step)) if init.elements.is_empty() => {
+            if let Some(element) = appended_element(&comp.loop_step) {
+                let rendered = render_expression(&element)?;
+                return Ok(format!(
+                    "{}.{}({}, {})",
+                    render_operand(&comp.iter_range, PREC_MEMBER)?,
+                    operators::MAP,
+                    comp.iter_var,
+                    rendered
+                ));
+            }
+            if step.func_name == operators::CONDITIONAL
+                && step.args.len() == 3
+                && is_accu(&step.args[2])
+                && let Some(element) = appended_element(&step.args[1])
+            {
+                let filter = render_expression(&step.args[0])?;
+                let range = render_operand(&comp.iter_range, PREC_MEMBER)?;
+                if matches!(&element.expr, Expr::Ident(name) if *name == comp.iter_var) {
+                    return Ok(format!(
+                        "{}.{}({}, {})",
+                        range,
+                        operators::FILTER,
+                        comp.iter_var,
+                        filter
+                    ));
+                }
+                return Ok(format!(
+                    "{}.{}({}, {}, {})",
+                    range,
+                    operators::MAP,
+                    comp.iter_var,
+                    filter,
+                    render_expression(&element)?
+                ));
+            }
+            bail!("cannot render comprehension over `{}`", comp.iter_var)
+        }
+        _ => bail!("cannot render comprehension over `{}`", comp.iter_var),
+    };
+
+    let rendered_args = args
+        .into_iter()
+        .map(render_expression)
+        .collect::<Result<Vec<_>>>()?
+        .join(", ");
+    Ok(format!(
+        "{}.{}({}, {})",
+        render_operand(&comp.iter_range, PREC_MEMBER)?,
+        name,
+        comp.iter_var,
+        rendered_args
+    ))
+}
+
+fn render_literal(literal: &CelVal) -> Result<String> {
+    let rendered = match literal {
+        CelVal::Boolean(b) => b.to_string(),
+        CelVal::Int(i) => i.to_string(),
+        CelVal::UInt(u) => format!("{}u", u),
+        CelVal::Double(f) => format!("{:?}", f),
+        CelVal::String(s) => serde_json::to_string(s)?,
+        CelVal::Bytes(bytes) => {
+            let mut rendered = String::from("b\"");
+            for &byte in bytes {
+                match byte {
+                    b'"' => rendered.push_str("\\\""),
+                    b'\\' => rendered.push_str("\\\\"),
+                    0x20..=0x7e => rendered.push(byte as char),
+                    _ => rendered.push_str(&format!("\\x{:02x}", byte)),
+                }
+            }
+            rendered.push('"');
+            rendered
+        }
+        CelVal::Null => "null".to_string(),
+        other => bail!("cannot render literal {:?}", other),
+    };
+    Ok(rendered)
+}
+
+#[cfg(test)]
+#[path = "optimizer_test.rs"]
+mod test;
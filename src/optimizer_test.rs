@@ -0,0 +1,53 @@
+use super::*;
+
+fn optimized(source: &str) -> String {
+    let program = Program::compile(source).expect("expression should compile");
+    optimize(&program).expect("expression should render")
+}
+
+#[test]
+fn test_folds_arithmetic() {
+    assert_eq!(optimized("1 + 2 * 3"), "7");
+}
+
+#[test]
+fn test_folds_constant_operands_only() {
+    assert_eq!(optimized("this.x + 2 * 3"), "this.x + 6");
+    assert_eq!(optimized("size(this) > 2 - 1"), "size(this) > 1");
+}
+
+#[test]
+fn test_folds_strings_and_functions() {
+    assert_eq!(optimized("'a' + 'b' == this"), "\"ab\" == this");
+    assert_eq!(optimized("size([1, 2, 3]) * x"), "3 * x");
+}
+
+#[test]
+fn test_keeps_parentheses() {
+    assert_eq!(optimized("(this.a + 1) * 2"), "(this.a + 1) * 2");
+    assert_eq!(optimized("a - (b - c)"), "a - (b - c)");
+    assert_eq!(optimized("!(a && b)"), "!(a && b)");
+}
+
+#[test]
+fn test_keeps_failing_subexpressions() {
+    assert_eq!(optimized("1 / 0 + x"), "1 / 0 + x");
+}
+
+#[test]
+fn test_folds_inside_macros() {
+    assert_eq!(
+        optimized("[1, 2].map(x, x * (1 + 1))"),
+        "[1, 2].map(x, x * 2)"
+    );
+    assert_eq!(
+        optimized("this.filter(x, x > 2 + 2)"),
+        "this.filter(x, x > 4)"
+    );
+    assert_eq!(optimized("this.all(x, x > 0)"), "this.all(x, x > 0)");
+    assert_eq!(
+        optimized("[1, 2, 3].exists(x, x == 2)"),
+        "[1, 2, 3].exists(x, x == 2)"
+    );
+    assert_eq!(optimized("has(this.a)"), "has(this.a)");
+}
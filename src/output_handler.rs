@@ -0,0 +1,232 @@
+use anyhow::Result;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::io::Write;
+
+/// A formatted result and its truthiness, ready to be written
+#[derive(Debug, Clone, PartialEq)]
+pub struct Output {
+    pub content: Content,
+    pub is_truthy: bool,
+    /// The CEL type of the result, or None if the output is not a result, like an error report
+    pub type_name: Option<&'static str>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Content {
+    /// Text, followed by the terminator
+    Text(String),
+    /// Raw bytes from `--output-raw-bytes`, written as they are without any terminator, so
+    /// binary outputs are concatenated
+    Bytes(Vec<u8>),
+}
+
+impl Content {
+    fn as_bytes(&self) -> &[u8] {
+        match self {
+            Content::Text(text) => text.as_bytes(),
+            Content::Bytes(bytes) => bytes,
+        }
+    }
+}
+
+impl fmt::Display for Content {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Content::Text(text) => f.write_str(text),
+            Content::Bytes(bytes) => f.write_str(&String::from_utf8_lossy(bytes)),
+        }
+    }
+}
+
+/// Receives outputs as they are produced, prints them and tracks their truthiness
+///
+/// Outputs are written immediately, so streamed inputs do not accumulate in memory.
+pub struct OutputHandler<W: Write> {
+    writer: W,
+    print: bool,
+    terminator: u8,
+    trailing_terminator: bool,
+    dedupe_window: Option<DedupeWindow>,
+    result_count: usize,
+    truthy_count: usize,
+    last_truthy: bool,
+    type_counts: BTreeMap<&'static str, usize>,
+}
+
+impl<W: Write> OutputHandler<W> {
+    /// Create a handler writing each output followed by `terminator`, usually a newline
+    ///
+    /// Without `trailing_terminator`, the terminator only separates outputs, so nothing follows
+    /// the last one.
+    pub fn new(
+        writer: W,
+        print: bool,
+        terminator: u8,
+        trailing_terminator: bool,
+        dedupe_window: Option<DedupeWindow>,
+    ) -> Self {
+        OutputHandler {
+            writer,
+            print,
+            terminator,
+            trailing_terminator,
+            dedupe_window,
+            result_count: 0,
+            truthy_count: 0,
+            last_truthy: false,
+            type_counts: BTreeMap::new(),
+        }
+    }
+
+    /// Handle the next output
+    pub fn emit(&mut self, output: Output) -> Result<()> {
+        if !self.admit(&output) {
+            return Ok(());
+        }
+        if !self.print {
+            return Ok(());
+        }
+
+        match &output.content {
+            Content::Text(text) => {
+                // The last output is not known in advance, so separators go before the next one
+                if !self.trailing_terminator && self.result_count > 1 {
+                    self.writer.write_all(&[self.terminator])?;
+                }
+                self.writer.write_all(text.as_bytes())?;
+                if self.trailing_terminator {
+                    self.writer.write_all(&[self.terminator])?;
+                }
+            }
+            Content::Bytes(bytes) => self.writer.write_all(bytes)?,
+        }
+        Ok(())
+    }
+
+    /// Count an output, unless it was already emitted within the dedupe window
+    ///
+    /// # Returns
+    /// * true - The output should be written
+    /// * false - The output is a duplicate, and is skipped
+    fn admit(&mut self, output: &Output) -> bool {
+        if let Some(window) = &mut self.dedupe_window
+            && !window.admit(output.content.as_bytes())
+        {
+            return false;
+        }
+
+        self.result_count += 1;
+        self.truthy_count += usize::from(output.is_truthy);
+        self.last_truthy = output.is_truthy;
+        if let Some(type_name) = output.type_name {
+            *self.type_counts.entry(type_name).or_default() += 1;
+        }
+        true
+    }
+
+    /// Returns true if there was at least one result, and every result was truthy
+    pub fn all_truthy(&self) -> bool {
+        self.result_count > 0 && self.truthy_count == self.result_count
+    }
+
+    /// Returns true if at least one result was falsy
+    pub fn any_falsy(&self) -> bool {
+        self.truthy_count < self.result_count
+    }
+
+    /// Returns true if the last result was truthy
+    pub fn last_truthy(&self) -> bool {
+        self.last_truthy
+    }
+
+    /// Number of results of each CEL type so far, for `--type-summary`
+    pub fn type_counts(&self) -> &BTreeMap<&'static str, usize> {
+        &self.type_counts
+    }
+
+    /// Write the number of truthy results so far, even if outputs are not printed
+    pub fn write_truthy_count(&mut self) -> Result<()> {
+        self.writer
+            .write_all(self.truthy_count.to_string().as_bytes())?;
+        if self.trailing_terminator {
+            self.writer.write_all(&[self.terminator])?;
+        }
+        Ok(())
+    }
+
+    /// Flush the outputs written so far
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// The writer the outputs were written to
+    pub fn into_writer(self) -> W {
+        self.writer
+    }
+}
+
+/// Sliding window over the outputs of the most recent records, used by `--dedupe-window`
+///
+/// Memory is bounded by the window size, unless the window is unbounded as with `--dedup`.
+pub struct DedupeWindow {
+    capacity: Option<usize>,
+    recent: VecDeque<Vec<u8>>,
+    /// How many times each output occurs in the window
+    seen: HashMap<Vec<u8>, usize>,
+}
+
+impl DedupeWindow {
+    pub fn new(capacity: usize) -> Self {
+        DedupeWindow {
+            capacity: Some(capacity),
+            recent: VecDeque::with_capacity(capacity),
+            seen: HashMap::with_capacity(capacity),
+        }
+    }
+
+    /// A window remembering every emitted output, so each output is emitted once
+    pub fn unbounded() -> Self {
+        DedupeWindow {
+            capacity: None,
+            recent: VecDeque::new(),
+            seen: HashMap::new(),
+        }
+    }
+
+    /// Returns true if the output should be emitted, i.e. it is not among the outputs of the
+    /// last `capacity` records, or among any output if the window is unbounded.
+    /// Every output is remembered, including the skipped duplicates.
+    pub fn admit(&mut self, output: &[u8]) -> bool {
+        let is_new = !self.seen.contains_key(output);
+
+        // Without a capacity nothing is evicted, so the first occurrence is enough
+        let Some(capacity) = self.capacity else {
+            if is_new {
+                self.seen.insert(output.to_vec(), 1);
+            }
+            return is_new;
+        };
+
+        *self.seen.entry(output.to_vec()).or_default() += 1;
+        self.recent.push_back(output.to_vec());
+
+        if self.recent.len() > capacity
+            && let Some(evicted) = self.recent.pop_front()
+            && let Entry::Occupied(mut count) = self.seen.entry(evicted)
+        {
+            *count.get_mut() -= 1;
+            if *count.get() == 0 {
+                count.remove();
+            }
+        }
+
+        is_new
+    }
+}
+
+#[cfg(test)]
+#[path = "output_handler_test.rs"]
+mod test;
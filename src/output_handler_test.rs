@@ -0,0 +1,171 @@
+use super::*;
+
+fn text(output: &str, is_truthy: bool) -> Output {
+    Output {
+        content: Content::Text(output.to_string()),
+        is_truthy,
+        type_name: None,
+    }
+}
+
+fn bytes(output: &[u8], is_truthy: bool) -> Output {
+    Output {
+        content: Content::Bytes(output.to_vec()),
+        is_truthy,
+        type_name: Some("bytes"),
+    }
+}
+
+#[test]
+fn test_duplicate_inside_window_is_suppressed() {
+    let mut window = DedupeWindow::new(2);
+    assert!(window.admit(b"a"));
+    assert!(window.admit(b"b"));
+    assert!(!window.admit(b"a"));
+}
+
+#[test]
+fn test_duplicate_outside_window_is_emitted() {
+    let mut window = DedupeWindow::new(2);
+    assert!(window.admit(b"a"));
+    assert!(window.admit(b"b"));
+    assert!(window.admit(b"c"));
+    assert!(window.admit(b"a"));
+}
+
+#[test]
+fn test_skipped_duplicates_fill_window() {
+    // The skipped "b" still takes a slot, so the second "a" is outside the last two records
+    let mut window = DedupeWindow::new(2);
+    let outputs = ["a", "b", "b", "a"];
+    let emitted: Vec<&str> = outputs
+        .into_iter()
+        .filter(|output| window.admit(output.as_bytes()))
+        .collect();
+    assert_eq!(emitted, vec!["a", "b", "a"]);
+}
+
+#[test]
+fn test_window_of_one_suppresses_consecutive_duplicates() {
+    let mut window = DedupeWindow::new(1);
+    let outputs = ["a", "a", "b", "a", "a"];
+    let emitted: Vec<&str> = outputs
+        .into_iter()
+        .filter(|output| window.admit(output.as_bytes()))
+        .collect();
+    assert_eq!(emitted, vec!["a", "b", "a"]);
+}
+
+#[test]
+fn test_output_handler_prints_and_tracks_truthiness() {
+    let mut buffer = Vec::new();
+    let mut handler = OutputHandler::new(&mut buffer, true, b'\n', true, None);
+    handler.emit(text("1", true)).unwrap();
+    handler.emit(text("0", false)).unwrap();
+    assert!(!handler.all_truthy());
+    assert!(handler.any_falsy());
+    assert!(!handler.last_truthy());
+    assert_eq!(String::from_utf8(buffer).unwrap(), "1\n0\n");
+}
+
+#[test]
+fn test_output_handler_without_results_is_not_truthy() {
+    let handler = OutputHandler::new(Vec::new(), true, b'\n', true, None);
+    assert!(!handler.all_truthy());
+    assert!(!handler.any_falsy());
+    assert!(!handler.last_truthy());
+}
+
+#[test]
+fn test_output_handler_skips_deduplicated_outputs() {
+    let mut buffer = Vec::new();
+    let mut handler =
+        OutputHandler::new(&mut buffer, true, b'\n', true, Some(DedupeWindow::new(1)));
+    handler.emit(text("true", true)).unwrap();
+    handler.emit(text("true", true)).unwrap();
+    assert!(handler.all_truthy());
+    assert_eq!(String::from_utf8(buffer).unwrap(), "true\n");
+}
+
+#[test]
+fn test_output_handler_nul_terminator() {
+    let mut buffer = Vec::new();
+    let mut handler = OutputHandler::new(&mut buffer, true, b'\0', true, None);
+    handler.emit(text("a\nb", true)).unwrap();
+    handler.emit(text("c", true)).unwrap();
+    assert_eq!(buffer, b"a\nb\0c\0");
+}
+
+#[test]
+fn test_unbounded_window_keeps_first_occurrences() {
+    let mut window = DedupeWindow::unbounded();
+    let outputs = ["a", "b", "a", "c", "b", "a"];
+    let emitted: Vec<&str> = outputs
+        .into_iter()
+        .filter(|output| window.admit(output.as_bytes()))
+        .collect();
+    assert_eq!(emitted, vec!["a", "b", "c"]);
+}
+
+#[test]
+fn test_write_truthy_count_without_printing() {
+    let mut buffer = Vec::new();
+    let mut handler = OutputHandler::new(&mut buffer, false, b'\n', true, None);
+    handler.emit(text("true", true)).unwrap();
+    handler.emit(text("false", false)).unwrap();
+    handler.emit(text("1", true)).unwrap();
+    handler.write_truthy_count().unwrap();
+    assert_eq!(String::from_utf8(buffer).unwrap(), "2\n");
+}
+
+fn printed(outputs: &[&str], trailing_terminator: bool) -> String {
+    let mut buffer = Vec::new();
+    let mut handler = OutputHandler::new(&mut buffer, true, b'\n', trailing_terminator, None);
+    for output in outputs {
+        handler.emit(text(output, true)).unwrap();
+    }
+    String::from_utf8(buffer).unwrap()
+}
+
+#[test]
+fn test_each_output_is_followed_by_one_newline() {
+    assert_eq!(printed(&[], true), "");
+    assert_eq!(printed(&["1"], true), "1\n");
+    assert_eq!(printed(&["1", "2", "3"], true), "1\n2\n3\n");
+}
+
+#[test]
+fn test_no_trailing_newline_only_separates_outputs() {
+    assert_eq!(printed(&[], false), "");
+    assert_eq!(printed(&["1"], false), "1");
+    assert_eq!(printed(&["1", "2", "3"], false), "1\n2\n3");
+}
+
+#[test]
+fn test_raw_bytes_are_concatenated() {
+    let mut buffer = Vec::new();
+    let mut handler = OutputHandler::new(&mut buffer, true, b'\n', true, None);
+    handler.emit(bytes(&[0x00, 0xff], true)).unwrap();
+    handler.emit(bytes(b"\n", true)).unwrap();
+    handler.emit(bytes(&[], false)).unwrap();
+    assert!(handler.any_falsy());
+    assert_eq!(buffer, vec![0x00, 0xff, b'\n']);
+}
+
+#[test]
+fn test_type_counts_skip_duplicates_and_reports() {
+    let mut handler =
+        OutputHandler::new(Vec::new(), false, b'\n', true, Some(DedupeWindow::new(1)));
+    let result = |output: &str, type_name| Output {
+        type_name: Some(type_name),
+        ..text(output, true)
+    };
+    handler.emit(result("1", "int")).unwrap();
+    handler.emit(result("1", "int")).unwrap();
+    handler.emit(result("\"a\"", "string")).unwrap();
+    handler.emit(text("{\"line\":1}", false)).unwrap();
+    assert_eq!(
+        *handler.type_counts(),
+        BTreeMap::from([("int", 1), ("string", 1)])
+    );
+}
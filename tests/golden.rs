@@ -115,6 +115,27 @@ test!(
     r#"hello world"#
 );
 
+test!(
+    nul_output_raw_strings,
+    &["-r", "-0", "this.path"],
+    "{\"path\":\"a b\\nc\"}\n{\"path\":\"d\"}\n",
+    "a b\nc\0d\0"
+);
+
+test!(
+    ascii_output_escapes_keys_and_values,
+    &["-A", "{'é': ['naïve', '😀']}"],
+    "{}",
+    r#"{"\u00e9":["na\u00efve","\ud83d\ude00"]}"#
+);
+
+test!(
+    ascii_output_quotes_raw_strings,
+    &["-A", "-r", "'café'"],
+    "{}",
+    r#""caf\u00e9""#
+);
+
 // Logical operations
 test!(logical_and_true, &["true && true"], "{}", "true");
 test!(logical_and_false, &["true && false"], "{}", "false");
@@ -155,6 +176,140 @@ test!(
     "{}",
     r#"{"id":1,"person":{"age":30,"name":"Alice"}}"#
 );
+test!(
+    map_numeric_key_sort,
+    &["-S", "--numeric-key-sort", r#"{"10": "b", "2": "a"}"#],
+    "{}",
+    r#"{"2":"a","10":"b"}"#
+);
+test!(
+    optimize_constant_folding,
+    &["--optimize", "1 + 2 * 3"],
+    "",
+    "7"
+);
+test!(
+    optimize_partial_folding,
+    &["--optimize", "this.x + 2 * 3"],
+    "",
+    "this.x + 6"
+);
+test!(
+    with_filename_stdin,
+    &["--with-filename", "[filename, this.x]"],
+    "{\"x\": 1}\n{\"x\": 2}",
+    "[\"<stdin>\",1]\n[\"<stdin>\",2]"
+);
+test!(
+    with_filename_null_input,
+    &["-n", "--with-filename", "filename"],
+    "",
+    "null"
+);
+test!(
+    header_line_defaults,
+    &["--header-line", "this.x > threshold"],
+    "{\"threshold\": 2}\n{\"x\": 1}\n{\"x\": 3}",
+    "false\ntrue"
+);
+test!(
+    header_line_arg_precedence,
+    &[
+        "--header-line",
+        "--arg=threshold:int=0",
+        "this.x > threshold"
+    ],
+    "{\"threshold\": 2}\n{\"x\": 1}",
+    "true"
+);
+test!(
+    truncate_strings_nested,
+    &[
+        "--truncate-strings",
+        "4",
+        "--truncate-marker",
+        "…",
+        "[this.a, [this.a]]"
+    ],
+    "{\"a\": \"abcdefgh\"}",
+    "[\"abcd…\",[\"abcd…\"]]"
+);
+test!(
+    args_from_stdin_header,
+    &[
+        "--args-from-stdin-header",
+        "--arg=x:int=1",
+        "this.value + x"
+    ],
+    "{\"x\": 100}\n{\"value\": 1}\n{\"value\": 2}",
+    "101\n102"
+);
+test!(
+    args_from_stdin_header_slurp,
+    &["--args-from-stdin-header", "-s", "size(this) * x"],
+    "{\"x\": 2}\n1\n2\n3",
+    "6"
+);
+test!(
+    compact_output,
+    &["-c", "this"],
+    "{\"a\": [1, 2]}",
+    "{\"a\":[1,2]}"
+);
+test!(
+    length_prefixed_input_frames,
+    &["--length-prefixed-input", "this.x"],
+    "\u{0}\u{0}\u{0}\u{7}{\"x\":1}\u{0}\u{0}\u{0}\u{8}{\"x\":\n2}",
+    "1\n2"
+);
+test!(
+    dedupe_window_inside_and_outside,
+    &["--dedupe-window", "2", "this.x"],
+    "{\"x\": 1}\n{\"x\": 2}\n{\"x\": 1}\n{\"x\": 3}\n{\"x\": 4}\n{\"x\": 1}",
+    "1\n2\n3\n4\n1"
+);
+test!(
+    sql_insert_quoting,
+    &[
+        "--sql-insert",
+        "people",
+        "--sql-columns",
+        "name,age",
+        "this"
+    ],
+    "{\"age\": 42, \"name\": \"O'Brien\"}",
+    "INSERT INTO people (name, age) VALUES ('O''Brien', 42);"
+);
+test!(
+    changes_only_collapses_runs,
+    &["--changes-only", "this.x"],
+    "{\"x\": 1}\n{\"x\": 1}\n{\"x\": 2}\n{\"x\": 2}\n{\"x\": 2}\n{\"x\": 3}",
+    "1\n2\n3"
+);
+test!(
+    base64_decode_to_string,
+    &["string(base64Decode(this.payload))"],
+    "{\"payload\": \"aGVsbG8=\"}",
+    "\"hello\""
+);
+test!(
+    null_for_missing_exposed_fields,
+    &["--expose-fields", "--null-for-missing", "[x, y]"],
+    "{\"x\": 1}",
+    "[1,null]"
+);
+test!(
+    keys_and_values_sorted_by_key,
+    &["[keys(this), values(this)]"],
+    "{\"b\": 1, \"a\": 2}",
+    "[[\"a\",\"b\"],[2,1]]"
+);
+test!(
+    default_output_fills_null,
+    &["--default-output", "\"anonymous\"", "this.name"],
+    "{\"name\": \"Ada\"}\n{\"name\": null}",
+    "\"Ada\"\n\"anonymous\""
+);
 test!(map_access, &[r#"{"a": 1, "b": 2}["a"]"#], "{}", "1");
 test!(
     map_dot_access,
@@ -229,6 +384,41 @@ test!(
     "6.28318"
 );
 
+// Arguments: duration type
+test!(
+    arg_duration,
+    &[
+        "--arg",
+        "timeout:duration=1h30m",
+        "timeout > duration(\"45m\")"
+    ],
+    "{}",
+    "true"
+);
+
+// Arguments: list type
+test!(
+    arg_list,
+    &["-n", "--arg", r#"tags:list=["a","b"]"#, "\"b\" in tags"],
+    "",
+    "true"
+);
+
+// Arguments: map and json types
+test!(
+    arg_map,
+    &["-n", "--arg", r#"config:map={"k":1}"#, "config.k + 1"],
+    "",
+    "2"
+);
+
+test!(
+    arg_json,
+    &["-n", "--arg", r#"config:json=[{"k":1}]"#, "config[0].k"],
+    "",
+    "1"
+);
+
 // JSON input - accessing fields
 test!(
     json_input_field,
@@ -258,6 +448,21 @@ test!(
     "30"
 );
 
+// Integers outside the i64 range must not lose precision
+test!(
+    json_input_max_uint,
+    &["this"],
+    r#"{"id":18446744073709551615}"#,
+    r#"{"id":18446744073709551615}"#
+);
+
+test!(
+    json_input_min_int,
+    &["this.id"],
+    r#"{"id":-9223372036854775808}"#,
+    "-9223372036854775808"
+);
+
 // Newline-Delimited JSON (NDJSON)
 test!(
     ndjson_multi_line,
@@ -277,6 +482,51 @@ test!(
     "true\nfalse\ntrue"
 );
 
+test!(
+    ndjson_presort_by,
+    &["--presort-by", "this.n", "this.name"],
+    r#"{"n":3,"name":"c"}
+{"n":1,"name":"a"}
+{"n":2,"name":"b"}"#,
+    "\"a\"\n\"b\"\n\"c\""
+);
+
+// Log lines parsed with regex capture groups
+test!(
+    grok_capture_groups,
+    &[
+        "--grok",
+        r"^(?P<method>[A-Z]+) (?P<path>\S+)",
+        "this.method == 'GET' ? this.path : ''"
+    ],
+    "GET /index.html HTTP/1.1\nPOST /login HTTP/1.1",
+    "\"/index.html\"\n\"\""
+);
+
+// CSV input
+#[cfg(feature = "from-csv")]
+test!(
+    csv_input_infer_types,
+    &["--csv-input", "--csv-infer-types", "this.age > 40"],
+    "name,age\n\"Smith, J\",42\nAda,36\n",
+    "true\nfalse"
+);
+
+#[cfg(feature = "to-csv")]
+test!(
+    csv_output_union_of_keys,
+    &["--csv-output", "this"],
+    r#"[{"name":"Ada","age":36},{"name":"Smith, J","tags":[1,2]}]"#,
+    "age,name,tags\n36,Ada,\n,\"Smith, J\",\"[1,2]\""
+);
+
+test!(
+    tab_output_per_line,
+    &["--tab", "[this.name, this.age]"],
+    "{\"name\":\"Ada\",\"age\":36}\n{\"name\":\"a\\tb\",\"age\":null}\n",
+    "Ada\t36\na\\tb\t"
+);
+
 // Multi-line JSON (pretty-printed)
 test!(
     multiline_json_object,
@@ -329,6 +579,13 @@ test!(
     r#""computed value""#
 );
 
+test!(
+    null_input_binds_null_root,
+    &["-n", "this == null"],
+    "",
+    "true"
+);
+
 // Null input with arguments
 test!(
     null_input_with_args,
@@ -450,6 +707,14 @@ test!(
     "20"
 );
 
+// Binding top-level fields as variables
+test!(
+    expand_only_fields,
+    &["--expand-only", "x,y", "x + y + this.z"],
+    r#"{"x":1,"y":2,"z":3}"#,
+    "6"
+);
+
 // Multi-line JSON5 (with trailing comma and comment)
 test!(
     multiline_json5_object,
@@ -564,6 +829,52 @@ test!(
 }"#
 );
 
+test!(
+    pretty_indent_four_spaces,
+    &["-p", "--indent", "4", r#"{"list": [1]}"#],
+    "{}",
+    r#"{
+    "list": [
+        1
+    ]
+}"#
+);
+
+test!(
+    pretty_tab_indent,
+    &["-p", "--tab-indent", r#"{"list": [1]}"#],
+    "{}",
+    "{\n\t\"list\": [\n\t\t1\n\t]\n}"
+);
+
+test!(
+    pretty_indent_zero_uses_tabs,
+    &["-p", "--indent", "0", r#"{"a": 1}"#],
+    "{}",
+    "{\n\t\"a\": 1\n}"
+);
+
+test!(
+    color_always,
+    &["--color", "always", "[1, 'a']"],
+    "{}",
+    "\x1b[1;39m[\x1b[0m\x1b[0;39m1\x1b[0m\x1b[1;39m,\x1b[0m\x1b[0;32m\"a\"\x1b[0m\x1b[1;39m]\x1b[0m"
+);
+
+test!(
+    color_always_colors_strings,
+    &["--color", "always", "'a'"],
+    "{}",
+    "\x1b[0;32m\"a\"\x1b[0m"
+);
+
+test!(
+    color_always_keeps_raw_strings_plain,
+    &["--color", "always", "-r", "'a'"],
+    "{}",
+    "a"
+);
+
 test!(
     json5_to_pretty_sorted_json,
     &["--from-json5", "--sort-keys", "--pretty-print", "this"],
@@ -638,6 +949,14 @@ test!(
     "30"
 );
 
+// JSON Patch output
+test!(
+    diff_added_field,
+    &["--diff", r#"{"name": this.name, "active": true}"#],
+    r#"{"name":"Alice"}"#,
+    r#"[{"op":"add","path":"/active","value":true}]"#
+);
+
 // Void test
 test!(void_mode, &["--void", "-n", "2 + 2"], "", "");
 
@@ -670,6 +989,76 @@ fn from_file_multiline_expression() -> io::Result<()> {
     golden_test(&["--from-file", path], r#"{"a":1, "b":2, "c":3}"#, "9")
 }
 
+#[test]
+fn input_files_with_filename() -> io::Result<()> {
+    let first = NamedTempFile::new()?;
+    let second = NamedTempFile::new()?;
+    fs::write(first.path(), "{\"x\": 1}\n{\"x\": 2}\n")?;
+    fs::write(second.path(), "{\"x\": 3}")?;
+
+    let first_path = first.path().to_str().expect("non-utf8 temp path");
+    let second_path = second.path().to_str().expect("non-utf8 temp path");
+    let expected = format!(
+        "[{first},1]\n[{first},2]\n[{second},3]",
+        first = json_quoted(first_path),
+        second = json_quoted(second_path)
+    );
+
+    golden_test(
+        &[
+            "--with-filename",
+            "[filename, this.x]",
+            first_path,
+            second_path,
+        ],
+        "",
+        &expected,
+    )
+}
+
+#[test]
+fn input_files_with_expression_file() -> io::Result<()> {
+    let program = NamedTempFile::new()?;
+    let input = NamedTempFile::new()?;
+    fs::write(program.path(), "this.x * 2")?;
+    fs::write(input.path(), "{\"x\": 21}")?;
+
+    let program_path = program.path().to_str().expect("non-utf8 temp path");
+    let input_path = input.path().to_str().expect("non-utf8 temp path");
+
+    golden_test(&["-f", program_path, "-i", input_path], "", "42")
+}
+
+#[test]
+fn expression_file_rejects_expression_argument() -> io::Result<()> {
+    let program = NamedTempFile::new()?;
+    fs::write(program.path(), "this.x * 2")?;
+    let program_path = program.path().to_str().expect("non-utf8 temp path");
+
+    let output = run_celq(&["-f", program_path, "this.x"], "{\"x\": 21}")?;
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+
+    let output = run_celq(&["--expr", "this", "this.x"], "{\"x\": 21}")?;
+    assert_eq!(output.status.code(), Some(2));
+    Ok(())
+}
+
+/// Quote a string the way celq outputs it, escaping backslashes in Windows paths
+fn json_quoted(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\"))
+}
+
+#[test]
+fn test_missing_input_file_exit_code() -> io::Result<()> {
+    let output = run_celq(&["this", "does-not-exist.json"], "")?;
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("does-not-exist.json"));
+
+    Ok(())
+}
+
 #[test]
 fn test_boolean_false_exit_code() -> io::Result<()> {
     let mut child = process::Command::new(env!("CARGO_BIN_EXE_celq"))
@@ -694,3 +1083,982 @@ fn test_boolean_false_exit_code() -> io::Result<()> {
 
     Ok(())
 }
+
+/// Run celq with the given stdin and return the raw output, regardless of the exit code
+fn run_celq(args: &[&str], input: &str) -> io::Result<process::Output> {
+    let mut child = process::Command::new(env!("CARGO_BIN_EXE_celq"))
+        .args(args)
+        .stdin(process::Stdio::piped())
+        .stdout(process::Stdio::piped())
+        .stderr(process::Stdio::piped())
+        .spawn()?;
+
+    use io::Write;
+    {
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(input.as_bytes())?;
+    }
+
+    child.wait_with_output()
+}
+
+#[test]
+fn test_boolean_true_suppresses_output() -> io::Result<()> {
+    let output = run_celq(&["-n", "-b", "1 < 2"], "")?;
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(
+        output.stdout.is_empty(),
+        "Expected no output in boolean mode, got {:?}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_boolean_ndjson_all_truthy() -> io::Result<()> {
+    let output = run_celq(&["-b", "this.x > 0"], "{\"x\": 1}\n{\"x\": 2}\n")?;
+    assert_eq!(output.status.code(), Some(0));
+
+    Ok(())
+}
+
+#[test]
+fn test_boolean_ndjson_any_falsy() -> io::Result<()> {
+    // The falsy line is not the last one, the exit code must still reflect it
+    let output = run_celq(&["-b", "this.x > 0"], "{\"x\": 0}\n{\"x\": 2}\n")?;
+    assert_eq!(output.status.code(), Some(1));
+
+    Ok(())
+}
+
+#[test]
+fn test_boolean_error_exit_code() -> io::Result<()> {
+    let output = run_celq(&["-n", "-b", "1 / 0 == 1"], "")?;
+    assert_eq!(output.status.code(), Some(2));
+
+    Ok(())
+}
+
+#[test]
+fn test_exit_status_prints_output() -> io::Result<()> {
+    let output = run_celq(&["-e", "this.x"], "{\"x\": 1}\n{\"x\": null}\n")?;
+
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).replace('\r', ""),
+        "1\nnull\n"
+    );
+
+    let output = run_celq(&["-n", "--exit-status", "'apple'"], "")?;
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "\"apple\"");
+
+    Ok(())
+}
+
+#[test]
+fn test_exit_status_conflicts_with_boolean() -> io::Result<()> {
+    let output = run_celq(&["-n", "-e", "-b", "true"], "")?;
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("cannot be used with"));
+
+    Ok(())
+}
+
+#[test]
+fn test_continue_reports_line_numbers() -> io::Result<()> {
+    let output = run_celq(&["-C", "this.x"], "{\"x\": 1}\n\n{bad}\n{\"x\": 3}\n")?;
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).replace('\r', ""),
+        "1\n3\n"
+    );
+    // Blank lines still count towards the line numbers
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Line 3:"));
+
+    Ok(())
+}
+
+#[test]
+fn test_type_summary_counts() -> io::Result<()> {
+    let output = run_celq(
+        &["--type-summary", "this.x"],
+        "{\"x\": 1}\n{\"x\": \"a\"}\n{\"x\": 2.5}\n{\"x\": 3}\n",
+    )?;
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).replace('\r', ""),
+        "1\n\"a\"\n2.5\n3\n"
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr).replace('\r', ""),
+        "double: 1\nint: 2\nstring: 1\n"
+    );
+
+    // Every result is counted once, however many times its line is evaluated
+    for args in [
+        &["--type-summary", "--presort-by", "this.x", "this.x"][..],
+        &["--type-summary", "-j", "4", "this.x"][..],
+    ] {
+        let output = run_celq(args, "{\"x\": 3}\n{\"x\": 1}\n{\"x\": 2}\n")?;
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(
+            String::from_utf8_lossy(&output.stderr).replace('\r', ""),
+            "int: 3\n"
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_invalid_root_var_exit_code() -> io::Result<()> {
+    let output = process::Command::new(env!("CARGO_BIN_EXE_celq"))
+        .args(["-n", "--root-var=.", "1"])
+        .stdin(process::Stdio::null())
+        .output()?;
+
+    assert_eq!(
+        output.status.code(),
+        Some(2),
+        "Expected exit code 2 for an invalid root variable, got {:?}",
+        output.status.code()
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not a valid CEL identifier"));
+
+    Ok(())
+}
+
+#[test]
+fn test_max_complexity_rejects_nested_expression() -> io::Result<()> {
+    let nested = format!("{}1{}", "(1 + ".repeat(20), ")".repeat(20));
+    let output = run_celq(&["-n", "--max-complexity", "10", &nested], "")?;
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--max-complexity 10"));
+
+    let output = run_celq(&["-n", "--max-complexity", "10", "1 + 2"], "")?;
+    assert_eq!(output.status.code(), Some(0));
+
+    Ok(())
+}
+
+#[test]
+fn test_invalid_base64_exit_code() -> io::Result<()> {
+    let output = run_celq(&["-n", "base64Decode('not base64!')"], "")?;
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn arg_file_explicit_args_win() -> io::Result<()> {
+    let file = NamedTempFile::new()?;
+    fs::write(file.path(), r#"{"limit": 5, "unit": "ms"}"#)?;
+
+    let path = file.path().to_str().expect("non-utf8 temp path");
+
+    golden_test(
+        &[
+            "-n",
+            "--arg-file",
+            path,
+            "-a",
+            "limit:int=9",
+            "string(limit) + unit",
+        ],
+        "",
+        r#""9ms""#,
+    )
+}
+
+// Expression pipelines
+test!(
+    expr_pipeline,
+    &["--expr", "this.items", "--expr", "this.map(x, x.id)"],
+    "{\"items\":[{\"id\":1},{\"id\":2}]}\n{\"items\":[]}\n",
+    "[1,2]\n[]"
+);
+
+// Positional arguments
+test!(
+    args_positional_strings,
+    &[
+        "-n",
+        "-a",
+        "x:int=1",
+        "--args",
+        "celq_args.positional[1] + string(celq_args.named.x)",
+        "a",
+        "b"
+    ],
+    "",
+    r#""b1""#
+);
+
+test!(
+    jsonargs_positional_values,
+    &["--jsonargs", "celq_args.positional[0] + this.n", "2"],
+    r#"{"n": 3}"#,
+    "5"
+);
+
+#[test]
+fn test_arg_at_path_reads_file() -> io::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let config = dir.path().join("config.json");
+    fs::write(&config, "{\"port\": 8080}")?;
+    let config_arg = format!("config:json=@{}", config.display());
+
+    golden_test(&["-n", "--arg", &config_arg, "config.port"], "", "8080")?;
+    // A doubled @ escapes a literal leading @
+    golden_test(&["-n", "--arg", "user=@@celq", "user"], "", "\"@celq\"")
+}
+
+#[test]
+#[cfg(unix)]
+fn test_args_reject_non_utf8_positional() -> io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let output = process::Command::new(env!("CARGO_BIN_EXE_celq"))
+        .args(["-n", "--args", "celq_args.positional"])
+        .arg(std::ffi::OsStr::from_bytes(b"caf\xe9"))
+        .output()?;
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not valid UTF-8"));
+
+    Ok(())
+}
+
+#[test]
+fn test_arg_env_reads_environment() -> io::Result<()> {
+    let output = process::Command::new(env!("CARGO_BIN_EXE_celq"))
+        .args(["-n", "--arg-env", "token:string=CELQ_TEST_TOKEN", "token"])
+        .env("CELQ_TEST_TOKEN", "s3cret")
+        .output()?;
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "\"s3cret\"");
+
+    Ok(())
+}
+
+#[test]
+fn test_env_input_binds_environment() -> io::Result<()> {
+    let output = process::Command::new(env!("CARGO_BIN_EXE_celq"))
+        .args(["--env-input", "this.CELQ_TEST_REGION"])
+        .env("CELQ_TEST_REGION", "eu-west-1")
+        .stdin(process::Stdio::null())
+        .output()?;
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        "\"eu-west-1\""
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_raw_input_lines_and_slurp() -> io::Result<()> {
+    let input = "{\"not\": \"parsed\"}\n\nplain text\n";
+
+    let output = run_celq(&["--raw-input", "size(this)"], input)?;
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "17\n0\n10\n");
+
+    let output = run_celq(&["--raw-input", "-s", "size(this)"], input)?;
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "30\n");
+
+    Ok(())
+}
+
+#[test]
+fn test_timestamp_and_duration_output() -> io::Result<()> {
+    let expr = "[timestamp('2024-01-02T03:04:05Z'), timestamp('2024-01-02T04:34:05Z') - timestamp('2024-01-02T03:04:05Z')]";
+
+    let output = run_celq(&["-n", expr], "")?;
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "[\"2024-01-02T03:04:05+00:00\",\"5400s\"]\n"
+    );
+
+    let output = run_celq(&["-n", "--iso-durations", expr], "")?;
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "[\"2024-01-02T03:04:05+00:00\",\"PT5400S\"]\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_output_file_keeps_errors_on_stderr() -> io::Result<()> {
+    let file = NamedTempFile::new()?;
+    fs::write(file.path(), "stale contents that must be truncated\n")?;
+    let path = file.path().to_str().expect("non-utf8 temp path");
+
+    let output = run_celq(
+        &["-C", "-o", path, "this.x"],
+        "{\"x\": 1}\nnot json\n{\"x\": 2}\n",
+    )?;
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+    assert!(!output.stderr.is_empty());
+    assert_eq!(fs::read_to_string(file.path())?, "1\n2\n");
+
+    Ok(())
+}
+
+#[test]
+fn test_raw_input_slurp_split() -> io::Result<()> {
+    golden_test(
+        &["--raw-input", "-s", "this.split(\",\")"],
+        "a,b\n,c",
+        r#"["a","b\n","c"]"#,
+    )?;
+    golden_test(&["--raw-input", "-s", "this.split(\",\")"], "", r#"[""]"#)
+}
+
+#[test]
+fn test_number_mode_mixes_ints_and_floats() -> io::Result<()> {
+    let input = r#"{"count": 2, "price": 1.5}"#;
+
+    // Without a number mode, int * double has no overload
+    let output = run_celq(&["this.count * this.price"], input)?;
+    assert_eq!(output.status.code(), Some(2));
+
+    golden_test(
+        &["--number-mode", "float", "this.count * this.price"],
+        input,
+        "3.0",
+    )?;
+    golden_test(
+        &["--number-mode", "int", "this.count + 1"],
+        r#"{"count": 2.0}"#,
+        "3",
+    )
+}
+
+#[test]
+fn test_parse_error_reports_line_number() -> io::Result<()> {
+    let output = run_celq(
+        &["this.x"],
+        "{\"x\": 1}\n{\"x\": 2}\n{\"x\": }\n{\"x\": 4}\n",
+    )?;
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Failed to process line 3"));
+
+    Ok(())
+}
+
+#[test]
+fn test_check_compiles_without_input() -> io::Result<()> {
+    // Input is never read, so invalid JSON is not an error
+    let output = run_celq(&["--check", "this.x > 0"], "not json")?;
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+    assert!(output.stderr.is_empty());
+
+    let output = run_celq(&["--compile-only", "this.x >"], "")?;
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("line 1, column 9"));
+
+    Ok(())
+}
+
+#[test]
+fn test_compile_error_points_at_column() -> io::Result<()> {
+    let output = run_celq(&["-n", "this.x > 0 && ("], "")?;
+    assert_eq!(output.status.code(), Some(2));
+
+    let stderr = String::from_utf8_lossy(&output.stderr).replace('\r', "");
+    assert!(stderr.contains("  Error: line 1, column 16: Syntax error"));
+    assert!(stderr.contains("\n  | this.x > 0 && (\n  |                ^\n"));
+
+    Ok(())
+}
+
+#[test]
+fn test_presort_by_spans_input_files() -> io::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let first = dir.path().join("a.json");
+    let second = dir.path().join("b.json");
+    fs::write(&first, "{\"k\": 2}\n{\"k\": 1}\n")?;
+    fs::write(&second, "{\"k\": 0}\n")?;
+    let first = first.to_str().unwrap();
+    let second = second.to_str().unwrap();
+
+    golden_test(
+        &["--presort-by", "this.k", "this.k", first, second],
+        "",
+        "0\n1\n2",
+    )?;
+
+    // Each record keeps the filename of the file it came from
+    let output = run_celq(
+        &[
+            "-r",
+            "--with-filename",
+            "--presort-by",
+            "this.k",
+            "filename",
+            first,
+            second,
+        ],
+        "",
+    )?;
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).replace('\r', ""),
+        format!("{second}\n{first}\n{first}\n")
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_presort_by_rejects_other_input_modes() -> io::Result<()> {
+    for flag in [
+        "--slurp",
+        "-n",
+        "--blank-separated",
+        "--from-json5",
+        "--env-input",
+        "--csv-input",
+        "--raw-input",
+        "--seq",
+    ] {
+        let output = run_celq(&["--presort-by", "this.k", flag, "this"], "")?;
+        assert_eq!(output.status.code(), Some(2), "{flag}");
+        assert!(String::from_utf8_lossy(&output.stderr).contains("cannot be used with"));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_presort_by_compile_error_fails_without_input() -> io::Result<()> {
+    let output = run_celq(&["--presort-by", "this.k >", "this"], "")?;
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("failed to compile --presort-by expression")
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_precheck_compile_error_fails_before_reading_input() -> io::Result<()> {
+    let output = run_celq(&["--precheck", "this.x >", "this"], "")?;
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("failed to compile --precheck expression")
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_verbose_echoes_inputs_to_stderr() -> io::Result<()> {
+    let output = run_celq(
+        &["-v", "has(this.x) ? this.x : null"],
+        "{\"x\": 1}\n{\"y\": 2}\n",
+    )?;
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).replace('\r', ""),
+        "1\nnull\n"
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr).replace('\r', ""),
+        "{\"x\":1} => 1\n{\"y\":2} => null\n"
+    );
+
+    // Single documents and null inputs are echoed too
+    let output = run_celq(&["-v", "this.x"], "{\n  \"x\": 1\n}\n")?;
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr).replace('\r', ""),
+        "{\"x\":1} => 1\n"
+    );
+    let output = run_celq(&["-v", "-n", "1 + 1"], "")?;
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr).replace('\r', ""),
+        "null => 2\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_bignum_error() -> io::Result<()> {
+    let input = "{\"id\": 18446744073709551616}\n";
+
+    // Without the flag the integer silently becomes a float
+    golden_test(&["this.id"], input, "1.8446744073709552e19")?;
+
+    let output = run_celq(&["--bignum-error", "this.id"], input)?;
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("18446744073709551616"));
+
+    golden_test(
+        &["--bignum-error", "this.id"],
+        "{\"id\": 18446744073709551615}\n",
+        "18446744073709551615",
+    )
+}
+
+#[test]
+fn test_dedup_keeps_first_seen_order() -> io::Result<()> {
+    let input = "{\"x\": 2}\n{\"x\": 1}\n{\"x\": 2}\n{\"x\": 1.0}\n{\"x\": 1}\n";
+    golden_test(&["-u", "this.x"], input, "2\n1\n1.0")?;
+    golden_test(&["-j", "4", "--dedup", "this.x"], input, "2\n1\n1.0")
+}
+
+#[test]
+fn test_count_truthy_results() -> io::Result<()> {
+    let input = "{\"x\": 1}\n{\"x\": 0}\n{\"x\": 5}\n";
+    golden_test(&["--count", "this.x > 0"], input, "2")?;
+    golden_test(&["--count", "this.x > 10"], input, "0")
+}
+
+#[test]
+fn test_limit_outputs_first_truthy_results() -> io::Result<()> {
+    let input = "1\n2\n3\n4\n5\n6\n7\n";
+    let expr = "this % 2 == 0 ? this : false";
+    golden_test(&["--limit", "2", expr], input, "2\n4")?;
+    golden_test(&["-j", "4", "--limit", "2", expr], input, "2\n4")
+}
+
+#[test]
+fn test_seeded_uuids_repeat_across_runs() -> io::Result<()> {
+    let args = ["--seed", "42", "[uuid(), uuid()]"];
+    let first = run_celq(&args, "{}\n{}\n")?;
+    let second = run_celq(&args, "{}\n{}\n")?;
+
+    assert_eq!(first.status.code(), Some(0));
+    assert_eq!(first.stdout, second.stdout);
+    // The sequence continues across documents
+    let lines: Vec<String> = String::from_utf8_lossy(&first.stdout)
+        .lines()
+        .map(str::to_string)
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert_ne!(lines[0], lines[1]);
+
+    Ok(())
+}
+
+#[test]
+fn test_no_trailing_newline() -> io::Result<()> {
+    let output = run_celq(
+        &["--no-trailing-newline", "this.x"],
+        "{\"x\": 1}\n{\"x\": 2}\n",
+    )?;
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n2");
+
+    let output = run_celq(&["--no-trailing-newline", "-n", "1"], "")?;
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1");
+
+    Ok(())
+}
+
+#[test]
+fn test_unbuffered_requires_single_job() -> io::Result<()> {
+    golden_test(&["-U", "this.x"], "{\"x\": 1}\n{\"x\": 2}\n", "1\n2")?;
+
+    let output = run_celq(&["-U", "-j", "4", "this.x"], "{\"x\": 1}\n")?;
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("-j 1"));
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_follow_stops_on_ctrl_c() -> io::Result<()> {
+    use io::{BufRead, Write};
+
+    let mut file = NamedTempFile::new()?;
+    writeln!(file, "{{\"x\": 1}}")?;
+
+    let mut child = process::Command::new(env!("CARGO_BIN_EXE_celq"))
+        .args(["-F", "this.x * 10"])
+        .arg(file.path())
+        .stdout(process::Stdio::piped())
+        .stderr(process::Stdio::piped())
+        .spawn()?;
+    let mut stdout = io::BufReader::new(child.stdout.take().unwrap());
+
+    let mut line = String::new();
+    stdout.read_line(&mut line)?;
+    assert_eq!(line, "10\n");
+
+    // Lines appended after celq reached the end of the file are still read
+    writeln!(file, "{{\"x\": 2}}")?;
+    line.clear();
+    stdout.read_line(&mut line)?;
+    assert_eq!(line, "20\n");
+
+    process::Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()?;
+    let status = child.wait()?;
+    assert_eq!(status.code(), Some(0));
+
+    Ok(())
+}
+
+#[test]
+fn test_follow_rejects_boolean_and_count() -> io::Result<()> {
+    for flag in ["-b", "--count"] {
+        let output = run_celq(&["-F", flag, "this", "a.json"], "")?;
+        assert_eq!(output.status.code(), Some(2));
+        assert!(String::from_utf8_lossy(&output.stderr).contains("cannot be used with"));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_follow_requires_single_file() -> io::Result<()> {
+    let output = run_celq(&["-F", "this", "a.json", "b.json"], "")?;
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("single input file"));
+
+    Ok(())
+}
+
+#[test]
+fn test_validate_exit_code() -> io::Result<()> {
+    let output = run_celq(
+        &["--validate", "has(this.id)"],
+        "{\"id\": 1}\n{\"name\": \"x\"}\n{\"id\": 3}\n",
+    )?;
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "{\"line\":2,\"input\":\"{\\\"name\\\": \\\"x\\\"}\"}\n"
+    );
+
+    let output = run_celq(
+        &["--validate", "has(this.id)"],
+        "{\"id\": 1}\n{\"id\": 2}\n",
+    )?;
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+
+    // Lines read in parallel are checked too, including the last one
+    let output = run_celq(
+        &["-j", "4", "--validate", "has(this.id)"],
+        "{\"id\": 1}\n{\"id\": 2}\n{\"name\": \"x\"}\n",
+    )?;
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "{\"line\":3,\"input\":\"{\\\"name\\\": \\\"x\\\"}\"}\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_ascii_output_escapes_reports() -> io::Result<()> {
+    let output = run_celq(&["-A", "--validate", "false"], "{\"k\":\"é\"}\n")?;
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "{\"line\":1,\"input\":\"{\\\"k\\\":\\\"\\u00e9\\\"}\"}\n"
+    );
+
+    let output = run_celq(&["-A", "--error-output", "this.k + 1"], "{\"k\":\"é\"}\n")?;
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.is_ascii(), "unexpected output: {}", stdout);
+    assert!(stdout.contains("\\u00e9"), "unexpected output: {}", stdout);
+
+    Ok(())
+}
+
+#[test]
+fn test_float_precision() -> io::Result<()> {
+    golden_test(
+        &["--float-precision", "3", "[this.x + this.y, this.x / 3.0]"],
+        "{\"x\": 0.1, \"y\": 0.2}\n",
+        "[0.3,0.033]",
+    )?;
+
+    // Precisions that formatting cannot handle are rejected up front
+    for precision in ["18", "70000"] {
+        let output = run_celq(&["--float-precision", precision, "1.5"], "")?;
+        assert_eq!(output.status.code(), Some(2));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_expose_fields() -> io::Result<()> {
+    golden_test(
+        &["--expose-fields", "--arg", "y:int=100", "x + y"],
+        "{\"x\": 1, \"y\": 2}\n{\"x\": 3, \"y\": 4}\n",
+        "101\n103",
+    )
+}
+
+#[test]
+fn test_type_of() -> io::Result<()> {
+    golden_test(
+        &["-r", "--type-of", "this.x"],
+        "{\"x\": \"a\"}\n{\"x\": {\"y\": 1}}\n",
+        "string\nmap",
+    )
+}
+
+#[test]
+#[cfg(unix)]
+fn test_input_from_named_pipe() -> io::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let fifo = dir.path().join("input.fifo");
+    let status = process::Command::new("mkfifo").arg(&fifo).status()?;
+    assert!(status.success());
+
+    // Opening a FIFO for writing blocks until celq opens it for reading
+    let writer_path = fifo.clone();
+    let writer = std::thread::spawn(move || fs::write(writer_path, "{\"x\": 1}\n{\"x\": 2}\n"));
+
+    let output = run_celq(
+        &["-s", "this.map(r, r.x)", "-i", fifo.to_str().unwrap()],
+        "",
+    )?;
+    writer.join().unwrap()?;
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "[1,2]\n");
+
+    Ok(())
+}
+
+#[test]
+fn test_max_line_bytes() -> io::Result<()> {
+    let input = "{\"x\": 1}\n{\"x\": \"too long\"}\n";
+    let output = run_celq(&["--max-line-bytes", "12", "this.x"], input)?;
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Line 2 is longer"));
+
+    golden_test(&["--max-line-bytes", "12", "-C", "this.x"], input, "1")
+}
+
+#[test]
+fn test_index_by_line_rejects_multiple_files() -> io::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let first = dir.path().join("first.json");
+    let second = dir.path().join("second.json");
+    fs::write(&first, "{\"x\": 1}\n")?;
+    fs::write(&second, "{\"x\": 2}\n")?;
+
+    // Both files have a line 1, so their records would overwrite each other
+    let output = run_celq(
+        &[
+            "--index-by-line",
+            "this.x",
+            first.to_str().unwrap(),
+            second.to_str().unwrap(),
+        ],
+        "",
+    )?;
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("requires a single input file"));
+
+    Ok(())
+}
+
+#[test]
+fn test_in_place_rewrites_each_file() -> io::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let first = dir.path().join("first.json");
+    let second = dir.path().join("second.json");
+    fs::write(&first, "{\"b\": 1, \"a\": 2}\n{\"b\": 3, \"a\": 4}\n")?;
+    fs::write(&second, "{\"b\": 5, \"a\": 6}\n")?;
+
+    let output = run_celq(
+        &[
+            "--in-place",
+            "-S",
+            "this",
+            first.to_str().unwrap(),
+            second.to_str().unwrap(),
+        ],
+        "",
+    )?;
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        fs::read_to_string(&first)?,
+        "{\"a\":2,\"b\":1}\n{\"a\":4,\"b\":3}\n"
+    );
+    assert_eq!(fs::read_to_string(&second)?, "{\"a\":6,\"b\":5}\n");
+
+    Ok(())
+}
+
+#[test]
+fn test_in_place_keeps_file_on_error() -> io::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("records.json");
+    let original = "{\"x\": 1}\n{\"x\": \"a\"}\n";
+    fs::write(&path, original)?;
+
+    let output = run_celq(&["--in-place", "this.x + 1", path.to_str().unwrap()], "")?;
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(fs::read_to_string(&path)?, original);
+    // The temporary file is cleaned up
+    assert_eq!(fs::read_dir(dir.path())?.count(), 1);
+
+    // Skipping or reporting failed records would drop them from the rewritten file
+    for flag in ["--continue", "--error-output"] {
+        let args = ["--in-place", flag, "this.x + 1", path.to_str().unwrap()];
+        let output = run_celq(&args, "")?;
+        assert_eq!(output.status.code(), Some(2));
+        assert_eq!(fs::read_to_string(&path)?, original);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_arg_type_default() -> io::Result<()> {
+    golden_test(&["-n", "--arg", "name=Ada", "name + '!'"], "", "\"Ada!\"")?;
+    golden_test(
+        &[
+            "-n",
+            "--arg-type-default",
+            "int",
+            "--arg",
+            "x=3",
+            "--arg",
+            "y:uint=4",
+            "x * 2 + int(y)",
+        ],
+        "",
+        "10",
+    )
+}
+
+#[test]
+fn test_output_raw_bytes() -> io::Result<()> {
+    let output = run_celq(
+        &["--output-raw-bytes", "base64Decode(this.data)"],
+        "{\"data\": \"AAH/\"}\n{\"data\": \"Zm9v\"}\n",
+    )?;
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"\x00\x01\xfffoo");
+
+    let output = run_celq(&["--output-raw-bytes", "this"], "{\"data\": 1}\n")?;
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("expects a bytes result"));
+
+    // Raw bytes have no terminator to change
+    for flag in ["-0", "--no-trailing-newline"] {
+        let output = run_celq(&["--output-raw-bytes", flag, "b'a'"], "")?;
+        assert_eq!(output.status.code(), Some(2));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_group_by() -> io::Result<()> {
+    golden_test(
+        &[
+            "-S",
+            "--group-by",
+            "this.status",
+            "{'status': this[0].status, 'count': size(this)}",
+        ],
+        "{\"status\": 500}\n{\"status\": 200}\n{\"status\": 500}\n{\"status\": 404}\n",
+        "{\"count\":1,\"status\":200}\n{\"count\":1,\"status\":404}\n{\"count\":2,\"status\":500}",
+    )
+}
+
+#[test]
+fn test_null_for_missing_covers_key_expressions() -> io::Result<()> {
+    golden_test(
+        &[
+            "--expose-fields",
+            "--null-for-missing",
+            "--group-by",
+            "status",
+            "size(this)",
+        ],
+        "{\"status\": 500}\n{\"x\": 1}\n{\"status\": 500}\n",
+        "1\n2",
+    )?;
+    golden_test(
+        &[
+            "--expose-fields",
+            "--null-for-missing",
+            "--precheck",
+            "version == null",
+            "x",
+        ],
+        "{\"x\": 1}\n",
+        "1",
+    )?;
+    golden_test(
+        &[
+            "--expose-fields",
+            "--null-for-missing",
+            "--presort-by",
+            "rank",
+            "x",
+        ],
+        "{\"x\": 1, \"rank\": 2}\n{\"x\": 2}\n{\"x\": 3, \"rank\": 1}\n",
+        "2\n3\n1",
+    )
+}
+
+#[test]
+fn test_group_by_spans_input_files() -> io::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let first = dir.path().join("first.json");
+    let second = dir.path().join("second.json");
+    fs::write(&first, "{\"status\": 500}\n{\"status\": 200}\n")?;
+    fs::write(&second, "{\"status\": 500}\n")?;
+
+    let output = run_celq(
+        &[
+            "--group-by",
+            "this.status",
+            "size(this)",
+            first.to_str().unwrap(),
+            second.to_str().unwrap(),
+        ],
+        "",
+    )?;
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n2\n");
+
+    // Without input there is nothing to group
+    let output = run_celq(&["-n", "--group-by", "this", "this"], "")?;
+    assert_eq!(output.status.code(), Some(2));
+
+    Ok(())
+}